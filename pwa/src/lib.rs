@@ -1,33 +1,55 @@
-#![recursion_limit = "256"]
+#![recursion_limit = "512"]
 extern crate console_error_panic_hook;
 
+use std::collections::HashMap;
 use std::panic;
+use std::time::Duration;
 
 use wasm_bindgen::JsCast;
 use wasm_bindgen::prelude::*;
 use yew::prelude::*;
-use yew::services::{ConsoleService, DialogService};
+use yew::services::{ConsoleService, DialogService, IntervalService};
+use yew::services::interval::IntervalTask;
 
 #[wasm_bindgen]
 extern "C" {
     fn open(payload: JsValue);
     fn pw_prompt(payload: JsValue);
+    fn save(payload: JsValue);
     fn set_window_focus();
 }
 
 pub enum Msg {
+    Audit,
+    Compare,
+    CompareDB(JsValue),
+    ComparePassword(JsValue),
     Exit,
+    NewDB,
+    NewPassword(JsValue),
     OpenDB,
     Password(JsValue),
+    SaveDB,
     Search(String),
+    Tick,
     UnencryptedDB(JsValue),
 }
 
 struct PasswordDB {
+    // Shows the password audit report in place of the records
+    audit: bool,
     db: Option<pwdb::Database>,
+    // The changes from the open DB to another file, shown in place of the records
+    diff: Option<pwdb::diff::Diff>,
     link: ComponentLink<Self>,
     raw_db: Option<Vec<u8>>,
+    // The file to compare against while its password is prompted for
+    raw_compare: Option<Vec<u8>>,
     search: String,
+    // Set while a render is only to update the two factor codes, which mustn't move the focus
+    ticked: bool,
+    // Re-renders every second so two factor codes stay current
+    _ticker: IntervalTask,
 }
 
 impl Component for PasswordDB {
@@ -35,16 +57,98 @@ impl Component for PasswordDB {
     type Properties = ();
     fn create(_: Self::Properties, link: ComponentLink<Self>) -> Self {
         Self {
+            audit: false,
             db: None,
-            link,
+            diff: None,
+            link: link.clone(),
             raw_db: None,
+            raw_compare: None,
             search: String::new(),
+            ticked: false,
+            _ticker: IntervalService::spawn(Duration::from_secs(1), link.callback(|_| Msg::Tick)),
         }
     }
 
     fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        self.ticked = matches!(msg, Msg::Tick);
         match msg {
-            Msg::Exit => self.db = None,
+            Msg::Audit => {
+                self.audit = !self.audit;
+                self.diff = None;
+            },
+            Msg::Compare => {
+                if self.diff.take().is_some() {
+                    return true
+                }
+                let callback = self.link.callback(Msg::CompareDB);
+                open(Closure::once_into_js(move |payload: JsValue| {
+                    callback.emit(payload)
+                }));
+                return false
+            },
+            Msg::CompareDB(contents) => {
+                let raw: serde_bytes::ByteBuf = match serde_wasm_bindgen::from_value(contents) {
+                    Ok(value) => value,
+                    Err(msg) => {
+                        DialogService::alert(&format!("Failed decoding Password DB file {}", msg));
+                        return false;
+                    }
+                };
+                self.raw_compare = Some(raw.into_vec());
+
+                let callback = self.link.callback(Msg::ComparePassword);
+                pw_prompt(Closure::once_into_js(move |payload: JsValue| {
+                    callback.emit(payload)
+                }));
+                return false
+            },
+            Msg::ComparePassword(password) => {
+                let raw = self.raw_compare.as_ref().expect("no DB to compare was specified");
+                let pw = password.as_string().expect("password is not a string");
+                let other = match pwdb::Database::new(raw, &pw) {
+                    Ok(other) => other,
+                    Err(pwdb::Error::WrongPassword) => {
+                        DialogService::alert("Invalid password, try again");
+                        let callback = self.link.callback(Msg::ComparePassword);
+                        pw_prompt(Closure::once_into_js(move |payload: JsValue| {
+                            callback.emit(payload)
+                        }));
+                        return false
+                    },
+                    Err(msg) => {
+                        DialogService::alert(&format!("failed opening DB to compare: {}", msg));
+                        return false
+                    },
+                };
+                self.raw_compare = None;
+                if let Some(db) = &self.db {
+                    self.audit = false;
+                    self.diff = Some(pwdb::diff(db, &other));
+                }
+            },
+            Msg::Exit => {
+                self.audit = false;
+                self.db = None;
+                self.diff = None;
+            },
+            Msg::NewDB => {
+                let callback = self.link.callback(Msg::NewPassword);
+                pw_prompt(Closure::once_into_js(move |payload: JsValue| {
+                    callback.emit(payload)
+                }));
+                return false
+            },
+            Msg::NewPassword(password) => {
+                let pw = password.as_string().expect("password is not a string");
+                let name = DialogService::prompt("Name for the new Password DB:", Some("")).unwrap_or_default();
+                self.db = match pwdb::Database::create(&pw, &name, "") {
+                    Ok(db) => Some(db),
+                    Err(msg) => {
+                        DialogService::alert(&format!("failed creating DB: {}", msg));
+                        return false
+                    },
+                }
+            },
             Msg::OpenDB => {
                 // The Javascript functions to open a file are asynchronous. Neither Javascript nor
                 // WASM have multiple threads so I can't block waiting for that asynchronous function
@@ -63,16 +167,41 @@ impl Component for PasswordDB {
                         ConsoleService::info(&format!("Opened DB named {}", db.header.name));
                         Some(db)
                     },
+                    Err(pwdb::Error::WrongPassword) => {
+                        DialogService::alert("Invalid password, try again");
+                        let callback = self.link.callback(Msg::Password);
+                        pw_prompt(Closure::once_into_js(move |payload: JsValue| {
+                            callback.emit(payload)
+                        }));
+                        return false
+                    },
                     Err(msg) => {
                         DialogService::alert(&format!("failed opening DB: {}", msg));
                         return false
                     },
                 }
             },
+            Msg::SaveDB => {
+                if let Some(db) = self.db.as_mut() {
+                    let raw = serde_bytes::ByteBuf::from(db.save());
+                    match serde_wasm_bindgen::to_value(&raw) {
+                        Ok(contents) => save(contents),
+                        Err(msg) => DialogService::alert(&format!("Failed encoding Password DB file {}", msg)),
+                    }
+                }
+                return false
+            },
             Msg::Search(value) => {
                 self.search = value;
                 return true
             }
+            Msg::Tick => {
+                return match &self.db {
+                    Some(_) if self.audit || self.diff.is_some() => false,
+                    Some(db) => db.records().values().any(|record| record.totp.is_some()),
+                    None => false,
+                }
+            },
             Msg::UnencryptedDB(contents) => {
                 let raw: serde_bytes::ByteBuf = match serde_wasm_bindgen::from_value(contents) {
                     Ok(value) => value,
@@ -102,24 +231,134 @@ impl Component for PasswordDB {
 
     fn view(&self) -> Html {
         // TODO make a component for the full record data which can be opened from each line
-        let render_record = |(_uuid, record): (&uuid::Uuid, &pwdb::record::Record)| {
+        let render_record = |records: &HashMap<uuid::Uuid, pwdb::record::Record>, record: &pwdb::record::Record| {
+            // Aliases and shortcuts show the password of their base record along with its title
+            let (password, base_title) = match record.base(records) {
+                Ok(base) if base.uuid() != record.uuid() => (base.password.as_str(), format!(" (base: {})", base.title)),
+                Ok(_) => (record.password.as_str(), String::new()),
+                Err(msg) => (record.password.as_str(), format!(" ({})", msg)),
+            };
+            let code = match record.one_time_code() {
+                Some(code) => format!("{} ({}s)", code.code, code.seconds_remaining),
+                None => String::new(),
+            };
             html! {
                 <tr>
                     <td onClick="navigator.clipboard.writeText(this.innerText)">{&record.group}</td>
-                    <td onClick="navigator.clipboard.writeText(this.innerText)">{&record.title}</td>
+                    <td onClick="navigator.clipboard.writeText(this.firstChild.textContent)"><span>{&record.title}</span><i>{base_title}</i></td>
                     <td onClick="navigator.clipboard.writeText(this.innerText)">{&record.username}</td>
-                    <td onClick="navigator.clipboard.writeText(this.firstChild.value)"><input type="password" readonly=true value={&record.password} /><img src="icons/eye.svg" height="20" width="20" style="vertical-align:middle" onClick="toggleVisibility(this.previousSibling)"/></td>
+                    <td onClick="navigator.clipboard.writeText(this.firstChild.value)"><input type="password" readonly=true value={password} /><img src="icons/eye.svg" height="20" width="20" style="vertical-align:middle" onClick="toggleVisibility(this.previousSibling)"/></td>
+                    <td onClick="navigator.clipboard.writeText(this.innerText.split(' ')[0])">{code}</td>
                     <td><a href={&record.url[..]} target="_blank">{&record.url}</a></td>
                     <td onClick="navigator.clipboard.writeText(this.innerText)">{&record.notes}</td>
                 </tr>
             }
         };
 
+        let render_audit = |record: &pwdb::audit::RecordAudit| {
+            html! {
+                <tr>
+                    <td>{&record.group}</td>
+                    <td>{&record.title}</td>
+                    <td>{ for record.issues.iter().map(|issue| html! { <div>{issue.to_string()}</div> }) }</td>
+                </tr>
+            }
+        };
+        // Passwords which have expired or will soon are listed above the records as a reminder
+        let render_expiring = |db: &pwdb::Database| {
+            let expiring = db.expiring_within(chrono::Duration::days(pwdb::record::DUE_SOON_DAYS));
+            if expiring.is_empty() {
+                return html! {}
+            }
+            let render_expiry = |record: &&pwdb::record::Record| {
+                let status = match record.expiry_status() {
+                    pwdb::record::ExpiryStatus::Expired => "expired",
+                    _ => "expires",
+                };
+                let expiry = record.password_expiry_time.map(|time| time.format("%Y-%m-%d").to_string()).unwrap_or_default();
+                html! { <li>{format!("{} [{}] {} {}", record.title, record.group, status, expiry)}</li> }
+            };
+            html! {
+                <div>
+                    <b>{"Needs rotation:"}</b>
+                    <ul>{ for expiring.iter().map(render_expiry) }</ul>
+                </div>
+            }
+        };
+        // The comparison lists added, removed and modified records with the fields which differ,
+        // passwords and other secrets are masked
+        let render_diff = |diff: &pwdb::diff::Diff| {
+            if diff.is_empty() {
+                return html! { <p>{"No differences found"}</p> }
+            }
+            let render_changes = |changes: &[pwdb::diff::FieldChange]| {
+                html! { for changes.iter().map(|change| html! { <div>{change.to_string()}</div> }) }
+            };
+            let render_diff_record = |change: &str, record: &pwdb::diff::RecordDiff| {
+                html! {
+                    <tr>
+                        <td>{change}</td>
+                        <td>{&record.group}</td>
+                        <td>{&record.title}</td>
+                        <td>{render_changes(&record.changes)}</td>
+                    </tr>
+                }
+            };
+            html! {
+                <table>
+                    <tr>
+                        <th>{"Change"}</th>
+                        <th>{"Group"}</th>
+                        <th>{"Title"}</th>
+                        <th>{"Fields"}</th>
+                    </tr>
+                    { if diff.header.is_empty() { html! {} } else { html! {
+                        <tr><td>{"header"}</td><td></td><td></td><td>{render_changes(&diff.header)}</td></tr>
+                    } } }
+                { for diff.added.iter().map(|record| render_diff_record("added", record)) }
+                { for diff.removed.iter().map(|record| render_diff_record("removed", record)) }
+                { for diff.modified.iter().map(|record| render_diff_record("modified", record)) }
+                </table>
+            }
+        };
+        let render_records = |db: &pwdb::Database| {
+            if let Some(diff) = &self.diff {
+                return render_diff(diff)
+            }
+            if self.audit {
+                let report = db.audit(&pwdb::audit::AuditOptions::default());
+                return html! {
+                    <table>
+                        <tr>
+                            <th>{"Group"}</th>
+                            <th>{"Title"}</th>
+                            <th>{"Issues"}</th>
+                        </tr>
+                    { for report.records.iter().map(render_audit) }
+                    </table>
+                }
+            }
+            html! {
+                <table>
+                    <tr>
+                        <th>{"Group"}</th>
+                        <th>{"Title"}</th>
+                        <th>{"Username"}</th>
+                        <th>{"Password"}</th>
+                        <th>{"2FA Code"}</th>
+                        <th>{"URL"}</th>
+                        <th>{"Notes"}</th>
+                    </tr>
+                { for db.record_search(&self.search).values().map(|record| render_record(db.records(), record)) }
+                </table>
+            }
+        };
 
         match &self.db {
             None => html! {
                 <>
                     <button type="button" id="OpenFile" onclick=self.link.callback(|_| Msg::OpenDB)>{"Open Password DB File"}</button>
+                    <button type="button" id="NewFile" onclick=self.link.callback(|_| Msg::NewDB)>{"New Password DB"}</button>
                 </>
             },
             Some(db) => html! {
@@ -127,19 +366,15 @@ impl Component for PasswordDB {
                     <h1>{format!("Password DB - {}", db.header.name)}</h1>
                     <p> <b>{"Search:"}</b> <input type="text" id="Search" oninput=self.link.callback(|e: InputData| Msg::Search(e.value)) /> </p>
                     <p>{"Tap value to copy to clipboard."}</p>
-                    <p> <button type="button" id="Exit" onclick=self.link.callback(|_| Msg::Exit)>{"Close DB"}</button> </p>
+                    <p>
+                        <button type="button" id="Save" onclick=self.link.callback(|_| Msg::SaveDB)>{"Save DB"}</button>
+                        <button type="button" id="Audit" onclick=self.link.callback(|_| Msg::Audit)>{if self.audit { "Show Records" } else { "Audit Passwords" }}</button>
+                        <button type="button" id="Compare" onclick=self.link.callback(|_| Msg::Compare)>{if self.diff.is_some() { "Show Records" } else { "Compare With File" }}</button>
+                        <button type="button" id="Exit" onclick=self.link.callback(|_| Msg::Exit)>{"Close DB"}</button>
+                    </p>
+                    { render_expiring(db) }
                     <div style="overflow-x:auto;">
-                    <table>
-                        <tr>
-                            <th>{"Group"}</th>
-                            <th>{"Title"}</th>
-                            <th>{"Username"}</th>
-                            <th>{"Password"}</th>
-                            <th>{"URL"}</th>
-                            <th>{"Notes"}</th>
-                        </tr>
-                    { for db.record_search(&self.search).iter().map(render_record) }
-                    </table>
+                    { render_records(db) }
                     </div>
                 </>
             }
@@ -147,6 +382,10 @@ impl Component for PasswordDB {
     }
 
     fn rendered(&mut self, first_render: bool) {
+        // Refocusing every second would take the focus from wherever the user has put it
+        if self.ticked {
+            return
+        }
         set_search_focus();
 
         if !first_render {
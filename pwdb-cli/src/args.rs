@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use pwdb::csv::Duplicates;
+
+pub const USAGE: &str = "Usage: pwdb-cli [--password-fd FD] DB_FILE COMMAND [ARGS]
+
+Commands:
+    list [SEARCH]                   List records, only those matching SEARCH if given
+    show RECORD [--show-password]   Show the fields of a record
+    get RECORD FIELD                Print a single field, one of group, title, username, password,
+                                    url, email, notes, uuid, totp or otpauth
+    add TITLE [FIELD OPTIONS]       Add a record, the password is prompted for unless generated
+    edit RECORD [FIELD OPTIONS]     Change the fields of a record
+    delete RECORD                   Delete a record
+    generate [RECORD] [--length N]  Generate a password with the record's policy or the default
+    passwd [--iterations N]         Change the master password
+    import FILE [--duplicates D]    Import records from CSV with a header row naming the columns,
+                                    D is skip (the default), replace or add for records with the
+                                    same group, title and username as an existing record. Files
+                                    ending in .xml are imported as a pwsafe XML export.
+    import FILE.kdbx [--key-file K] Import a KeePass database, its password is prompted for
+    import FILE.json                Import an unencrypted Bitwarden JSON export
+    import FILE.1pux                Import a 1Password export
+    export [SEARCH] [--passwords]   Write records as CSV, the passwords only with --passwords
+    export --xml                    Write the whole DB, including passwords, as pwsafe XML
+    audit [--max-age DAYS]          Report reused, weak, old and expired passwords and records
+          [--min-score N]           without a URL, by default passwords over 365 days old or with a
+                                    strength score under 3, from 0 to 4, are reported
+    expiring [DAYS]                 List records whose password has expired or will within DAYS,
+                                    14 by default
+    breached HASHES                 List records whose password is in a local copy of the Have I
+                                    Been Pwned SHA-1 hashes, either a directory of range files or a
+                                    sorted HASH:COUNT file
+    diff OTHER_DB                   List the changes from the DB to OTHER_DB, its password is
+                                    prompted for. Passwords and other secrets are masked.
+
+RECORD is a record title or UUID. The FIELD OPTIONS are --group, --username, --url, --notes and
+--email each followed by the new value, --password to prompt for a new password and --generate
+to generate one.
+
+The master password is prompted for on the terminal unless --password-fd is given, then it is
+read from the first line of that file descriptor. New passwords are read from the following lines.";
+
+// The most days ahead expiring looks, 100 years
+const MAX_EXPIRING_DAYS: i64 = 36_500;
+
+// The options which take a value, all others are flags
+const VALUE_OPTIONS: [&str; 12] = ["duplicates", "email", "group", "iterations", "key-file", "length", "max-age", "min-score", "notes",
+                                   "password-fd", "url", "username"];
+const FIELD_OPTIONS: [&str; 7] = ["email", "generate", "group", "notes", "password", "url", "username"];
+
+#[derive(Debug, PartialEq)]
+pub struct Args {
+    pub db_path: PathBuf,
+    // Read passwords from this file descriptor rather than prompting on the terminal
+    pub password_fd: Option<i32>,
+    pub command: Command,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    List { search: String },
+    Show { record: String, show_password: bool },
+    Get { record: String, field: String },
+    Add { title: String, fields: RecordFields },
+    Edit { record: String, fields: RecordFields },
+    Delete { record: String },
+    Generate { record: Option<String>, length: Option<u16> },
+    ChangePassword { iterations: Option<u32> },
+    Import { path: PathBuf, duplicates: Duplicates, key_file: Option<PathBuf> },
+    Export { search: String, passwords: bool },
+    ExportXml,
+    Audit { max_age: Option<i64>, min_score: Option<u8> },
+    Expiring { days: Option<i64> },
+    Breached { hashes: PathBuf },
+    Diff { other: PathBuf },
+}
+
+// RecordFields are the record values to set, None leaves a value unchanged
+#[derive(Debug, Default, PartialEq)]
+pub struct RecordFields {
+    pub email: Option<String>,
+    pub group: Option<String>,
+    pub notes: Option<String>,
+    pub url: Option<String>,
+    pub username: Option<String>,
+    pub password: NewPassword,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub enum NewPassword {
+    #[default]
+    Unchanged,
+    Prompt,
+    Generate,
+}
+
+// parse reads the arguments, not including the program name
+pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut positional = Vec::new();
+    let mut options: HashMap<String, Option<String>> = HashMap::new();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            positional.push(arg);
+            continue
+        }
+        let name = arg[2..].to_string();
+        let value = if VALUE_OPTIONS.contains(&name.as_str()) {
+            Some(args.next().ok_or(format!("{} needs a value", arg))?)
+        } else {
+            None
+        };
+        if options.insert(name, value).is_some() {
+            return Err(format!("{} is given more than once", arg))
+        }
+    }
+
+    let mut positional = positional.into_iter();
+    let db_path = PathBuf::from(positional.next().ok_or("No DB file given")?);
+    let password_fd = number(options.remove("password-fd"), "--password-fd")?;
+    let name = positional.next().ok_or("No command given")?;
+    let positional: Vec<String> = positional.collect();
+
+    let (command, allowed, arguments): (Command, &[&str], (usize, usize)) = match name.as_str() {
+        "list" => (Command::List { search: positional.first().cloned().unwrap_or_default() }, &[], (0, 1)),
+        "show" => (Command::Show {
+            record: positional.first().cloned().unwrap_or_default(),
+            show_password: options.contains_key("show-password"),
+        }, &["show-password"], (1, 1)),
+        "get" => (Command::Get {
+            record: positional.first().cloned().unwrap_or_default(),
+            field: positional.get(1).cloned().unwrap_or_default(),
+        }, &[], (2, 2)),
+        "add" => {
+            let mut fields = record_fields(&options);
+            if fields.password == NewPassword::Unchanged {
+                fields.password = NewPassword::Prompt;
+            }
+            (Command::Add { title: positional.first().cloned().unwrap_or_default(), fields }, &FIELD_OPTIONS, (1, 1))
+        },
+        "edit" => (Command::Edit {
+            record: positional.first().cloned().unwrap_or_default(),
+            fields: record_fields(&options),
+        }, &FIELD_OPTIONS, (1, 1)),
+        "delete" => (Command::Delete { record: positional.first().cloned().unwrap_or_default() }, &[], (1, 1)),
+        "generate" => (Command::Generate {
+            record: positional.first().cloned(),
+            length: number(options.remove("length"), "--length")?,
+        }, &[], (0, 1)),
+        "passwd" => (Command::ChangePassword {
+            iterations: number(options.remove("iterations"), "--iterations")?,
+        }, &[], (0, 0)),
+        "import" => {
+            let duplicates = match options.remove("duplicates").flatten().as_deref() {
+                None | Some("skip") => Duplicates::Skip,
+                Some("replace") => Duplicates::Replace,
+                Some("add") => Duplicates::Add,
+                Some(other) => return Err(format!("--duplicates must be skip, replace or add, not {}", other)),
+            };
+            (Command::Import {
+                path: PathBuf::from(positional.first().cloned().unwrap_or_default()),
+                duplicates,
+                key_file: options.remove("key-file").flatten().map(PathBuf::from),
+            }, &[], (1, 1))
+        },
+        "export" if options.contains_key("xml") => (Command::ExportXml, &["xml"], (0, 0)),
+        "export" => (Command::Export {
+            search: positional.first().cloned().unwrap_or_default(),
+            passwords: options.contains_key("passwords"),
+        }, &["passwords"], (0, 1)),
+        "audit" => (Command::Audit {
+            max_age: number(options.remove("max-age"), "--max-age")?,
+            min_score: number(options.remove("min-score"), "--min-score")?,
+        }, &[], (0, 0)),
+        "expiring" => {
+            let days = number(positional.first().cloned().map(Some), "DAYS")?;
+            if days.is_some_and(|days| !(0..=MAX_EXPIRING_DAYS).contains(&days)) {
+                return Err(format!("DAYS must be from 0 to {}", MAX_EXPIRING_DAYS))
+            }
+            (Command::Expiring { days }, &[], (0, 1))
+        },
+        "breached" => (Command::Breached { hashes: PathBuf::from(positional.first().cloned().unwrap_or_default()) }, &[], (1, 1)),
+        "diff" => (Command::Diff { other: PathBuf::from(positional.first().cloned().unwrap_or_default()) }, &[], (1, 1)),
+        _ => return Err(format!("Unknown command {}", name)),
+    };
+
+    if positional.len() < arguments.0 || positional.len() > arguments.1 {
+        return Err(format!("Wrong number of arguments for {}", name))
+    }
+    if let Some(option) = options.keys().find(|option| !allowed.contains(&option.as_str())) {
+        return Err(format!("--{} is not an option of {}", option, name))
+    }
+
+    Ok(Args {
+        db_path,
+        password_fd,
+        command,
+    })
+}
+
+fn record_fields(options: &HashMap<String, Option<String>>) -> RecordFields {
+    let password = match (options.contains_key("password"), options.contains_key("generate")) {
+        (_, true) => NewPassword::Generate,
+        (true, false) => NewPassword::Prompt,
+        (false, false) => NewPassword::Unchanged,
+    };
+    RecordFields {
+        email: options.get("email").cloned().flatten(),
+        group: options.get("group").cloned().flatten(),
+        notes: options.get("notes").cloned().flatten(),
+        url: options.get("url").cloned().flatten(),
+        username: options.get("username").cloned().flatten(),
+        password,
+    }
+}
+
+fn number<T: std::str::FromStr>(value: Option<Option<String>>, option: &str) -> Result<Option<T>, String> {
+    match value.flatten() {
+        Some(value) => value.parse().map(Some).map_err(|_| format!("{} must be a number, not {}", option, value)),
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,338 @@
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use std::process;
+
+use pwdb::Database;
+use pwdb::audit::AuditOptions;
+use pwdb::breach::BreachList;
+use pwdb::csv::{CsvImport, PasswordExport};
+use pwdb::import::ImportReport;
+use pwdb::policy::PasswordPolicy;
+use pwdb::record::{DUE_SOON_DAYS, ExpiryStatus, Record, RecordBuilder};
+use pwdb::strength::{self, Strength};
+
+use args::{Command, NewPassword, RecordFields};
+
+mod args;
+#[cfg(test)]
+mod test;
+
+fn main() {
+    let args = match args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(msg) => {
+            eprintln!("{}\n\n{}", msg, args::USAGE);
+            process::exit(2);
+        },
+    };
+
+    let mut passwords = PasswordReader::new(args.password_fd);
+    if let Err(err) = run(&args.db_path, args.command, &mut passwords) {
+        eprintln!("Error: {}", err);
+        process::exit(1);
+    }
+}
+
+fn run(path: &Path, command: Command, passwords: &mut PasswordReader) -> Result<(), Box<dyn Error>> {
+    // Generating without a record doesn't need the DB so it can be used without the password
+    if let Command::Generate { record: None, length } = command {
+        let password = generate(&PasswordPolicy::default(), length)?;
+        println!("{}", password);
+        eprintln!("Strength: {}", meter(&strength::estimate(&password, &[])));
+        return Ok(())
+    }
+
+    let password = passwords.read("Password: ")?;
+    let mut db = Database::new(&fs::read(path)?, &password)?;
+    let mut changed = false;
+    match command {
+        Command::List { search } => {
+            let results = db.record_search(&search);
+            let mut records: Vec<&Record> = results.values().copied().collect();
+            records.sort_by(|a, b| (&a.group, &a.title).cmp(&(&b.group, &b.title)));
+            for record in records {
+                println!("{}\t{}\t{}\t{}", record.uuid(), record.group, record.title, record.username);
+            }
+        },
+        Command::Show { record, show_password } => {
+            let record = find_record(&db, &record)?;
+            let password = record.effective_password(db.records())?;
+            println!("UUID: {}", record.uuid());
+            println!("Group: {}", record.group);
+            println!("Title: {}", record.title);
+            println!("Username: {}", record.username);
+            println!("Password: {}", if show_password { password } else { "********" });
+            println!("Strength: {}", meter(&record.base(db.records())?.strength()));
+            if let Some(expiry) = record.password_expiry_time {
+                let status = match record.expiry_status() {
+                    ExpiryStatus::Ok => "",
+                    ExpiryStatus::DueSoon => " (due soon)",
+                    ExpiryStatus::Expired => " (expired)",
+                };
+                println!("Password expires: {}{}", expiry.to_rfc3339(), status);
+            }
+            println!("URL: {}", record.url);
+            println!("Email: {}", record.email);
+            if let Some(code) = record.one_time_code() {
+                println!("Two factor code: {} ({}s remaining)", code.code, code.seconds_remaining);
+            }
+            if let Some(modified) = record.mod_time {
+                println!("Modified: {}", modified.to_rfc3339());
+            }
+            println!("Notes: {}", record.notes);
+        },
+        Command::Get { record, field } => println!("{}", get_field(&db, find_record(&db, &record)?, &field)?),
+        Command::Add { title, fields } => {
+            let password = match fields.password {
+                NewPassword::Generate => generate_shown(&PasswordPolicy::default())?,
+                _ => passwords.read_new(&format!("Password for {}: ", title))?,
+            };
+            let mut record = RecordBuilder::new(&title, &password).build()?;
+            set_fields(&mut record, &fields);
+            eprintln!("Strength: {}", meter(&record.strength()));
+            println!("{}", db.add_record(record)?);
+            changed = true;
+        },
+        Command::Edit { record, fields } => {
+            let mut record = find_record(&db, &record)?.clone();
+            set_fields(&mut record, &fields);
+            match fields.password {
+                NewPassword::Unchanged => (),
+                NewPassword::Prompt => record.password = passwords.read_new(&format!("Password for {}: ", record.title))?.into(),
+                NewPassword::Generate => record.password = generate_shown(&db.password_policy(&record))?.into(),
+            }
+            if fields.password != NewPassword::Unchanged {
+                eprintln!("Strength: {}", meter(&record.strength()));
+            }
+            db.update_record(record)?;
+            changed = true;
+        },
+        Command::Delete { record } => {
+            let uuid = find_record(&db, &record)?.uuid();
+            db.delete_record(&uuid)?;
+            changed = true;
+        },
+        Command::Generate { record, length } => {
+            // The case without a record is handled above
+            let record = find_record(&db, &record.unwrap_or_default())?;
+            let password = generate(&db.password_policy(record), length)?;
+            println!("{}", password);
+            eprintln!("Strength: {}", meter(&strength::estimate(&password, &[&record.title, &record.username])));
+        },
+        Command::ChangePassword { iterations } => {
+            let new = passwords.read_new("New password: ")?;
+            db.change_password(&password, &new, iterations)?;
+            changed = true;
+        },
+        Command::Import { path, key_file, .. } if path.extension() == Some("kdbx".as_ref()) => {
+            let key_file = key_file.map(fs::read).transpose()?;
+            let keepass_password = passwords.read("KeePass password: ")?;
+            print_report(&db.import_kdbx(&fs::read(path)?, &keepass_password, key_file.as_deref())?);
+            changed = true;
+        },
+        Command::Import { path, .. } if path.extension() == Some("1pux".as_ref()) => {
+            print_report(&db.import_1pux(&fs::read(path)?)?);
+            changed = true;
+        },
+        Command::Import { path, .. } if path.extension() == Some("json".as_ref()) => {
+            print_report(&db.import_bitwarden(&fs::read_to_string(path)?)?);
+            changed = true;
+        },
+        Command::Import { path, .. } if path.extension() == Some("xml".as_ref()) => {
+            let added = db.import_xml(&fs::read_to_string(path)?)?;
+            println!("Added {} records", added.len());
+            changed = true;
+        },
+        Command::Import { path, duplicates, .. } => {
+            let options = CsvImport { duplicates, ..CsvImport::default() };
+            let report = db.import_csv(&fs::read_to_string(path)?, &options)?;
+            print_report(&report);
+            changed = !report.added.is_empty() || !report.replaced.is_empty();
+        },
+        Command::Export { search, passwords } => {
+            let passwords = if passwords { PasswordExport::Plaintext } else { PasswordExport::Omit };
+            print!("{}", db.export_csv(db.record_search(&search).values().copied(), passwords)?);
+        },
+        Command::ExportXml => print!("{}", db.export_xml()),
+        Command::Audit { max_age, min_score } => {
+            let defaults = AuditOptions::default();
+            let report = db.audit(&AuditOptions {
+                max_age_days: max_age.unwrap_or(defaults.max_age_days),
+                min_score: min_score.unwrap_or(defaults.min_score),
+            });
+            if report.is_empty() {
+                println!("No issues found");
+            } else {
+                print!("{}", report);
+            }
+        },
+        Command::Expiring { days } => {
+            for record in db.expiring_within(chrono::Duration::days(days.unwrap_or(DUE_SOON_DAYS))) {
+                let expiry = record.password_expiry_time.expect("expiring records have an expiry time");
+                println!("{}\t{}\t{}\t{}", expiry.format("%Y-%m-%d"), record.uuid(), record.group, record.title);
+            }
+        },
+        Command::Breached { hashes } => {
+            let breached = db.breached(&BreachList::open(&hashes)?)?;
+            for record in &breached {
+                println!("{} [{}] seen {} times", record.title, record.group, record.count);
+            }
+            if breached.is_empty() {
+                println!("No breached passwords found");
+            }
+        },
+        Command::Diff { other } => {
+            let other_password = passwords.read(&format!("Password for {}: ", other.display()))?;
+            let diff = pwdb::diff(&db, &Database::new(&fs::read(&other)?, &other_password)?);
+            if diff.is_empty() {
+                println!("No differences found");
+            } else {
+                print!("{}", diff);
+            }
+        },
+    }
+
+    if changed {
+        save(&mut db, path)?;
+    }
+    Ok(())
+}
+
+// print_report lists the items an import skipped, the records replaced and those given a generated password
+fn print_report(report: &ImportReport) {
+    for (item, err) in &report.skipped {
+        eprintln!("Skipped {}: {}", item, err);
+    }
+    println!("Added {} records", report.added.len());
+    if !report.replaced.is_empty() {
+        println!("Replaced {} records", report.replaced.len());
+    }
+    if !report.generated.is_empty() {
+        println!("{} records had no password and were given a generated one", report.generated.len());
+    }
+}
+
+// find_record looks up a record by UUID or else by its title, which must be unique
+fn find_record<'a>(db: &'a Database, id: &str) -> Result<&'a Record, Box<dyn Error>> {
+    if let Ok(uuid) = uuid::Uuid::parse_str(id) {
+        if let Some(record) = db.record(&uuid) {
+            return Ok(record)
+        }
+    }
+    let mut matches = db.records().values().filter(|record| record.title == id);
+    match (matches.next(), matches.next()) {
+        (Some(record), None) => Ok(record),
+        (Some(_), Some(_)) => Err(format!("More than one record is titled '{}', use the UUID instead", id).into()),
+        (None, _) => Err(format!("No record with the title or UUID '{}'", id).into()),
+    }
+}
+
+fn get_field(db: &Database, record: &Record, field: &str) -> Result<String, Box<dyn Error>> {
+    Ok(match field {
+        "group" => record.group.clone(),
+        "title" => record.title.clone(),
+        "username" => record.username.clone(),
+        "password" => record.effective_password(db.records())?.to_string(),
+        "url" => record.url.clone(),
+        "email" => record.email.clone(),
+        "notes" => record.notes.clone(),
+        "uuid" => record.uuid().to_string(),
+        "totp" => record.one_time_code().ok_or("The record has no two factor key")?.code,
+        "otpauth" => record.otpauth_uri().ok_or("The record has no two factor key")?,
+        _ => return Err(format!("Unknown field {}, see the usage for the fields", field).into()),
+    })
+}
+
+// set_fields applies the values given on the command line to the record, except the password
+fn set_fields(record: &mut Record, fields: &RecordFields) {
+    let values = [
+        (&mut record.email, &fields.email),
+        (&mut record.group, &fields.group),
+        (&mut record.notes, &fields.notes),
+        (&mut record.url, &fields.url),
+        (&mut record.username, &fields.username),
+    ];
+    for (value, new) in values {
+        if let Some(new) = new {
+            *value = new.clone();
+        }
+    }
+}
+
+// meter draws the strength score as a bar followed by the description
+fn meter(strength: &Strength) -> String {
+    format!("[{:<4}] {}", "#".repeat(strength.score as usize), strength)
+}
+
+// generate_shown generates a password and prints it as the user won't otherwise know it
+fn generate_shown(policy: &PasswordPolicy) -> Result<String, pwdb::Error> {
+    let password = pwdb::generate(policy)?;
+    println!("Generated password: {}", password);
+    Ok(password)
+}
+
+fn generate(policy: &PasswordPolicy, length: Option<u16>) -> Result<String, pwdb::Error> {
+    let mut policy = policy.clone();
+    if let Some(length) = length {
+        policy.length = length;
+    }
+    pwdb::generate(&policy)
+}
+
+// save writes to a temporary file which then replaces the DB so a failed write doesn't lose it
+fn save(db: &mut Database, path: &Path) -> io::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    fs::write(&temp, db.save())?;
+    fs::rename(&temp, path)
+}
+
+// PasswordReader reads passwords from the terminal or, one per line, from a file descriptor
+struct PasswordReader {
+    fd: Option<BufReader<File>>,
+}
+
+impl PasswordReader {
+    fn new(fd: Option<i32>) -> PasswordReader {
+        PasswordReader {
+            fd: fd.map(|fd| BufReader::new(open_fd(fd))),
+        }
+    }
+
+    fn read(&mut self, prompt: &str) -> io::Result<String> {
+        match self.fd.as_mut() {
+            Some(reader) => {
+                let mut line = String::new();
+                if reader.read_line(&mut line)? == 0 {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "No more passwords to read"))
+                }
+                Ok(line.trim_end_matches(&['\n', '\r'][..]).to_string())
+            },
+            None => rpassword::read_password_from_tty(Some(prompt)),
+        }
+    }
+
+    // read_new reads a new password, on the terminal it must be typed twice
+    fn read_new(&mut self, prompt: &str) -> io::Result<String> {
+        let password = self.read(prompt)?;
+        if self.fd.is_none() && password != self.read("Confirm the password: ")? {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "The passwords don't match"))
+        }
+        Ok(password)
+    }
+}
+
+#[cfg(unix)]
+fn open_fd(fd: i32) -> File {
+    use std::os::unix::io::FromRawFd;
+    // The descriptor is given by the user for this program to read and isn't used elsewhere
+    unsafe { File::from_raw_fd(fd) }
+}
+
+#[cfg(not(unix))]
+fn open_fd(_fd: i32) -> File {
+    eprintln!("--password-fd is only supported on unix");
+    process::exit(2);
+}
@@ -0,0 +1,59 @@
+use std::path::PathBuf;
+
+use crate::args::*;
+
+fn parse_args(args: &str) -> Result<Args, String> {
+    parse(args.split_whitespace().map(String::from))
+}
+
+#[test]
+fn parse_commands() {
+    assert_eq!(parse_args("--password-fd 3 db.psafe3 list mail").unwrap(), Args {
+        db_path: PathBuf::from("db.psafe3"),
+        password_fd: Some(3),
+        command: Command::List { search: "mail".to_string() },
+    });
+    assert_eq!(parse_args("db.psafe3 get bank password").unwrap().command,
+               Command::Get { record: "bank".to_string(), field: "password".to_string() });
+    assert_eq!(parse_args("db.psafe3 add bank --group personal --generate").unwrap().command, Command::Add {
+        title: "bank".to_string(),
+        fields: RecordFields { group: Some("personal".to_string()), password: NewPassword::Generate, ..Default::default() },
+    });
+    assert_eq!(parse_args("db.psafe3 add bank").unwrap().command, Command::Add {
+        title: "bank".to_string(),
+        fields: RecordFields { password: NewPassword::Prompt, ..Default::default() },
+    });
+    assert_eq!(parse_args("db.psafe3 edit bank --username alice").unwrap().command, Command::Edit {
+        record: "bank".to_string(),
+        fields: RecordFields { username: Some("alice".to_string()), ..Default::default() },
+    });
+    assert_eq!(parse_args("db.psafe3 generate --length 20").unwrap().command, Command::Generate { record: None, length: Some(20) });
+    assert_eq!(parse_args("db.psafe3 passwd --iterations 20000").unwrap().command, Command::ChangePassword { iterations: Some(20000) });
+    assert_eq!(parse_args("db.psafe3 import passwords.csv --duplicates replace").unwrap().command,
+               Command::Import { path: PathBuf::from("passwords.csv"), duplicates: pwdb::csv::Duplicates::Replace, key_file: None });
+    assert_eq!(parse_args("db.psafe3 import keepass.kdbx --key-file keepass.keyx").unwrap().command, Command::Import {
+        path: PathBuf::from("keepass.kdbx"),
+        duplicates: pwdb::csv::Duplicates::Skip,
+        key_file: Some(PathBuf::from("keepass.keyx")),
+    });
+    assert_eq!(parse_args("db.psafe3 export --passwords").unwrap().command, Command::Export { search: "".to_string(), passwords: true });
+    assert_eq!(parse_args("db.psafe3 export --xml").unwrap().command, Command::ExportXml);
+    assert_eq!(parse_args("db.psafe3 audit --max-age 90").unwrap().command, Command::Audit { max_age: Some(90), min_score: None });
+    assert_eq!(parse_args("db.psafe3 expiring 30").unwrap().command, Command::Expiring { days: Some(30) });
+    assert_eq!(parse_args("db.psafe3 breached pwned-passwords").unwrap().command, Command::Breached { hashes: PathBuf::from("pwned-passwords") });
+    assert_eq!(parse_args("db.psafe3 diff backup.psafe3").unwrap().command, Command::Diff { other: PathBuf::from("backup.psafe3") });
+}
+
+#[test]
+fn parse_errors() {
+    for args in ["", "db.psafe3", "db.psafe3 unknown", "db.psafe3 show", "db.psafe3 get bank", "db.psafe3 list --generate",
+                 "db.psafe3 generate --length many", "db.psafe3 add bank --group", "--password-fd x db.psafe3 list",
+                 "db.psafe3 delete a b", "db.psafe3 edit bank --url a --url b",
+                 "db.psafe3 import", "db.psafe3 import a.csv --duplicates merge",
+                 "db.psafe3 export bank --xml", "db.psafe3 export --xml --passwords",
+                 "db.psafe3 audit bank", "db.psafe3 breached", "db.psafe3 expiring soon",
+                 "db.psafe3 expiring 9999999999999", "db.psafe3 expiring -1", "db.psafe3 audit --min-score strong",
+                 "db.psafe3 diff", "db.psafe3 diff a b"].iter() {
+        assert!(parse_args(args).is_err(), "{}", args);
+    }
+}
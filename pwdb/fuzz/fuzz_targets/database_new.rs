@@ -0,0 +1,7 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+// Arbitrary bytes as a DB file, this mostly exercises the preamble and finding the EOF
+fuzz_target!(|data: &[u8]| {
+    let _ = pwdb::Database::new(&data.to_vec(), "password");
+});
@@ -0,0 +1,7 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+// Arbitrary bytes encrypted as the header and records of a DB, this exercises the field parsers
+fuzz_target!(|data: &[u8]| {
+    let _ = pwdb::Database::new(&pwdb::fuzz_db("password", data), "password");
+});
@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::Database;
+use crate::record::Record;
+use crate::strength::Strength;
+
+// AuditOptions are the thresholds for weak and old passwords
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuditOptions {
+    // Passwords with a strength score below this, from 0 to 4, are weak
+    pub min_score: u8,
+    // Passwords unchanged for more days than this are old
+    pub max_age_days: i64,
+}
+
+impl Default for AuditOptions {
+    fn default() -> AuditOptions {
+        AuditOptions {
+            min_score: 3,
+            max_age_days: 365,
+        }
+    }
+}
+
+// AuditReport lists the records with problems, sorted by group then title
+#[derive(Debug, Default, PartialEq)]
+pub struct AuditReport {
+    pub records: Vec<RecordAudit>,
+    // The records sharing each reused password, in the same order as records
+    pub reused: Vec<Vec<Uuid>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct RecordAudit {
+    pub uuid: Uuid,
+    pub group: String,
+    pub title: String,
+    pub issues: Vec<Issue>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Issue {
+    // The password is also used by the listed records
+    Reused(Vec<Uuid>),
+    PasswordIsUsername,
+    PasswordIsTitle,
+    // The estimated strength of the password is below the minimum score
+    Weak(Strength),
+    // The password hasn't been changed for this many days
+    Old(i64),
+    // The password expired at this time
+    Expired(DateTime<Utc>),
+    NoUrl,
+}
+
+impl Database {
+    // audit checks the password of each record for reuse, weakness and age as well as reporting
+    // expired records and those without a URL. Aliases and shortcuts are left out as their password
+    // is their base record's.
+    pub fn audit(&self, options: &AuditOptions) -> AuditReport {
+        let now = crate::now();
+        let mut records: Vec<&Record> = self.records.values().filter(|record| record.reference().is_none()).collect();
+        records.sort_by(|a, b| (&a.group, &a.title, a.uuid).cmp(&(&b.group, &b.title, b.uuid)));
+
+        let mut by_password: HashMap<&str, Vec<Uuid>> = HashMap::new();
+        for record in &records {
+            by_password.entry(record.password.as_str()).or_default().push(record.uuid);
+        }
+
+        let mut report = AuditReport::default();
+        for record in records {
+            let mut issues = Vec::new();
+            let shared = &by_password[record.password.as_str()];
+            if shared.len() > 1 {
+                if shared[0] == record.uuid {
+                    report.reused.push(shared.clone());
+                }
+                issues.push(Issue::Reused(shared.iter().filter(|uuid| **uuid != record.uuid).cloned().collect()));
+            }
+            if !record.username.is_empty() && record.password.eq_ignore_ascii_case(&record.username) {
+                issues.push(Issue::PasswordIsUsername);
+            }
+            if record.password.eq_ignore_ascii_case(&record.title) {
+                issues.push(Issue::PasswordIsTitle);
+            }
+            let strength = record.strength();
+            if strength.score < options.min_score {
+                issues.push(Issue::Weak(strength));
+            }
+            if let Some(changed) = record.password_mod_time.or(record.create_time) {
+                let days = (now - changed).num_days();
+                if days > options.max_age_days {
+                    issues.push(Issue::Old(days));
+                }
+            }
+            if let Some(expiry) = record.password_expiry_time.filter(|expiry| *expiry <= now) {
+                issues.push(Issue::Expired(expiry));
+            }
+            if record.url.is_empty() {
+                issues.push(Issue::NoUrl);
+            }
+
+            if !issues.is_empty() {
+                report.records.push(RecordAudit {
+                    uuid: record.uuid,
+                    group: record.group.clone(),
+                    title: record.title.clone(),
+                    issues,
+                });
+            }
+        }
+        report
+    }
+}
+
+impl AuditReport {
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+}
+
+// Display lists each record with its issues followed by the groups of records sharing a password
+impl fmt::Display for AuditReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = |uuid: &Uuid| self.records.iter().find(|record| record.uuid == *uuid)
+            .map(|record| format!("{} [{}]", record.title, record.group))
+            .unwrap_or_else(|| uuid.to_string());
+        for record in &self.records {
+            writeln!(f, "{} [{}]", record.title, record.group)?;
+            for issue in &record.issues {
+                writeln!(f, "    {}", issue)?;
+            }
+        }
+        for records in &self.reused {
+            writeln!(f, "Shared password: {}", records.iter().map(name).collect::<Vec<String>>().join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Issue::Reused(others) if others.len() == 1 => write!(f, "Password is shared with 1 other record"),
+            Issue::Reused(others) => write!(f, "Password is shared with {} other records", others.len()),
+            Issue::PasswordIsUsername => write!(f, "Password is the username"),
+            Issue::PasswordIsTitle => write!(f, "Password is the title"),
+            Issue::Weak(strength) => write!(f, "Password is {}", strength),
+            Issue::Old(days) => write!(f, "Password is {} days old", days),
+            Issue::Expired(time) => write!(f, "Password expired on {}", time.format("%Y-%m-%d")),
+            Issue::NoUrl => write!(f, "No URL"),
+        }
+    }
+}
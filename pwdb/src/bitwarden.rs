@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, TimeZone, Utc};
+use uuid::Uuid;
+
+use crate::{Database, Error};
+use crate::group;
+use crate::history::PasswordHistory;
+use crate::import::ImportReport;
+use crate::json::{self, Value};
+use crate::otp::Totp;
+use crate::record::{self, Record};
+
+// The item types of a Bitwarden export
+const LOGIN: i64 = 1;
+const SECURE_NOTE: i64 = 2;
+const CARD: i64 = 3;
+const IDENTITY: i64 = 4;
+const SSH_KEY: i64 = 5;
+
+// The custom field type which links to a login field rather than holding a value
+const LINKED_FIELD: i64 = 3;
+
+impl Database {
+    // import_bitwarden adds the items of an unencrypted Bitwarden JSON export. Folders become groups,
+    // or for an organization export the first collection of an item. Logins, cards and secure notes
+    // are imported, custom fields and the URIs after the first are added to the notes. Items without
+    // a password, such as cards and notes, are given a generated one. Identities and other item
+    // types are reported as skipped.
+    pub fn import_bitwarden(&mut self, data: &str) -> Result<ImportReport, Error> {
+        let root = json::parse(data)?;
+        if root.get("encrypted") == &Value::Bool(true) {
+            return Err(Error::InvalidExport("encrypted Bitwarden exports can't be read".to_string()))
+        }
+        if !matches!(root.get("items"), Value::Array(_)) {
+            return Err(Error::InvalidExport("there is no Bitwarden items list".to_string()))
+        }
+        // Nested folders are named with their path separated by /
+        let groups: HashMap<&str, String> = root.get("folders").array().iter()
+            .chain(root.get("collections").array())
+            .map(|folder| {
+                let names: Vec<String> = folder.get("name").str().split('/').map(String::from).collect();
+                (folder.get("id").str(), group::join_path(&names))
+            })
+            .collect();
+
+        let mut report = ImportReport::default();
+        for item in root.get("items").array() {
+            let group = groups.get(item.get("folderId").str())
+                .or_else(|| item.get("collectionIds").array().first().and_then(|id| groups.get(id.str())))
+                .cloned()
+                .unwrap_or_default();
+            match self.bitwarden_item(item, &group).and_then(|record| self.add_imported(record, &mut report)) {
+                Ok(()) => (),
+                Err(err) => {
+                    let mut names = group::split_path(&group);
+                    names.push(item.get("name").str().to_string());
+                    report.skipped.push((group::join_path(&names), err));
+                },
+            }
+        }
+        self.modified();
+        Ok(report)
+    }
+
+    fn bitwarden_item(&self, item: &Value, group: &str) -> Result<Record, Error> {
+        let mut record = Record {
+            group: group.to_string(),
+            title: item.get("name").str().to_string(),
+            notes: item.get("notes").str().to_string(),
+            uuid: Uuid::parse_str(item.get("id").str()).unwrap_or_default(),
+            create_time: time(item.get("creationDate")),
+            mod_time: time(item.get("revisionDate")),
+            ..Default::default()
+        };
+        let mut notes = Vec::new();
+        let item_type = integer(item.get("type"));
+        match item_type {
+            Some(LOGIN) => {
+                let login = item.get("login");
+                record.username = login.get("username").str().to_string();
+                record.password = login.get("password").str().into();
+                record.password_mod_time = time(login.get("passwordRevisionDate"));
+                let mut uris = login.get("uris").array().iter().map(|uri| uri.get("uri").str()).filter(|uri| !uri.is_empty());
+                record.url = uris.next().unwrap_or_default().to_string();
+                notes.extend(uris.map(|uri| format!("URL: {}", uri)));
+                let secret = login.get("totp").str();
+                if !secret.is_empty() {
+                    match Totp::from_secret(secret) {
+                        Some(totp) => record.totp = Some(totp),
+                        None => notes.push(format!("TOTP: {}", secret)),
+                    }
+                }
+            },
+            Some(CARD) => {
+                let card = item.get("card");
+                record.username = card.get("cardholderName").str().to_string();
+                record.credit_card_number = card.get("number").str().into();
+                record.credit_card_verify = card.get("code").str().into();
+                if let (Ok(month), Ok(year)) = (card.get("expMonth").str().parse(), card.get("expYear").str().parse()) {
+                    record.credit_card_expiration = record::card_expiration(month, year);
+                }
+                if !card.get("brand").str().is_empty() {
+                    notes.push(format!("Brand: {}", card.get("brand").str()));
+                }
+            },
+            Some(SECURE_NOTE) => (),
+            Some(IDENTITY) => return Err(Error::UnsupportedItem("Bitwarden identity".to_string())),
+            Some(SSH_KEY) => return Err(Error::UnsupportedItem("Bitwarden SSH key".to_string())),
+            _ => return Err(Error::UnsupportedItem(format!("Bitwarden type {}", item_type.unwrap_or_default()))),
+        }
+        if record.title.is_empty() {
+            record.title = record.url.clone();
+        }
+        record.password_mod_time = record.password_mod_time.or(record.create_time);
+
+        for field in item.get("fields").array() {
+            if integer(field.get("type")) == Some(LINKED_FIELD) {
+                continue
+            }
+            let value = match field.get("value") {
+                Value::String(value) => value.clone(),
+                Value::Bool(value) => value.to_string(),
+                _ => continue,
+            };
+            notes.push(format!("{}: {}", field.get("name").str(), value));
+        }
+        record.append_notes(&notes);
+
+        let history = item.get("passwordHistory").array().iter()
+            .map(|entry| (time(entry.get("lastUsedDate")).unwrap_or_else(crate::now), entry.get("password").str().to_string()))
+            .collect();
+        record.password_history = PasswordHistory::imported(history);
+        Ok(record)
+    }
+}
+
+// integer is a whole number value, the item and field types are numbers
+fn integer(value: &Value) -> Option<i64> {
+    value.number().filter(|number| number.fract() == 0.0).map(|number| number as i64)
+}
+
+// time parses an ISO 8601 time to the second, as records store them, missing or invalid times are
+// treated as unset
+fn time(value: &Value) -> Option<DateTime<Utc>> {
+    let time = DateTime::parse_from_rfc3339(value.str()).ok()?;
+    Utc.timestamp_opt(time.timestamp(), 0).single()
+}
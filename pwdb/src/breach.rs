@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use sha1::{Digest, Sha1};
+use uuid::Uuid;
+
+use crate::{Database, Error};
+use crate::record::Record;
+
+// Binary searching a sorted file stops once the range is this small and reads it line by line
+const SCAN_BYTES: u64 = 4096;
+
+// BreachList is a local copy of the Have I Been Pwned SHA-1 password hashes. Passwords are only ever
+// looked up on disk, nothing is sent over the network.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BreachList {
+    // A directory of k-anonymity range files, each named by the first 5 hex digits of the hashes it
+    // holds, optionally with a .txt extension, and made of SUFFIX:COUNT lines
+    Ranges(PathBuf),
+    // A single file of HASH:COUNT lines sorted by hash
+    Sorted(PathBuf),
+}
+
+// Breached is a record whose password is in the breach list
+#[derive(Debug, PartialEq)]
+pub struct Breached {
+    pub uuid: Uuid,
+    pub group: String,
+    pub title: String,
+    // The number of times the password has been seen in breaches
+    pub count: u64,
+}
+
+impl BreachList {
+    // open picks the format from the path, a directory of range files or a sorted hash file
+    pub fn open(path: &Path) -> Result<BreachList, Error> {
+        let metadata = path.metadata().map_err(|err| io_error(path, err))?;
+        if metadata.is_dir() {
+            Ok(BreachList::Ranges(path.to_path_buf()))
+        } else {
+            Ok(BreachList::Sorted(path.to_path_buf()))
+        }
+    }
+
+    // count returns the number of times the password has been seen in breaches, 0 if never
+    pub fn count(&self, password: &str) -> Result<u64, Error> {
+        let hash: String = Sha1::digest(password.as_bytes()).iter().map(|b| format!("{:02X}", b)).collect();
+        match self {
+            BreachList::Ranges(dir) => range_count(dir, &hash),
+            BreachList::Sorted(path) => sorted_count(path, &hash),
+        }
+    }
+}
+
+impl Database {
+    // breached lists the records whose password is in the breach list, sorted by group then title.
+    // Aliases and shortcuts are left out as their password is their base record's.
+    pub fn breached(&self, list: &BreachList) -> Result<Vec<Breached>, Error> {
+        let mut records: Vec<&Record> = self.records.values()
+            .filter(|record| record.reference().is_none() && !record.password.is_empty())
+            .collect();
+        records.sort_by(|a, b| (&a.group, &a.title, a.uuid).cmp(&(&b.group, &b.title, b.uuid)));
+
+        let mut counts: HashMap<&str, u64> = HashMap::new();
+        let mut breached = Vec::new();
+        for record in records {
+            let count = match counts.get(record.password.as_str()) {
+                Some(count) => *count,
+                None => {
+                    let count = list.count(&record.password)?;
+                    counts.insert(record.password.as_str(), count);
+                    count
+                },
+            };
+            if count > 0 {
+                breached.push(Breached {
+                    uuid: record.uuid,
+                    group: record.group.clone(),
+                    title: record.title.clone(),
+                    count,
+                });
+            }
+        }
+        Ok(breached)
+    }
+}
+
+// range_count reads the range file for the first 5 digits of the hash and finds the rest in it
+fn range_count(dir: &Path, hash: &str) -> Result<u64, Error> {
+    let (prefix, suffix) = hash.split_at(5);
+    let mut path = dir.join(format!("{}.txt", prefix));
+    if !path.exists() {
+        path = dir.join(prefix);
+    }
+    let reader = BufReader::new(File::open(&path).map_err(|err| io_error(&path, err))?);
+    for line in reader.lines() {
+        let line = line.map_err(|err| io_error(&path, err))?;
+        if let Some((candidate, count)) = parse_line(&line, 35, &path)? {
+            if candidate.eq_ignore_ascii_case(suffix) {
+                return Ok(count)
+            }
+        }
+    }
+    Ok(0)
+}
+
+// sorted_count binary searches the byte offsets of the file for the line of the hash
+fn sorted_count(path: &Path, hash: &str) -> Result<u64, Error> {
+    let file = File::open(path).map_err(|err| io_error(path, err))?;
+    let length = file.metadata().map_err(|err| io_error(path, err))?.len();
+    let mut reader = BufReader::new(file);
+    let mut line = String::new();
+
+    // low is always the start of a line, every line starting before it has a smaller hash
+    let (mut low, mut high) = (0, length);
+    while high.saturating_sub(low) > SCAN_BYTES {
+        let middle = low + (high - low) / 2;
+        reader.seek(SeekFrom::Start(middle - 1)).map_err(|err| io_error(path, err))?;
+        line.clear();
+        let skipped = reader.read_line(&mut line).map_err(|err| io_error(path, err))?;
+        line.clear();
+        reader.read_line(&mut line).map_err(|err| io_error(path, err))?;
+        match parse_line(&line, 40, path)? {
+            Some((candidate, _)) if candidate.to_ascii_uppercase().as_str() < hash => low = middle - 1 + skipped as u64,
+            _ => high = middle,
+        }
+    }
+
+    reader.seek(SeekFrom::Start(low)).map_err(|err| io_error(path, err))?;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).map_err(|err| io_error(path, err))? == 0 {
+            return Ok(0)
+        }
+        if let Some((candidate, count)) = parse_line(&line, 40, path)? {
+            let candidate = candidate.to_ascii_uppercase();
+            if candidate.as_str() >= hash {
+                return Ok(if candidate == hash { count } else { 0 })
+            }
+        }
+    }
+}
+
+// parse_line splits a HASH:COUNT line where the hash has the given number of hex digits, blank
+// lines are None
+fn parse_line<'a>(line: &'a str, digits: usize, path: &Path) -> Result<Option<(&'a str, u64)>, Error> {
+    let line = line.trim();
+    if line.is_empty() {
+        return Ok(None)
+    }
+    let invalid = || Error::InvalidHashList(format!("{}: '{}' is not a HASH:COUNT line", path.display(), line));
+    let (hash, count) = line.split_once(':').ok_or_else(invalid)?;
+    if hash.len() != digits || !hash.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return Err(invalid())
+    }
+    Ok(Some((hash, count.parse().map_err(|_| invalid())?)))
+}
+
+fn io_error(path: &Path, err: std::io::Error) -> Error {
+    Error::InvalidHashList(format!("{}: {}", path.display(), err))
+}
@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+
+use uuid::Uuid;
+
+use crate::{Database, Error};
+use crate::import::ImportReport;
+use crate::record::{Record, RecordBuilder};
+
+// Column is the record field held in a CSV column
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Column {
+    Group,
+    Title,
+    Username,
+    Password,
+    Url,
+    Notes,
+    Email,
+    Ignore,
+}
+
+impl Column {
+    // from_name maps a header name to a column, the names used by common browsers and password
+    // managers are recognised
+    pub fn from_name(name: &str) -> Column {
+        match name.trim().to_lowercase().as_str() {
+            "group" | "grouping" | "folder" | "category" => Column::Group,
+            "title" | "name" => Column::Title,
+            "username" | "user" | "user name" | "login" | "login_username" => Column::Username,
+            "password" | "login_password" => Column::Password,
+            "url" | "website" | "web site" | "login_uri" => Column::Url,
+            "notes" | "note" | "extra" | "comments" => Column::Notes,
+            "email" | "e-mail" => Column::Email,
+            _ => Column::Ignore,
+        }
+    }
+}
+
+// Duplicates is what to do with an imported record with the same group, title and username as an
+// existing record
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Duplicates {
+    Skip,
+    // The existing record's fields are updated from the CSV values which aren't empty
+    Replace,
+    // The record is added alongside the existing one
+    Add,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CsvImport {
+    // The field of each column, when None the first row is a header naming the columns
+    pub columns: Option<Vec<Column>>,
+    // With columns given the first row is skipped if it is a header
+    pub has_header: bool,
+    pub duplicates: Duplicates,
+}
+
+impl Default for CsvImport {
+    fn default() -> CsvImport {
+        CsvImport {
+            columns: None,
+            has_header: true,
+            duplicates: Duplicates::Skip,
+        }
+    }
+}
+
+// PasswordExport must be given explicitly when exporting as the CSV is not encrypted
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PasswordExport {
+    Omit,
+    Plaintext,
+}
+
+impl Database {
+    // import_csv adds records from CSV data, see CsvImport for the options. Rows are skipped by their
+    // number, duplicates which aren't replaced are skipped with a DuplicateRecord error.
+    pub fn import_csv(&mut self, data: &str, options: &CsvImport) -> Result<ImportReport, Error> {
+        let mut rows = parse(data)?.into_iter().enumerate();
+        let columns = match &options.columns {
+            Some(columns) => {
+                if options.has_header {
+                    rows.next();
+                }
+                columns.clone()
+            },
+            None => match rows.next() {
+                Some((_, header)) => header.iter().map(|name| Column::from_name(name)).collect(),
+                None => Vec::new(),
+            },
+        };
+        if !columns.contains(&Column::Password) {
+            return Err(Error::InvalidCsv("there is no password column".to_string()))
+        }
+
+        let mut existing: HashMap<(String, String, String), Uuid> = self.records.values()
+            .map(|record| (duplicate_key(record), record.uuid()))
+            .collect();
+        let mut report = ImportReport::default();
+        for (i, row) in rows {
+            if row.iter().all(|value| value.is_empty()) {
+                continue
+            }
+            let value = |column: Column| columns.iter().zip(&row)
+                .filter(|(c, _)| **c == column)
+                .map(|(_, value)| value.as_str())
+                .find(|value| !value.is_empty())
+                .unwrap_or_default();
+            // Browser exports sometimes leave the name empty so fall back to the URL
+            let title = if value(Column::Title).is_empty() { value(Column::Url) } else { value(Column::Title) };
+            let record = RecordBuilder::new(title, value(Column::Password))
+                .email(value(Column::Email))
+                .group(value(Column::Group))
+                .notes(value(Column::Notes))
+                .url(value(Column::Url))
+                .username(value(Column::Username))
+                .build();
+            let record = match record {
+                Ok(record) => record,
+                Err(err) => {
+                    report.skipped.push((row_name(i), err));
+                    continue
+                },
+            };
+
+            let key = duplicate_key(&record);
+            match (existing.get(&key), options.duplicates) {
+                (Some(uuid), Duplicates::Skip) => report.skipped.push((row_name(i), Error::DuplicateRecord(*uuid))),
+                (Some(uuid), Duplicates::Replace) => {
+                    // Columns which aren't mapped or are empty in this row keep the existing value
+                    let mut replaced = self.records[uuid].clone();
+                    if !record.email.is_empty() {
+                        replaced.email = record.email;
+                    }
+                    if !record.notes.is_empty() {
+                        replaced.notes = record.notes;
+                    }
+                    if !record.url.is_empty() {
+                        replaced.url = record.url;
+                    }
+                    replaced.password = record.password;
+                    self.update_record(replaced)?;
+                    report.replaced.push(*uuid);
+                },
+                (_, _) => {
+                    let uuid = self.add_record(record)?;
+                    existing.insert(key, uuid);
+                    report.added.push(uuid);
+                },
+            }
+        }
+        Ok(report)
+    }
+
+    // export_csv writes the given records, either all of the DB records or a search result, as CSV
+    // with a header row. Aliases and shortcuts are written with the password of their base record.
+    pub fn export_csv<'a>(&self, records: impl IntoIterator<Item = &'a Record>, passwords: PasswordExport) -> Result<String, Error> {
+        let mut header = vec!["group", "title", "username", "password", "url", "notes", "email"];
+        if passwords == PasswordExport::Omit {
+            header.retain(|column| *column != "password");
+        }
+        let mut csv = row(header.into_iter());
+
+        let mut records: Vec<&Record> = records.into_iter().collect();
+        records.sort_by(|a, b| (&a.group, &a.title, a.uuid()).cmp(&(&b.group, &b.title, b.uuid())));
+        for record in records {
+            let password = match passwords {
+                PasswordExport::Plaintext => Some(record.effective_password(&self.records)?),
+                PasswordExport::Omit => None,
+            };
+            let fields = [Some(record.group.as_str()), Some(&record.title), Some(&record.username), password,
+                Some(&record.url), Some(&record.notes), Some(&record.email)];
+            csv += &row(fields.iter().flatten().copied());
+        }
+        Ok(csv)
+    }
+}
+
+// duplicate_key identifies records which are considered the same when importing
+fn duplicate_key(record: &Record) -> (String, String, String) {
+    (record.group.clone(), record.title.clone(), record.username.clone())
+}
+
+// row_name names a skipped row by its number counting from 1, given its index in the data
+fn row_name(index: usize) -> String {
+    format!("row {}", index + 1)
+}
+
+// parse reads RFC 4180 CSV, quoted values may contain commas, quotes as "" and line breaks
+fn parse(data: &str) -> Result<Vec<Vec<String>>, Error> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut value = String::new();
+    let mut quoted = false;
+    let mut chars = data.trim_start_matches('\u{feff}').chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                value.push('"');
+                chars.next();
+            },
+            ('"', true) => quoted = false,
+            ('"', false) if value.is_empty() => quoted = true,
+            (',', false) => row.push(std::mem::take(&mut value)),
+            ('\r', false) if chars.peek() == Some(&'\n') => (),
+            ('\n', false) => {
+                row.push(std::mem::take(&mut value));
+                rows.push(std::mem::take(&mut row));
+            },
+            _ => value.push(c),
+        }
+    }
+    if quoted {
+        return Err(Error::InvalidCsv(format!("a quoted value in row {} is not closed", rows.len() + 1)))
+    }
+    if !value.is_empty() || !row.is_empty() {
+        row.push(value);
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+// row formats a CSV row, values are quoted when needed
+fn row<'a>(values: impl Iterator<Item = &'a str>) -> String {
+    let values: Vec<String> = values.map(|value| {
+        if value.contains(&[',', '"', '\n', '\r'][..]) || value.trim() != value {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        } else {
+            value.to_string()
+        }
+    }).collect();
+    values.join(",") + "\r\n"
+}
@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use uuid::Uuid;
+
+use crate::Database;
+use crate::record::Record;
+use crate::secret::SecretString;
+
+// Secret values are replaced with this unless diffs are made with the passwords shown
+const MASK: &str = "********";
+
+// Diff is the set of changes from an old to a new version of a database
+#[derive(Debug, Default, PartialEq)]
+pub struct Diff {
+    pub header: Vec<FieldChange>,
+    pub added: Vec<RecordDiff>,
+    pub removed: Vec<RecordDiff>,
+    pub modified: Vec<RecordDiff>,
+}
+
+// RecordDiff is the changed fields of a record, for added and removed records every field with a
+// value is listed
+#[derive(Debug, PartialEq)]
+pub struct RecordDiff {
+    pub uuid: Uuid,
+    pub group: String,
+    pub title: String,
+    pub changes: Vec<FieldChange>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct FieldChange {
+    pub field: &'static str,
+    pub old: String,
+    pub new: String,
+}
+
+// diff compares two databases with passwords and other secrets masked
+pub fn diff(old: &Database, new: &Database) -> Diff {
+    diff_records(old, new, false)
+}
+
+// diff_showing_passwords compares two databases including the values of passwords and other secrets
+pub fn diff_showing_passwords(old: &Database, new: &Database) -> Diff {
+    diff_records(old, new, true)
+}
+
+fn diff_records(old: &Database, new: &Database, show_passwords: bool) -> Diff {
+    let mut diff = Diff::default();
+    for ((field, old_value), (_, new_value)) in old.header.display_fields().into_iter().zip(new.header.display_fields()) {
+        if old_value != new_value {
+            diff.header.push(FieldChange { field, old: old_value, new: new_value });
+        }
+    }
+
+    for (uuid, new_record) in &new.records {
+        match old.records.get(uuid) {
+            None => diff.added.push(record_diff(None, new_record, show_passwords)),
+            Some(old_record) => {
+                let changed = record_diff(Some(old_record), new_record, show_passwords);
+                if !changed.changes.is_empty() {
+                    diff.modified.push(changed);
+                }
+            },
+        }
+    }
+    diff.removed = removed(&old.records, &new.records, show_passwords);
+
+    for records in [&mut diff.added, &mut diff.removed, &mut diff.modified].iter_mut() {
+        records.sort_by(|a, b| (&a.group, &a.title, a.uuid).cmp(&(&b.group, &b.title, b.uuid)));
+    }
+    diff
+}
+
+fn removed(old: &HashMap<Uuid, Record>, new: &HashMap<Uuid, Record>, show_passwords: bool) -> Vec<RecordDiff> {
+    old.iter()
+        .filter(|(uuid, _)| !new.contains_key(uuid))
+        .map(|(_, record)| {
+            // Compare against an empty record to list its values, then swap to show them as removed
+            let mut removed = record_diff(None, record, show_passwords);
+            for change in removed.changes.iter_mut() {
+                std::mem::swap(&mut change.old, &mut change.new);
+            }
+            removed
+        })
+        .collect()
+}
+
+// record_diff lists the fields which differ, with no old record every field with a value is listed
+fn record_diff(old: Option<&Record>, new: &Record, show_passwords: bool) -> RecordDiff {
+    let new_fields = new.display_fields();
+    let old_fields = match old {
+        Some(old) => old.display_fields(),
+        None => new_fields.iter().map(|(field, _, secret)| (*field, SecretString::default(), *secret)).collect(),
+    };
+
+    let mask = |value: SecretString, secret: bool| {
+        if secret && !show_passwords && !value.is_empty() { MASK.to_string() } else { value.as_str().to_string() }
+    };
+    let changes = old_fields.into_iter().zip(new_fields)
+        .filter(|((_, old_value, _), (_, new_value, _))| old_value != new_value)
+        .map(|((field, old_value, secret), (_, new_value, _))| FieldChange {
+            field,
+            old: mask(old_value, secret),
+            new: mask(new_value, secret),
+        })
+        .collect();
+
+    RecordDiff {
+        uuid: new.uuid(),
+        group: new.group.clone(),
+        title: new.title.clone(),
+        changes,
+    }
+}
+
+impl Diff {
+    pub fn is_empty(&self) -> bool {
+        self.header.is_empty() && self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+// Display lists the changes one per line, added records are prefixed with +, removed with - and
+// modified with ~ followed by the title and group
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for change in &self.header {
+            writeln!(f, "header {}", change)?;
+        }
+        for (prefix, records) in [("+", &self.added), ("-", &self.removed), ("~", &self.modified)].iter() {
+            for record in records.iter() {
+                writeln!(f, "{} {} [{}]", prefix, record.title, record.group)?;
+                for change in &record.changes {
+                    writeln!(f, "    {}", change)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for FieldChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: '{}' -> '{}'", self.field, self.old, self.new)
+    }
+}
@@ -0,0 +1,97 @@
+use std::fmt;
+
+use uuid::Uuid;
+
+// Error is returned by all fallible pwdb operations. The variants are distinct so callers can react
+// differently to each, for example re-prompting only on a wrong password.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    // The password does not match the one used to encrypt the DB
+    WrongPassword,
+    // The data is not in the Password Safe V3 format
+    NotPwsafe3,
+    // The data ended before a complete structure was read, the string describes what was expected
+    Truncated(String),
+    // The HMAC of the decrypted data does not match the HMAC stored in the DB
+    HmacMismatch,
+    // The length of a field's data is not valid for its type
+    BadFieldLength { type_id: u8, length: usize },
+    // A text field is not valid UTF-8
+    InvalidUtf8 { type_id: u8 },
+    // The data of a field doesn't match the format for its type, the string describes the problem
+    InvalidField { type_id: u8, reason: String },
+    // A field required by the spec is missing, the string describes which
+    MissingField(String),
+    // A DB can't be created or its password changed to an empty password
+    EmptyPassword,
+    // The number of iterations used to stretch the password is out of the allowed range
+    InvalidIterations(u32),
+    // A password can't be generated with the policy, the string describes why
+    InvalidPolicy(String),
+    // An otpauth URI can't be used as a two factor key, the string describes why
+    InvalidOtpAuth(String),
+    // CSV data can't be imported, the string describes why
+    InvalidCsv(String),
+    // A pwsafe XML export can't be imported, the string describes why
+    InvalidXml(String),
+    // A KeePass database can't be read, the string describes why
+    InvalidKdbx(String),
+    // JSON data can't be parsed, the string describes the problem and where it is
+    InvalidJson(String),
+    // The data isn't laid out as the password manager export it is read as, the string describes why
+    InvalidExport(String),
+    // An item of another password manager's export has no equivalent record, the string names its type
+    UnsupportedItem(String),
+    // The breached password hashes can't be read or aren't HASH:COUNT lines, the string describes why
+    InvalidHashList(String),
+    // A record with the UUID is already in the DB, or a CSV row matches it and was skipped
+    DuplicateRecord(Uuid),
+    // No record in the DB has the UUID
+    RecordNotFound(Uuid),
+    // The group path isn't in the tree of record and empty groups
+    GroupNotFound(String),
+    // The group path is already in the tree of record and empty groups
+    GroupExists(String),
+    // The group can't be deleted as it or a group below it has records
+    GroupNotEmpty(String),
+    // An alias or shortcut refers to a base record which doesn't exist
+    DanglingReference(Uuid),
+    // Following aliases or shortcuts from the record loops back on itself
+    CyclicReference(Uuid),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::WrongPassword => write!(f, "Invalid Password"),
+            Error::NotPwsafe3 => write!(f, "Data is not a Password Safe V3 DB"),
+            Error::Truncated(expected) => write!(f, "DB data is truncated: {}", expected),
+            Error::HmacMismatch => write!(f, "HMAC mismatch!"),
+            Error::BadFieldLength { type_id, length } =>
+                write!(f, "Unexpected length {} for field type {:#04x}", length, type_id),
+            Error::InvalidUtf8 { type_id } => write!(f, "Field type {:#04x} is not valid UTF-8", type_id),
+            Error::InvalidField { type_id, reason } => write!(f, "Field type {:#04x} is invalid: {}", type_id, reason),
+            Error::MissingField(field) => write!(f, "Missing required field: {}", field),
+            Error::EmptyPassword => write!(f, "The DB password must not be empty"),
+            Error::InvalidIterations(iter) => write!(f, "Invalid number of hash function iterations: {}", iter),
+            Error::InvalidPolicy(reason) => write!(f, "Invalid password policy: {}", reason),
+            Error::InvalidOtpAuth(reason) => write!(f, "Invalid otpauth URI: {}", reason),
+            Error::InvalidCsv(reason) => write!(f, "Invalid CSV: {}", reason),
+            Error::InvalidXml(reason) => write!(f, "Invalid XML: {}", reason),
+            Error::InvalidKdbx(reason) => write!(f, "Invalid KeePass database: {}", reason),
+            Error::InvalidJson(reason) => write!(f, "Invalid JSON: {}", reason),
+            Error::InvalidExport(reason) => write!(f, "Invalid export: {}", reason),
+            Error::UnsupportedItem(item) => write!(f, "Unsupported item type: {}", item),
+            Error::InvalidHashList(reason) => write!(f, "Invalid breached password list: {}", reason),
+            Error::DuplicateRecord(uuid) => write!(f, "A record with UUID {} already exists", uuid),
+            Error::RecordNotFound(uuid) => write!(f, "No record with UUID {}", uuid),
+            Error::GroupNotFound(group) => write!(f, "No group {}", group),
+            Error::GroupExists(group) => write!(f, "The group {} already exists", group),
+            Error::GroupNotEmpty(group) => write!(f, "The group {} has records", group),
+            Error::DanglingReference(uuid) => write!(f, "The referenced base record {} does not exist", uuid),
+            Error::CyclicReference(uuid) => write!(f, "The references from record {} form a cycle", uuid),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,86 @@
+use std::collections::BTreeMap;
+
+// GroupTree is the hierarchy of groups made from the record groups and the empty groups in the
+// header. Group paths are the names of each level separated by '.', a '.' within a name is escaped
+// as "\.". The root of the tree is the empty path.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GroupTree {
+    // The number of records directly in this group
+    pub entries: usize,
+    pub children: BTreeMap<String, GroupTree>,
+}
+
+impl GroupTree {
+    // new builds the tree from the group of each record and the empty groups
+    pub(super) fn new<'a>(groups: impl Iterator<Item = &'a str>, empty_groups: &[String]) -> GroupTree {
+        let mut tree = GroupTree::default();
+        for group in groups {
+            tree.insert(&split_path(group)).entries += 1;
+        }
+        for group in empty_groups {
+            tree.insert(&split_path(group));
+        }
+        tree
+    }
+
+    fn insert(&mut self, names: &[String]) -> &mut GroupTree {
+        let mut node = self;
+        for name in names {
+            node = node.children.entry(name.clone()).or_default();
+        }
+        node
+    }
+
+    // get returns the group at the path, the empty path is the root
+    pub fn get(&self, path: &str) -> Option<&GroupTree> {
+        let mut node = self;
+        for name in split_path(path) {
+            node = node.children.get(&name)?;
+        }
+        Some(node)
+    }
+
+    // entry_count is the number of records in this group and all groups below it
+    pub fn entry_count(&self) -> usize {
+        self.entries + self.children.values().map(|child| child.entry_count()).sum::<usize>()
+    }
+}
+
+// split_path splits a group path into the name of each level, unescaping "\."
+pub fn split_path(path: &str) -> Vec<String> {
+    if path.is_empty() {
+        return Vec::new()
+    }
+    let mut names = Vec::new();
+    let mut name = String::new();
+    let mut chars = path.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'.') => {
+                name.push('.');
+                chars.next();
+            },
+            '.' => names.push(std::mem::take(&mut name)),
+            _ => name.push(c),
+        }
+    }
+    names.push(name);
+    names
+}
+
+// join_path is the reverse of split_path
+pub fn join_path(names: &[String]) -> String {
+    names.iter().map(|name| name.replace('.', "\\.")).collect::<Vec<String>>().join(".")
+}
+
+// move_path returns the path with the from prefix replaced by to, or None if the path isn't from or
+// a group below it
+pub(super) fn move_path(path: &str, from: &[String], to: &[String]) -> Option<String> {
+    let names = split_path(path);
+    if !names.starts_with(from) {
+        return None
+    }
+    let mut moved = to.to_vec();
+    moved.extend_from_slice(&names[from.len()..]);
+    Some(join_path(&moved))
+}
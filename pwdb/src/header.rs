@@ -1,21 +1,34 @@
+use std::collections::BTreeMap;
+
 use chrono::{DateTime, Utc};
 use hmac::Mac;
+use uuid::Uuid;
+
+use crate::Error;
+use crate::policy::{self, PasswordPolicy};
+
+// The format version written to new databases, 0x030D little endian, see the format spec
+const VERSION: [u8; 2] = [0x0d, 0x03];
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, PartialEq)]
 pub struct Header {
     pub description: String,
-    empty_groups: String,
+    // Groups without records, there is a field for each
+    pub(super) empty_groups: Vec<String>,
     filters: String,
-    last_master_password_update: Option<DateTime<Utc>>,
+    pub(super) last_master_password_update: Option<DateTime<Utc>>,
     pub(super) last_save: Option<DateTime<Utc>>,
-    last_save_by: String,
+    pub(super) last_save_by: String,
     last_save_host: String,
     last_save_user: String,
     pub name: String,
-    password_policy: String,
+    // The named password policies records can refer to
+    pub password_policies: BTreeMap<String, PasswordPolicy>,
     preferences: String,
     recently_used: String,
     tree_display_status: String,
+    // Fields this application doesn't interpret, kept so they are written back unchanged
+    pub(super) unknown_fields: Vec<(u8, Vec<u8>)>,
     uuid: [u8; 16],
     version: [u8; 2],
     // little endian, see the format spec for details
@@ -23,9 +36,40 @@ pub struct Header {
 }
 
 impl Header {
+    // create makes the header for a new database, the last save is set to now.
+    pub(super) fn create(name: &str, description: &str) -> Header {
+        Header {
+            description: description.to_string(),
+            last_save: Some(crate::now()),
+            name: name.to_string(),
+            uuid: *Uuid::new_v4().as_bytes(),
+            version: VERSION,
+            ..Default::default()
+        }
+    }
+
+    // display_fields lists the name and value of each field shown to users, this is used to compare
+    // headers
+    pub(super) fn display_fields(&self) -> Vec<(&'static str, String)> {
+        let date = |date: Option<DateTime<Utc>>| date.map(|d| d.to_rfc3339()).unwrap_or_default();
+        vec![
+            ("name", self.name.clone()),
+            ("description", self.description.clone()),
+            ("last save", date(self.last_save)),
+            ("last save by", self.last_save_by.clone()),
+            ("last save user", self.last_save_user.clone()),
+            ("last save host", self.last_save_host.clone()),
+            ("last password change", date(self.last_master_password_update)),
+            ("empty groups", self.empty_groups.join(", ")),
+            ("password policies", self.password_policies.iter()
+                .map(|(name, policy)| format!("{} {} {}", name, policy, policy.symbols))
+                .collect::<Vec<String>>().join(", ")),
+        ]
+    }
+
     // Parse the header out of the given data return all bytes after the header end field.
     // As the data is parsed out the mac is updated with the string values of the records.
-    pub(super) fn new(bytes: &[u8], mac: &mut crate::HmacSha256) -> Result<(Header, Vec<u8>), String> {
+    pub(super) fn new(bytes: &[u8], mac: &mut crate::HmacSha256) -> Result<(Header, Vec<u8>), Error> {
         let mut hdr = Header::default();
         let mut i = 0;
         while i <= bytes.len() { // Generally the loop should break before this condition is hit
@@ -39,60 +83,40 @@ impl Header {
             i += field.total_size;
             if i + 1 >= bytes.len() {
                 if field.type_id != 0xff {
-                    return Err("Final Header field is not expected end type".to_string())
+                    return Err(Error::Truncated("Final Header field is not expected end type".to_string()))
                 }
                 break
             }
 
             match field.type_id {
-                0x00 => hdr.version = crate::copy_into_array(&*field.data),
+                0x00 => hdr.version = field.array()?,
                 0x01 => {
                     if field.data.len() != 16 {
-                        return Err("UUID field was not 16 bytes long".to_string())
+                        return Err(Error::BadFieldLength { type_id: field.type_id, length: field.data.len() })
                     }
                     hdr.uuid = crate::copy_into_array(&field.data);
                 },
-                0x02 => hdr.preferences = String::from_utf8(
-                    field.data.clone()
-                ).expect("Failed to convert preferences to a string"),
-                0x03 => hdr.tree_display_status = String::from_utf8(
-                    field.data.clone()
-                ).expect("Failed to convert tree display status to a string"),
-                0x04 => hdr.last_save = Some(crate::pwsafe_date(&field.data)?),
-                0x05 => continue, // deprecated field, just drop it
-                0x06 => hdr.last_save_by = String::from_utf8(
-                    field.data.clone()
-                ).expect("Failed to convert last save by field to a string"),
-                0x07 => hdr.last_save_user = String::from_utf8(
-                    field.data.clone()
-                ).expect("Failed to convert last save user field to a string"),
-                0x08 => hdr.last_save_host = String::from_utf8(
-                    field.data.clone()
-                ).expect("Failed to convert last save host field to a string"),
-                0x09 => hdr.name = String::from_utf8(
-                    field.data.clone()
-                ).expect("Failed to convert DB name field to a string"),
-                0x0a => hdr.description = String::from_utf8(
-                    field.data.clone()
-                ).expect("Failed to convert description field to a string"),
-                0x0b => hdr.filters = String::from_utf8(
-                    field.data.clone()
-                ).expect("Failed to convert filters field to a string"),
-                0x0f => hdr.recently_used = String::from_utf8(
-                    field.data.clone()
-                ).expect("Failed to convert recently used field to a string"),
-                0x10 => hdr.password_policy = String::from_utf8(
-                    field.data.clone()
-                ).expect("Failed to convert password policy field to a string"),
-                0x11 => hdr.empty_groups = String::from_utf8(
-                    field.data.clone()
-                ).expect("Failed to convert empty groups field to a string"),
-                0x12 => hdr.yubico = String::from_utf8(
-                    field.data.clone()
-                ).expect("Failed to convert yubico field to a string"),
-                0x13 => hdr.last_master_password_update = Some(crate::pwsafe_date(&field.data)?),
+                0x02 => hdr.preferences = field.string()?,
+                0x03 => hdr.tree_display_status = field.string()?,
+                0x04 => hdr.last_save = Some(field.date()?),
+                0x06 => hdr.last_save_by = field.string()?,
+                0x07 => hdr.last_save_user = field.string()?,
+                0x08 => hdr.last_save_host = field.string()?,
+                0x09 => hdr.name = field.string()?,
+                0x0a => hdr.description = field.string()?,
+                0x0b => hdr.filters = field.string()?,
+                0x0f => hdr.recently_used = field.string()?,
+                // Named policies which can't be parsed are kept as is but not used, as in records
+                0x10 => match field.string().ok().and_then(|value| policy::parse_named(&value).ok()) {
+                    Some(policies) => hdr.password_policies = policies,
+                    None => hdr.unknown_fields.push((field.type_id, field.data.to_vec())),
+                },
+                0x11 => hdr.empty_groups.push(field.string()?),
+                0x12 => hdr.yubico = field.string()?,
+                0x13 => hdr.last_master_password_update = Some(field.date()?),
                 0xff => break,
-                _ => return Err("Unknown header field type".to_string()),
+                // Includes the deprecated 0x05 field which is preserved but otherwise ignored
+                _ => hdr.unknown_fields.push((field.type_id, field.data.to_vec())),
             }
 
             // For every field except end add the data to the hmac
@@ -101,4 +125,41 @@ impl Header {
 
         Ok((hdr, bytes[i..].to_owned()))
     }
-}
\ No newline at end of file
+
+    // to_bytes serializes the header fields followed by the end field, the reverse of new.
+    // As with parsing the mac is updated with the data of each field.
+    pub(super) fn to_bytes(&self, mac: &mut crate::HmacSha256) -> Vec<u8> {
+        let mut writer = crate::FieldWriter::new(mac);
+        // The version must be the first field
+        if self.version == [0, 0] {
+            writer.field(0x00, &VERSION);
+        } else {
+            writer.field(0x00, &self.version);
+        }
+        if self.uuid != [0; 16] {
+            writer.field(0x01, &self.uuid);
+        }
+        writer.string(0x02, &self.preferences);
+        writer.string(0x03, &self.tree_display_status);
+        writer.date(0x04, self.last_save);
+        writer.string(0x06, &self.last_save_by);
+        writer.string(0x07, &self.last_save_user);
+        writer.string(0x08, &self.last_save_host);
+        writer.string(0x09, &self.name);
+        writer.string(0x0a, &self.description);
+        writer.string(0x0b, &self.filters);
+        writer.string(0x0f, &self.recently_used);
+        if !self.password_policies.is_empty() {
+            writer.string(0x10, &policy::format_named(&self.password_policies));
+        }
+        for group in &self.empty_groups {
+            writer.string(0x11, group);
+        }
+        writer.string(0x12, &self.yubico);
+        writer.date(0x13, self.last_master_password_update);
+        for (type_id, data) in &self.unknown_fields {
+            writer.field(*type_id, data);
+        }
+        writer.end()
+    }
+}
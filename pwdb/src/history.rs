@@ -0,0 +1,101 @@
+use chrono::{DateTime, TimeZone, Utc};
+
+use crate::Error;
+use crate::secret::SecretString;
+
+// The record field type holding the password history
+const TYPE_ID: u8 = 0x0f;
+// The entry count is stored as 2 hex digits
+const MAX_STORED_ENTRIES: usize = 0xff;
+
+// PasswordHistory is the list of previous passwords of a record, oldest first.
+// It is stored as "fmmnn" followed by an entry of "TTTTTTTTLLLL" and the password for each previous
+// password. f is 1 when history is kept, mm the max entries, nn the number of entries, T the time_t
+// the password was set and L the length of the password in characters, all in hex.
+#[derive(Clone, Default, Debug, PartialEq)]
+pub struct PasswordHistory {
+    // When false previous passwords are not added to the history
+    pub enabled: bool,
+    pub max_entries: u8,
+    pub entries: Vec<(DateTime<Utc>, SecretString)>,
+}
+
+impl PasswordHistory {
+    // new makes an enabled history which keeps up to max_entries previous passwords
+    pub fn new(max_entries: u8) -> PasswordHistory {
+        PasswordHistory {
+            enabled: true,
+            max_entries,
+            entries: Vec::new(),
+        }
+    }
+
+    // imported keeps all of the previous passwords exported by another password manager
+    pub(super) fn imported(mut entries: Vec<(DateTime<Utc>, String)>) -> PasswordHistory {
+        entries.retain(|(_, password)| !password.is_empty());
+        if entries.is_empty() {
+            return PasswordHistory::default()
+        }
+        entries.sort_by_key(|(time, _)| *time);
+        let mut history = PasswordHistory::new(entries.len().min(MAX_STORED_ENTRIES) as u8);
+        for (time, password) in entries {
+            history.push(time, &password);
+        }
+        history
+    }
+
+    // parse reads the history from the stored string form, an empty string is an empty history
+    pub(super) fn parse(value: &str) -> Result<PasswordHistory, Error> {
+        if value.is_empty() {
+            return Ok(PasswordHistory::default())
+        }
+
+        let mut reader = crate::TextReader::new(value, TYPE_ID);
+        let enabled = match reader.string(1)?.as_str() {
+            "0" => false,
+            "1" => true,
+            flag => return Err(reader.invalid(format!("unexpected enabled flag '{}'", flag))),
+        };
+        let max_entries = reader.hex(2)? as u8;
+        let count = reader.hex(2)?;
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            let time = Utc.timestamp(reader.hex(8)? as i64, 0);
+            let length = reader.hex(4)? as usize;
+            entries.push((time, reader.string(length)?.into()));
+        }
+        reader.end()?;
+
+        Ok(PasswordHistory {
+            enabled,
+            max_entries,
+            entries,
+        })
+    }
+
+    // push adds a previous password set at the given time, dropping the oldest entries beyond
+    // max_entries. Nothing is added when the history is disabled.
+    pub fn push(&mut self, time: DateTime<Utc>, password: &str) {
+        if !self.enabled {
+            return
+        }
+        self.entries.push((time, password.into()));
+        let excess = self.entries.len().saturating_sub(self.max_entries as usize);
+        self.entries.drain(..excess);
+    }
+
+    // to_stored formats the history as it is stored in the record field. Only the newest entries
+    // are written if there are more than the format can count.
+    pub(super) fn to_stored(&self) -> SecretString {
+        let skip = self.entries.len().saturating_sub(MAX_STORED_ENTRIES);
+        let length = 5 + self.entries.iter().skip(skip).map(|(_, password)| 12 + password.len()).sum::<usize>();
+        // Sized up front so the passwords aren't left behind in a reallocated buffer
+        let mut stored = String::with_capacity(length);
+        stored.push_str(&format!("{}{:02x}{:02x}", self.enabled as u8, self.max_entries, self.entries.len() - skip));
+        for (time, password) in self.entries.iter().skip(skip) {
+            stored.push_str(&format!("{:08x}{:04x}", time.timestamp() as u32, password.chars().count()));
+            stored.push_str(password);
+        }
+        stored.into()
+    }
+}
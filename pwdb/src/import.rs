@@ -0,0 +1,42 @@
+use uuid::Uuid;
+
+use crate::{Database, Error};
+use crate::policy;
+use crate::record::Record;
+
+// ImportReport describes the result of importing another password manager's export or a CSV file
+#[derive(Debug, Default, PartialEq)]
+pub struct ImportReport {
+    pub added: Vec<Uuid>,
+    // The existing records updated by a CSV import which replaces duplicates
+    pub replaced: Vec<Uuid>,
+    // Items which couldn't be imported with the reason. Exported items are named by their group path
+    // and title, CSV rows by their number counting from 1 and including the header.
+    pub skipped: Vec<(String, Error)>,
+    // The added records whose item had no password, they were given one generated with their policy
+    pub generated: Vec<Uuid>,
+}
+
+impl Database {
+    // add_imported adds a record read from another password manager's export. Every record needs a
+    // password so one is generated for items without, such as notes and cards. The record is given
+    // a new UUID if it has none or its own is already used.
+    pub(super) fn add_imported(&mut self, mut record: Record, report: &mut ImportReport) -> Result<(), Error> {
+        let generated = record.password.is_empty();
+        if generated {
+            record.password = policy::generate(&self.password_policy(&record))?.into();
+        }
+        record.validate()?;
+        if record.uuid.is_nil() || self.records.contains_key(&record.uuid) {
+            record.uuid = Uuid::new_v4();
+        }
+        self.remove_empty_groups(&record.group);
+        let uuid = record.uuid;
+        self.records.insert(uuid, record);
+        report.added.push(uuid);
+        if generated {
+            report.generated.push(uuid);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,228 @@
+use crate::Error;
+
+// Value is a parsed JSON value, object members are kept in document order
+#[derive(Clone, Debug, PartialEq)]
+pub(super) enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+const NULL: Value = Value::Null;
+
+impl Value {
+    // get is the member with the key, null if this isn't an object or there is no such member
+    pub(super) fn get(&self, key: &str) -> &Value {
+        match self {
+            Value::Object(members) => members.iter().find(|(name, _)| name == key).map_or(&NULL, |(_, value)| value),
+            _ => &NULL,
+        }
+    }
+
+    // str is the string value, empty for anything other than a string
+    pub(super) fn str(&self) -> &str {
+        match self {
+            Value::String(value) => value,
+            _ => "",
+        }
+    }
+
+    // array is the elements, empty for anything other than an array
+    pub(super) fn array(&self) -> &[Value] {
+        match self {
+            Value::Array(values) => values,
+            _ => &[],
+        }
+    }
+
+    pub(super) fn number(&self) -> Option<f64> {
+        match self {
+            Value::Number(number) => Some(*number),
+            _ => None,
+        }
+    }
+}
+
+// parse reads a JSON document as specified by RFC 8259
+pub(super) fn parse(data: &str) -> Result<Value, Error> {
+    // Windows tools sometimes write a byte order mark
+    let data = data.strip_prefix('\u{feff}').unwrap_or(data);
+    let mut parser = Parser { data, pos: 0 };
+    let value = parser.value(0)?;
+    parser.skip_whitespace();
+    if parser.pos != data.len() {
+        return Err(parser.error("there is content after the value"))
+    }
+    Ok(value)
+}
+
+// Nesting deeper than this is rejected rather than risking the stack
+const MAX_DEPTH: usize = 128;
+
+struct Parser<'a> {
+    data: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.data[self.pos..]
+    }
+
+    fn error(&self, reason: &str) -> Error {
+        Error::InvalidJson(format!("{} at byte {}", reason, self.pos))
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start_matches(|c| " \t\r\n".contains(c)).len();
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), Error> {
+        self.skip_whitespace();
+        if !self.rest().starts_with(expected) {
+            return Err(self.error(&format!("{} is expected", expected)))
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    // next skips whitespace then consumes the character if it is the one given
+    fn next(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        if self.rest().starts_with(c) {
+            self.pos += 1;
+            return true
+        }
+        false
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, Error> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("the values are nested too deeply"))
+        }
+        self.skip_whitespace();
+        let rest = self.rest();
+        for (literal, value) in &[("null", Value::Null), ("true", Value::Bool(true)), ("false", Value::Bool(false))] {
+            if rest.starts_with(literal) {
+                self.pos += literal.len();
+                return Ok(value.clone())
+            }
+        }
+        match rest.chars().next() {
+            Some('"') => Ok(Value::String(self.string()?)),
+            Some('[') => {
+                self.pos += 1;
+                let mut values = Vec::new();
+                if self.next(']') {
+                    return Ok(Value::Array(values))
+                }
+                loop {
+                    values.push(self.value(depth + 1)?);
+                    if self.next(']') {
+                        return Ok(Value::Array(values))
+                    }
+                    self.expect(',')?;
+                }
+            },
+            Some('{') => {
+                self.pos += 1;
+                let mut members = Vec::new();
+                if self.next('}') {
+                    return Ok(Value::Object(members))
+                }
+                loop {
+                    self.skip_whitespace();
+                    let name = self.string()?;
+                    self.expect(':')?;
+                    members.push((name, self.value(depth + 1)?));
+                    if self.next('}') {
+                        return Ok(Value::Object(members))
+                    }
+                    self.expect(',')?;
+                }
+            },
+            Some(c) if c == '-' || c.is_ascii_digit() => {
+                let end = rest.find(|c: char| !(c.is_ascii_digit() || "+-.eE".contains(c))).unwrap_or(rest.len());
+                let number = rest[..end].parse().map_err(|_| self.error("a number is invalid"))?;
+                self.pos += end;
+                Ok(Value::Number(number))
+            },
+            Some(_) => Err(self.error("a value is expected")),
+            None => Err(self.error("the data ended before a value")),
+        }
+    }
+
+    // string parses a string starting at its opening quote
+    fn string(&mut self) -> Result<String, Error> {
+        if !self.rest().starts_with('"') {
+            return Err(self.error("\" is expected"))
+        }
+        self.pos += 1;
+        let mut value = String::new();
+        loop {
+            let rest = self.rest();
+            let end = rest.find(|c| c == '"' || c == '\\' || c < ' ').ok_or_else(|| self.error("a string is not closed"))?;
+            value.push_str(&rest[..end]);
+            self.pos += end;
+            match self.rest().chars().next() {
+                Some('"') => {
+                    self.pos += 1;
+                    return Ok(value)
+                },
+                Some('\\') => {
+                    self.pos += 1;
+                    value.push(self.escape()?);
+                },
+                _ => return Err(self.error("a control character is not escaped")),
+            }
+        }
+    }
+
+    // escape parses the escape sequence following a backslash
+    fn escape(&mut self) -> Result<char, Error> {
+        let c = self.rest().chars().next().ok_or_else(|| self.error("an escape is not complete"))?;
+        self.pos += 1;
+        Ok(match c {
+            '"' => '"',
+            '\\' => '\\',
+            '/' => '/',
+            'b' => '\u{8}',
+            'f' => '\u{c}',
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            'u' => {
+                let high = self.code_unit()?;
+                if !(0xd800..0xdc00).contains(&high) {
+                    return char::from_u32(high).ok_or_else(|| self.error("an escape is not a character"))
+                }
+                // Characters outside the basic plane are escaped as a UTF-16 surrogate pair
+                if !self.rest().starts_with("\\u") {
+                    return Err(self.error("a surrogate pair is not complete"))
+                }
+                self.pos += 2;
+                let low = self.code_unit()?;
+                if !(0xdc00..0xe000).contains(&low) {
+                    return Err(self.error("a surrogate pair is invalid"))
+                }
+                char::from_u32(0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00))
+                    .ok_or_else(|| self.error("an escape is not a character"))?
+            },
+            _ => return Err(self.error("an escape is invalid")),
+        })
+    }
+
+    fn code_unit(&mut self) -> Result<u32, Error> {
+        let digits = self.rest().get(..4).ok_or_else(|| self.error("an escape is not complete"))?;
+        if !digits.bytes().all(|c| c.is_ascii_hexdigit()) {
+            return Err(self.error("an escape is not hex"))
+        }
+        let unit = u32::from_str_radix(digits, 16).expect("hex digits");
+        self.pos += 4;
+        Ok(unit)
+    }
+}
@@ -0,0 +1,653 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::io::Read;
+
+use aes::{Aes256, BlockCipher, NewBlockCipher};
+use block_modes::{BlockMode, Cbc};
+use block_modes::block_padding::Pkcs7;
+use chacha20::ChaCha20;
+use chacha20::cipher::{NewStreamCipher, SyncStreamCipher};
+use chrono::{DateTime, TimeZone, Utc};
+use flate2::read::GzDecoder;
+use generic_array::GenericArray;
+use hmac::{Mac, NewMac};
+use salsa20::Salsa20;
+use sha2::{Digest, Sha256, Sha512};
+use twofish::Twofish;
+use uuid::Uuid;
+
+use crate::{Database, Error, HmacSha256};
+use crate::group;
+use crate::history::PasswordHistory;
+use crate::import::ImportReport;
+use crate::otp::{self, Algorithm, Totp};
+use crate::record::Record;
+use crate::xml::{self, Element};
+
+const SIGNATURE: [u8; 8] = [0x03, 0xd9, 0xa2, 0x9a, 0x67, 0xfb, 0x4b, 0xb5];
+
+// The cipher and key derivation function UUIDs, see the KeePass source for these
+const AES256_CIPHER: [u8; 16] = [0x31, 0xc1, 0xf2, 0xe6, 0xbf, 0x71, 0x43, 0x50, 0xbe, 0x58, 0x05, 0x21, 0x6a, 0xfc, 0x5a, 0xff];
+const CHACHA20_CIPHER: [u8; 16] = [0xd6, 0x03, 0x8a, 0x2b, 0x8b, 0x6f, 0x4c, 0xb5, 0xa5, 0x24, 0x33, 0x9a, 0x31, 0xdb, 0xb5, 0x9a];
+const TWOFISH_CIPHER: [u8; 16] = [0xad, 0x68, 0xf2, 0x9f, 0x57, 0x6f, 0x4b, 0xb9, 0xa3, 0x6a, 0xd4, 0x7a, 0xf9, 0x65, 0x34, 0x6c];
+const AES_KDF: [u8; 16] = [0xc9, 0xd9, 0xf3, 0x9a, 0x62, 0x8a, 0x44, 0x60, 0xbf, 0x74, 0x0d, 0x08, 0xc1, 0x8a, 0x4f, 0xea];
+const ARGON2D_KDF: [u8; 16] = [0xef, 0x63, 0x6d, 0xdf, 0x8c, 0x29, 0x44, 0x4b, 0x91, 0xf7, 0xa9, 0xa4, 0x03, 0xe3, 0x0a, 0x0c];
+const ARGON2ID_KDF: [u8; 16] = [0x9e, 0x29, 0x8b, 0x19, 0x56, 0xdb, 0x47, 0x73, 0xb2, 0x3d, 0xfc, 0x3e, 0xc6, 0xf0, 0xa1, 0xe6];
+
+// The fixed nonce of the Salsa20 stream protecting values in KDBX 3 files
+const SALSA20_NONCE: [u8; 8] = [0xe8, 0x30, 0x09, 0x4b, 0x97, 0x20, 0x5d, 0x2a];
+
+// Limits on the key derivation work a file can ask for. They are far above what KeePass sets but
+// stop a hostile file from taking all the memory or running for days.
+const MAX_AES_ROUNDS: u64 = 1_000_000_000;
+const MAX_ARGON2_MEMORY: u64 = 1 << 30;
+const MAX_ARGON2_ITERATIONS: u64 = 1_000;
+const MAX_ARGON2_PARALLELISM: u32 = 256;
+
+// The most decompressed XML a file can expand to, a small gzip payload can otherwise inflate without bound
+const MAX_XML_BYTES: u64 = 1 << 30;
+
+type Aes256Cbc = Cbc<Aes256, Pkcs7>;
+type TwofishCbc = Cbc<Twofish, Pkcs7>;
+
+// Seconds from 0001-01-01, the epoch of KDBX 4 times, to the Unix epoch
+const KDBX_EPOCH_OFFSET: i64 = 62_135_596_800;
+
+impl Database {
+    // import_kdbx adds the entries of a KeePass KDBX 3.1 or 4 database. The key file is optional,
+    // with a key file and an empty password only the key file is used.
+    // Groups keep their hierarchy below the root group, groups without entries become empty groups
+    // and the recycle bin is left out. Entry strings other than the standard fields are added to
+    // the notes, except for an Email string and the TOTP settings of KeePass or KeePassXC which
+    // map to those fields when they use SHA1, the only algorithm pwsafe supports. The password history is taken from the entry history. Attachments
+    // aren't imported. Entries without a password are given a generated one.
+    pub fn import_kdbx(&mut self, data: &[u8], password: &str, key_file: Option<&[u8]>) -> Result<ImportReport, Error> {
+        let (xml, mut stream) = decrypt(data, password, key_file)?;
+        let xml = String::from_utf8(xml).map_err(|_| invalid("the XML is not UTF-8"))?;
+        let mut root = xml::parse(&xml)?;
+        unprotect(&mut root, &mut stream)?;
+
+        let meta = root.child("Meta").ok_or_else(|| invalid("there is no Meta element"))?;
+        let importer = Importer {
+            recycle_bin: meta.text("RecycleBinUUID").trim().to_string(),
+            history_max: meta.child("HistoryMaxItems")
+                .and_then(|max| max.text.trim().parse::<i32>().ok())
+                // KeePass keeps 10 by default, a negative maximum is unlimited
+                .map_or(10, |max| if max < 0 { 255 } else { max.min(255) as u8 }),
+        };
+        let group = root.child("Root").and_then(|root| root.child("Group"))
+            .ok_or_else(|| invalid("there is no root group"))?;
+        let mut report = ImportReport::default();
+        let mut leaf_groups = Vec::new();
+        importer.group(self, group, &[], &mut report, &mut leaf_groups);
+
+        let tree = self.group_tree();
+        for path in leaf_groups {
+            if tree.get(&path).is_none() {
+                self.header.empty_groups.push(path);
+            }
+        }
+        self.modified();
+        Ok(report)
+    }
+}
+
+struct Importer {
+    recycle_bin: String,
+    history_max: u8,
+}
+
+impl Importer {
+    // group imports the entries of a group and its subgroups, collecting the paths of groups
+    // without subgroups
+    fn group(&self, db: &mut Database, group: &Element, path: &[String], report: &mut ImportReport, leaf_groups: &mut Vec<String>) {
+        let group_path = group::join_path(path);
+        for entry in group.children("Entry") {
+            match self.entry(entry, &group_path).and_then(|record| db.add_imported(record, report)) {
+                Ok(()) => (),
+                Err(err) => {
+                    let mut names = path.to_vec();
+                    names.push(strings(entry).remove("Title").unwrap_or_default());
+                    report.skipped.push((group::join_path(&names), err));
+                },
+            }
+        }
+
+        let mut subgroups = group.children("Group")
+            .filter(|subgroup| self.recycle_bin.is_empty() || subgroup.text("UUID").trim() != self.recycle_bin)
+            .peekable();
+        if subgroups.peek().is_none() && !path.is_empty() {
+            leaf_groups.push(group_path);
+        }
+        for subgroup in subgroups {
+            let mut names = path.to_vec();
+            names.push(subgroup.text("Name").to_string());
+            self.group(db, subgroup, &names, report, leaf_groups);
+        }
+    }
+
+    fn entry(&self, entry: &Element, group: &str) -> Result<Record, Error> {
+        let mut values = strings(entry);
+        let mut record = Record {
+            group: group.to_string(),
+            title: values.remove("Title").unwrap_or_default(),
+            username: values.remove("UserName").unwrap_or_default(),
+            password: values.remove("Password").unwrap_or_default().into(),
+            url: values.remove("URL").unwrap_or_default(),
+            notes: values.remove("Notes").unwrap_or_default(),
+            ..Default::default()
+        };
+        if record.title.is_empty() {
+            record.title = record.url.clone();
+        }
+        if let Some(uuid) = entry.child("UUID") {
+            record.uuid = Uuid::from_slice(&base64_decode(&uuid.text)?).map_err(|_| invalid("an entry UUID is not 16 bytes"))?;
+        }
+        if let Some(times) = entry.child("Times") {
+            record.create_time = time(times.text("CreationTime"));
+            record.mod_time = time(times.text("LastModificationTime"));
+            record.access_time = time(times.text("LastAccessTime"));
+            if times.text("Expires").trim().eq_ignore_ascii_case("true") {
+                record.password_expiry_time = time(times.text("ExpiryTime"));
+            }
+        }
+
+        // The history holds the earlier versions of the whole entry, the password history is made
+        // from the versions where the password changed. Each password is dated from the first
+        // version with it, as the record password modification time is.
+        let mut passwords: Vec<(Option<DateTime<Utc>>, String)> = Vec::new();
+        let versions = entry.child("History").into_iter().flat_map(|history| history.children("Entry"))
+            .map(|version| (version.child("Times").and_then(|times| time(times.text("LastModificationTime"))),
+                            strings(version).remove("Password").unwrap_or_default()))
+            .chain(std::iter::once((record.mod_time, record.password.to_string())));
+        for (modified, password) in versions {
+            if passwords.last().map(|(_, last)| last) != Some(&password) {
+                passwords.push((modified, password));
+            }
+        }
+        record.password_mod_time = passwords.pop().and_then(|(modified, _)| modified).or(record.create_time);
+        if self.history_max > 0 {
+            record.password_history = PasswordHistory::new(self.history_max);
+            for (modified, password) in passwords.iter().filter(|(_, password)| !password.is_empty()) {
+                record.password_history.push(modified.or(record.create_time).unwrap_or_else(crate::now), password);
+            }
+        }
+
+        if let Some(email) = values.keys().find(|key| key.eq_ignore_ascii_case("email")).cloned() {
+            record.email = values.remove(&email).unwrap_or_default();
+        }
+        totp(&mut record, &mut values);
+
+        // The remaining custom strings are kept in the notes in the order of the entry
+        let custom: Vec<String> = entry.children("String")
+            .map(|string| string.text("Key"))
+            .filter_map(|key| values.get(key).map(|value| format!("{}: {}", key, value)))
+            .collect();
+        record.append_notes(&custom);
+        Ok(record)
+    }
+}
+
+// strings maps the keys to the values of an entry's strings
+fn strings(entry: &Element) -> HashMap<String, String> {
+    entry.children("String")
+        .map(|string| (string.text("Key").to_string(), string.text("Value").to_string()))
+        .collect()
+}
+
+// totp sets the two factor key from either the otp string of KeePassXC, when it is an otpauth URI,
+// or the TimeOtp strings of KeePass. The strings used are removed, if they can't be parsed they are
+// left to be kept in the notes.
+fn totp(record: &mut Record, strings: &mut HashMap<String, String>) {
+    if let Some(uri) = strings.get("otp") {
+        if record.set_otpauth_uri(uri).is_ok() {
+            strings.remove("otp");
+            return
+        }
+    }
+
+    let secret = strings.get("TimeOtp-Secret-Base32").and_then(|secret| otp::base32_decode(secret))
+        .or_else(|| strings.get("TimeOtp-Secret-Hex").and_then(|secret| hex_decode(secret)))
+        .or_else(|| strings.get("TimeOtp-Secret-Base64").and_then(|secret| base64_decode(secret).ok()))
+        .or_else(|| strings.get("TimeOtp-Secret").map(|secret| secret.as_bytes().to_vec()));
+    let key = match secret {
+        Some(key) if !key.is_empty() => key,
+        _ => return,
+    };
+    let mut totp = Totp::new(&key);
+    let number = |name: &str, default: u8| strings.get(name).map_or(Some(default), |value| value.trim().parse().ok());
+    let algorithm = match strings.get("TimeOtp-Algorithm").map(|algorithm| algorithm.as_str()) {
+        None | Some("HMAC-SHA-1") => Some(Algorithm::Sha1),
+        Some("HMAC-SHA-256") => Some(Algorithm::Sha256),
+        Some("HMAC-SHA-512") => Some(Algorithm::Sha512),
+        Some(_) => None,
+    };
+    match (number("TimeOtp-Length", otp::DEFAULT_DIGITS), number("TimeOtp-Period", otp::DEFAULT_PERIOD), algorithm) {
+        (Some(digits), Some(period), Some(algorithm)) => {
+            totp.digits = digits;
+            totp.period = period;
+            totp.algorithm = algorithm;
+        },
+        _ => return,
+    }
+    if totp.validate().is_ok() {
+        record.totp = Some(totp);
+        strings.retain(|key, _| !key.starts_with("TimeOtp-"));
+    }
+}
+
+// time parses a KDBX 3 ISO 8601 time or a KDBX 4 base64 encoded count of seconds since year 1,
+// invalid times are treated as unset
+fn time(value: &str) -> Option<DateTime<Utc>> {
+    let value = value.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Some(time.with_timezone(&Utc))
+    }
+    let seconds = i64::from_le_bytes(base64_decode(value).ok()?.as_slice().try_into().ok()?);
+    Utc.timestamp_opt(seconds.checked_sub(KDBX_EPOCH_OFFSET)?, 0).single()
+}
+
+// unprotect decrypts the values marked as protected, which are encrypted by the inner stream in the
+// order they occur in the document
+fn unprotect(element: &mut Element, stream: &mut InnerStream) -> Result<(), Error> {
+    if element.attribute("Protected") == Some("True") {
+        let mut value = base64_decode(&element.text)?;
+        stream.apply(&mut value);
+        element.text = String::from_utf8(value).map_err(|_| invalid("a protected value is not UTF-8"))?;
+    }
+    for child in &mut element.children {
+        unprotect(child, stream)?;
+    }
+    Ok(())
+}
+
+// decrypt returns the XML document of a KDBX file along with the stream for its protected values
+fn decrypt(data: &[u8], password: &str, key_file: Option<&[u8]>) -> Result<(Vec<u8>, InnerStream), Error> {
+    let mut bytes = Bytes { data, pos: 0 };
+    if bytes.take(8)? != SIGNATURE {
+        return Err(invalid("this is not a KeePass database"))
+    }
+    let _minor = bytes.u16()?;
+    let major = bytes.u16()?;
+    if major != 3 && major != 4 {
+        return Err(invalid(&format!("KDBX version {} is not supported", major)))
+    }
+
+    let header = OuterHeader::read(&mut bytes, major)?;
+    let header_bytes = &data[..bytes.pos];
+    let transformed = header.kdf.transform(&composite_key(password, key_file)?)?;
+    let key = Sha256::new().chain(&header.master_seed).chain(&transformed).finalize();
+
+    if major == 3 {
+        let plain = header.decrypt(&key, bytes.rest()).map_err(|_| Error::WrongPassword)?;
+        if plain.len() < 32 || plain[..32] != header.stream_start[..] {
+            return Err(Error::WrongPassword)
+        }
+        let xml = header.decompress(hashed_blocks(&plain[32..])?)?;
+        let stream = InnerStream::new(header.inner_stream, &header.inner_stream_key)?;
+        return Ok((xml, stream))
+    }
+
+    if bytes.take(32)? != &Sha256::digest(header_bytes)[..] {
+        return Err(invalid("the header is corrupt"))
+    }
+    let hmac_key = Sha512::new().chain(&header.master_seed).chain(&transformed).chain([1u8]).finalize();
+    let mut mac = HmacSha256::new_varkey(&block_key(u64::MAX, &hmac_key)).expect("Invalid hmac");
+    mac.update(header_bytes);
+    if mac.verify(bytes.take(32)?).is_err() {
+        return Err(Error::WrongPassword)
+    }
+    let encrypted = hmac_blocks(&mut bytes, &hmac_key)?;
+    let plain = header.decompress(header.decrypt(&key, &encrypted)?)?;
+
+    // The inner header holds the protected value stream settings and the attachments
+    let mut inner = Bytes { data: &plain, pos: 0 };
+    let (mut stream_id, mut stream_key) = (0, Vec::new());
+    loop {
+        let id = inner.u8()?;
+        let size = inner.u32()? as usize;
+        let value = inner.take(size)?;
+        match id {
+            0 => break,
+            1 => stream_id = le_u32(value)?,
+            2 => stream_key = value.to_vec(),
+            _ => (),
+        }
+    }
+    Ok((inner.rest().to_vec(), InnerStream::new(stream_id, &stream_key)?))
+}
+
+// composite_key combines the SHA-256 hashes of the password and key file as KeePass does
+fn composite_key(password: &str, key_file: Option<&[u8]>) -> Result<Vec<u8>, Error> {
+    let mut hasher = Sha256::new();
+    if !password.is_empty() || key_file.is_none() {
+        hasher.update(Sha256::digest(password.as_bytes()));
+    }
+    if let Some(key_file) = key_file {
+        hasher.update(key_file_key(key_file)?);
+    }
+    Ok(hasher.finalize().to_vec())
+}
+
+// key_file_key reads the key from a KeePass XML key file, version 1 holds it as base64 and
+// version 2 as hex. Other files are a raw 32 byte key, 64 hex digits or else any data which is
+// hashed.
+fn key_file_key(file: &[u8]) -> Result<Vec<u8>, Error> {
+    if let Some(root) = std::str::from_utf8(file).ok().and_then(|text| xml::parse(text).ok()) {
+        if root.name == "KeyFile" {
+            let data = root.child("Key").map(|key| key.text("Data")).unwrap_or_default();
+            let key = if root.child("Meta").map(|meta| meta.text("Version")).unwrap_or_default().starts_with("2.") {
+                hex_decode(data)
+            } else {
+                base64_decode(data).ok()
+            };
+            return key.filter(|key| key.len() == 32).ok_or_else(|| invalid("the key file is invalid"))
+        }
+    }
+    if file.len() == 32 {
+        return Ok(file.to_vec())
+    }
+    if file.len() == 64 {
+        if let Some(key) = std::str::from_utf8(file).ok().and_then(hex_decode) {
+            return Ok(key)
+        }
+    }
+    Ok(Sha256::digest(file).to_vec())
+}
+
+// hashed_blocks joins the KDBX 3 payload blocks, each has an index, SHA-256 hash and size
+fn hashed_blocks(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut bytes = Bytes { data, pos: 0 };
+    let mut joined = Vec::new();
+    loop {
+        let _index = bytes.u32()?;
+        let hash = bytes.take(32)?;
+        let size = bytes.u32()? as usize;
+        if size == 0 {
+            return Ok(joined)
+        }
+        let block = bytes.take(size)?;
+        if hash != &Sha256::digest(block)[..] {
+            return Err(invalid("a block hash doesn't match"))
+        }
+        joined.extend_from_slice(block);
+    }
+}
+
+// hmac_blocks joins the KDBX 4 payload blocks, each has an HMAC of its index, size and data
+fn hmac_blocks(bytes: &mut Bytes, hmac_key: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut joined = Vec::new();
+    for index in 0u64.. {
+        let hmac = bytes.take(32)?;
+        let size = bytes.take(4)?;
+        let block = bytes.take(le_u32(size)? as usize)?;
+        let mut mac = HmacSha256::new_varkey(&block_key(index, hmac_key)).expect("Invalid hmac");
+        mac.update(&index.to_le_bytes());
+        mac.update(size);
+        mac.update(block);
+        if mac.verify(hmac).is_err() {
+            return Err(Error::HmacMismatch)
+        }
+        if block.is_empty() {
+            break
+        }
+        joined.extend_from_slice(block);
+    }
+    Ok(joined)
+}
+
+fn block_key(index: u64, hmac_key: &[u8]) -> Vec<u8> {
+    Sha512::new().chain(index.to_le_bytes()).chain(hmac_key).finalize().to_vec()
+}
+
+// OuterHeader holds the unencrypted header fields, only those needed to decrypt are kept
+struct OuterHeader {
+    cipher: [u8; 16],
+    compressed: bool,
+    master_seed: Vec<u8>,
+    iv: Vec<u8>,
+    kdf: Kdf,
+    // The KDBX 3 fields, in KDBX 4 the stream settings are in the encrypted inner header
+    stream_start: Vec<u8>,
+    inner_stream: u32,
+    inner_stream_key: Vec<u8>,
+}
+
+impl OuterHeader {
+    fn read(bytes: &mut Bytes, major: u16) -> Result<OuterHeader, Error> {
+        let mut header = OuterHeader {
+            cipher: [0; 16],
+            compressed: false,
+            master_seed: Vec::new(),
+            iv: Vec::new(),
+            kdf: Kdf::Aes { seed: Vec::new(), rounds: 0 },
+            stream_start: Vec::new(),
+            inner_stream: 0,
+            inner_stream_key: Vec::new(),
+        };
+        loop {
+            let id = bytes.u8()?;
+            let size = if major == 3 { bytes.u16()? as usize } else { bytes.u32()? as usize };
+            let value = bytes.take(size)?;
+            match id {
+                0 => break,
+                2 => header.cipher = value.try_into().map_err(|_| invalid("the cipher ID is not 16 bytes"))?,
+                3 => header.compressed = le_u32(value)? == 1,
+                4 => header.master_seed = value.to_vec(),
+                5 => if let Kdf::Aes { seed, .. } = &mut header.kdf { *seed = value.to_vec() },
+                6 => if let Kdf::Aes { rounds, .. } = &mut header.kdf { *rounds = le_u64(value)? },
+                7 => header.iv = value.to_vec(),
+                8 => header.inner_stream_key = value.to_vec(),
+                9 => header.stream_start = value.to_vec(),
+                10 => header.inner_stream = le_u32(value)?,
+                11 => header.kdf = Kdf::read(value)?,
+                _ => (),
+            }
+        }
+        if header.master_seed.len() != 32 {
+            return Err(invalid("the master seed is not 32 bytes"))
+        }
+        Ok(header)
+    }
+
+    // decrypt decrypts the payload, the block ciphers are CBC mode with PKCS7 padding
+    fn decrypt(&self, key: &[u8], data: &[u8]) -> Result<Vec<u8>, Error> {
+        match self.cipher {
+            AES256_CIPHER => {
+                let cipher = Aes256Cbc::new_var(key, &self.iv).map_err(|_| invalid("the AES IV is not 16 bytes"))?;
+                cipher.decrypt_vec(data).map_err(|_| invalid("the AES data length or padding is wrong"))
+            },
+            TWOFISH_CIPHER => {
+                let cipher = TwofishCbc::new_var(key, &self.iv).map_err(|_| invalid("the Twofish IV is not 16 bytes"))?;
+                cipher.decrypt_vec(data).map_err(|_| invalid("the Twofish data length or padding is wrong"))
+            },
+            CHACHA20_CIPHER => {
+                let mut cipher = ChaCha20::new_var(key, &self.iv).map_err(|_| invalid("the ChaCha20 IV is not 12 bytes"))?;
+                let mut plain = data.to_vec();
+                cipher.apply_keystream(&mut plain);
+                Ok(plain)
+            },
+            _ => Err(invalid("the cipher is not supported")),
+        }
+    }
+
+    fn decompress(&self, data: Vec<u8>) -> Result<Vec<u8>, Error> {
+        if !self.compressed {
+            return Ok(data)
+        }
+        let mut decompressed = Vec::new();
+        GzDecoder::new(&data[..]).take(MAX_XML_BYTES + 1).read_to_end(&mut decompressed)
+            .map_err(|err| invalid(&format!("gzip {}", err)))?;
+        if decompressed.len() as u64 > MAX_XML_BYTES {
+            return Err(invalid(&format!("the XML is over the limit of {} bytes", MAX_XML_BYTES)))
+        }
+        Ok(decompressed)
+    }
+}
+
+// Kdf is the key derivation function which transforms the composite key
+enum Kdf {
+    Aes { seed: Vec<u8>, rounds: u64 },
+    Argon2 { algorithm: argon2::Algorithm, version: argon2::Version, salt: Vec<u8>, memory: u64, iterations: u64, parallelism: u32 },
+}
+
+impl Kdf {
+    // read parses the KDBX 4 parameters, a variant dictionary of typed values
+    fn read(data: &[u8]) -> Result<Kdf, Error> {
+        let mut bytes = Bytes { data, pos: 0 };
+        let _version = bytes.u16()?;
+        let mut parameters = HashMap::new();
+        loop {
+            if bytes.u8()? == 0 {
+                break
+            }
+            let name_size = bytes.u32()? as usize;
+            let name = String::from_utf8_lossy(bytes.take(name_size)?).to_string();
+            let size = bytes.u32()? as usize;
+            parameters.insert(name, bytes.take(size)?);
+        }
+        let parameter = |name: &str| parameters.get(name).copied().ok_or_else(|| invalid(&format!("the KDF parameter {} is missing", name)));
+
+        let algorithm = match parameter("$UUID")? {
+            uuid if uuid == AES_KDF => return Ok(Kdf::Aes { seed: parameter("S")?.to_vec(), rounds: le_u64(parameter("R")?)? }),
+            uuid if uuid == ARGON2D_KDF => argon2::Algorithm::Argon2d,
+            uuid if uuid == ARGON2ID_KDF => argon2::Algorithm::Argon2id,
+            _ => return Err(invalid("the key derivation function is not supported")),
+        };
+        if parameters.contains_key("K") || parameters.contains_key("A") {
+            return Err(invalid("Argon2 secret keys and associated data are not supported"))
+        }
+        Ok(Kdf::Argon2 {
+            algorithm,
+            version: match le_u32(parameter("V")?)? {
+                0x10 => argon2::Version::V0x10,
+                0x13 => argon2::Version::V0x13,
+                _ => return Err(invalid("the Argon2 version is not supported")),
+            },
+            salt: parameter("S")?.to_vec(),
+            memory: le_u64(parameter("M")?)?,
+            iterations: le_u64(parameter("I")?)?,
+            parallelism: le_u32(parameter("P")?)?,
+        })
+    }
+
+    fn transform(&self, key: &[u8]) -> Result<Vec<u8>, Error> {
+        match self {
+            Kdf::Aes { seed, rounds } => {
+                if seed.len() != 32 {
+                    return Err(invalid("the AES KDF seed is not 32 bytes"))
+                }
+                if *rounds > MAX_AES_ROUNDS {
+                    return Err(invalid(&format!("{} AES KDF rounds is over the limit of {}", rounds, MAX_AES_ROUNDS)))
+                }
+                let cipher = Aes256::new(GenericArray::from_slice(seed));
+                let mut key = key.to_vec();
+                for _ in 0..*rounds {
+                    for block in key.chunks_exact_mut(16) {
+                        cipher.encrypt_block(GenericArray::from_mut_slice(block));
+                    }
+                }
+                Ok(Sha256::digest(&key).to_vec())
+            },
+            Kdf::Argon2 { algorithm, version, salt, memory, iterations, parallelism } => {
+                if *memory > MAX_ARGON2_MEMORY || *iterations > MAX_ARGON2_ITERATIONS || *parallelism > MAX_ARGON2_PARALLELISM {
+                    return Err(invalid(&format!("the Argon2 parameters of {} bytes, {} iterations and {} lanes are over the limits of {}, {} and {}",
+                                                memory, iterations, parallelism, MAX_ARGON2_MEMORY, MAX_ARGON2_ITERATIONS, MAX_ARGON2_PARALLELISM)))
+                }
+                let params = argon2::Params::new((memory / 1024).try_into().unwrap_or(u32::MAX),
+                                                 (*iterations).try_into().unwrap_or(u32::MAX), *parallelism, Some(32))
+                    .map_err(|err| invalid(&format!("the Argon2 parameters are invalid, {}", err)))?;
+                let mut transformed = vec![0; 32];
+                argon2::Argon2::new(*algorithm, *version, params).hash_password_into(key, salt, &mut transformed)
+                    .map_err(|err| invalid(&format!("Argon2 failed, {}", err)))?;
+                Ok(transformed)
+            },
+        }
+    }
+}
+
+// InnerStream is the stream cipher protecting values such as passwords within the XML
+enum InnerStream {
+    None,
+    Salsa20(Salsa20),
+    ChaCha20(ChaCha20),
+}
+
+impl InnerStream {
+    fn new(id: u32, key: &[u8]) -> Result<InnerStream, Error> {
+        match id {
+            0 => Ok(InnerStream::None),
+            2 => Ok(InnerStream::Salsa20(Salsa20::new(GenericArray::from_slice(&Sha256::digest(key)),
+                                                      GenericArray::from_slice(&SALSA20_NONCE)))),
+            3 => {
+                let hash = Sha512::digest(key);
+                Ok(InnerStream::ChaCha20(ChaCha20::new(GenericArray::from_slice(&hash[..32]), GenericArray::from_slice(&hash[32..44]))))
+            },
+            _ => Err(invalid("the protected value stream is not supported")),
+        }
+    }
+
+    fn apply(&mut self, data: &mut [u8]) {
+        match self {
+            InnerStream::None => (),
+            InnerStream::Salsa20(cipher) => cipher.apply_keystream(data),
+            InnerStream::ChaCha20(cipher) => cipher.apply_keystream(data),
+        }
+    }
+}
+
+// Bytes reads little endian values, running past the end is an error
+struct Bytes<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Bytes<'a> {
+    fn take(&mut self, size: usize) -> Result<&'a [u8], Error> {
+        if self.data.len() - self.pos < size {
+            return Err(Error::Truncated("KDBX data ends early".to_string()))
+        }
+        self.pos += size;
+        Ok(&self.data[self.pos - size..self.pos])
+    }
+
+    fn rest(&self) -> &'a [u8] {
+        &self.data[self.pos..]
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        le_u32(self.take(4)?)
+    }
+}
+
+fn le_u32(value: &[u8]) -> Result<u32, Error> {
+    Ok(u32::from_le_bytes(value.try_into().map_err(|_| invalid("a number is not 4 bytes"))?))
+}
+
+fn le_u64(value: &[u8]) -> Result<u64, Error> {
+    Ok(u64::from_le_bytes(value.try_into().map_err(|_| invalid("a number is not 8 bytes"))?))
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::InvalidKdbx(reason.to_string())
+}
+
+// base64_decode is RFC 4648 base64 ignoring whitespace
+fn base64_decode(value: &str) -> Result<Vec<u8>, Error> {
+    let value: String = value.chars().filter(|c| !c.is_ascii_whitespace()).collect();
+    base64::decode(value).map_err(|_| invalid("a value is not base64"))
+}
+
+// hex_decode ignores whitespace as key files group the digits
+fn hex_decode(value: &str) -> Option<Vec<u8>> {
+    let value: String = value.chars().filter(|c| !c.is_ascii_whitespace()).collect();
+    hex::decode(value).ok()
+}
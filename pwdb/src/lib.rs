@@ -3,18 +3,42 @@ use std::str;
 
 use block_modes::{BlockMode, Cbc};
 use block_modes::block_padding::NoPadding;
-use chrono::{DateTime, NaiveDateTime, Utc};
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
 use hmac::{Hmac, Mac, NewMac};
+use rand::RngCore;
+use rand::rngs::OsRng;
 use sha2::Sha256;
 use twofish::Twofish;
+use zeroize::Zeroizing;
 
+pub use diff::diff;
+pub use error::Error;
+pub use policy::generate;
 use header::*;
 use preamble::*;
 use record::*;
 
+pub mod audit;
+pub mod bitwarden;
+pub mod breach;
+pub mod csv;
+pub mod diff;
+mod error;
+pub mod group;
 mod header;
+pub mod history;
+pub mod import;
+mod json;
+pub mod kdbx;
+pub mod merge;
+pub mod onepassword;
+pub mod policy;
+pub mod otp;
 mod preamble;
 pub mod record;
+pub mod secret;
+pub mod strength;
+pub mod xml;
 #[cfg(test)]
 mod test;
 
@@ -23,6 +47,7 @@ const EOF: &str = "PWS3-EOFPWS3-EOF";
 const TWOFISH_BLOCK_SIZE: usize = 16;
 
 type HmacSha256 = Hmac<Sha256>;
+type TwoFishCbc = Cbc<Twofish, NoPadding>;
 
 // TODO review naming conventions
 // TODO review proper comment style
@@ -34,16 +59,16 @@ pub struct Database {
     pub header: Header,
     // Last update to the DB records, independent than the last save timestamp in the header
     last_mod: DateTime<Utc>,
-    //the key is the record title
-    pub records: HashMap<uuid::Uuid, Record>,
+    // True when records have been changed since the DB was opened or last saved
+    dirty: bool,
+    records: HashMap<uuid::Uuid, Record>,
 }
 
 impl Database {
-    // TODO consider switching to std::error::Error rather than a string for the Err result
     // new creates a new database by reading from an encrypted data
-    pub fn new(bytes: &Vec<u8>, password: &str) -> Result<Database, String> {
+    pub fn new(bytes: &Vec<u8>, password: &str) -> Result<Database, Error> {
         if bytes.len() < 200 {
-            return Err("DB data is less than minimum size".to_string());
+            return Err(Error::Truncated("DB data is less than minimum size".to_string()));
         };
 
         let preamble = Preamble::new(Vec::from(&bytes[0..152]), password)?;
@@ -52,25 +77,27 @@ impl Database {
         let mut pos = 152;
         while pos < bytes.len() {
             if pos + TWOFISH_BLOCK_SIZE > bytes.len() {
-                return Err("Data size does not match expected size for twofish blocks".to_string())
+                return Err(Error::Truncated("Data size does not match expected size for twofish blocks".to_string()))
             }
             if &bytes[pos..pos + TWOFISH_BLOCK_SIZE] == EOF.as_bytes() {
                 break
             }
             pos += TWOFISH_BLOCK_SIZE;
         }
-        if pos > bytes.len() {
-            return Err("No EOF found in DB".to_string())
+        if pos >= bytes.len() {
+            return Err(Error::Truncated("No EOF found in DB".to_string()))
         }
         let hmac = &bytes[pos + TWOFISH_BLOCK_SIZE..];
+        if hmac.len() != 32 {
+            return Err(Error::Truncated("The HMAC following the EOF is not 32 bytes".to_string()))
+        }
 
-        // Decrypt the primary block of data
-        type TwoFishCbc = Cbc<Twofish, NoPadding>;
-        let cipher = TwoFishCbc::new_var(&preamble.encryption_key, &preamble.cbciv).unwrap();
+        // Decrypt the primary block of data, the plaintext buffers are wiped when dropped
+        let cipher = TwoFishCbc::new_var(&preamble.encryption_key[..], &preamble.cbciv).unwrap();
         let result = cipher.decrypt_vec(&bytes[152..pos]);
         let data = match result {
-            Ok(data) => data,
-            Err(error) => return Err(error.to_string()),
+            Ok(data) => Zeroizing::new(data),
+            Err(error) => return Err(Error::Truncated(error.to_string())),
         };
 
         // Verify the hmac is as expected, it is calculated only on the plain text fields in the
@@ -78,36 +105,242 @@ impl Database {
         let mut mac = HmacSha256::new_varkey(&preamble.hmac_key[..]).expect("Invalid hmac");
 
         let (header, data) = Header::new(&data, &mut mac)?;
+        let data = Zeroizing::new(data);
         let last_mod = match header.last_save {
             Some(save_date) => save_date,
-            None => return Err("missing last_save date".to_string()),
+            None => return Err(Error::MissingField("header last save date".to_string())),
         };
 
         let records = Record::new_records(&data, &mut mac)?;
 
         if let Err(_) = mac.verify(&hmac) {
-            return Err("HMAC mismatch!".to_string())
+            return Err(Error::HmacMismatch)
         }
 
         Ok(Database {
             preamble,
             header,
             last_mod,
+            dirty: false,
             records,
         })
     }
 
-    // record_search returns just the records that match the given search
-    pub fn record_search(&self, search: &str) -> HashMap<uuid::Uuid, Record> {
-        let results = self.records.iter()
-            .filter_map(|(uuid, record): (&uuid::Uuid, &record::Record)|
-                if record.matches(search) {
-                    return Some((*uuid, record.clone()));
-                } else {
-                    return None;
-                })
-            .collect::<HashMap<uuid::Uuid, Record>>();
-        return results;
+    // create makes a new empty database protected by the given password
+    pub fn create(password: &str, name: &str, description: &str) -> Result<Database, Error> {
+        if password.is_empty() {
+            return Err(Error::EmptyPassword)
+        }
+        let header = Header::create(name, description);
+        let last_mod = header.last_save.unwrap();
+        Ok(Database {
+            preamble: Preamble::create(password, DEFAULT_ITERATIONS),
+            header,
+            last_mod,
+            dirty: false,
+            records: HashMap::new(),
+        })
+    }
+
+    // change_password re-keys the database with a new password, the iterations used to stretch the
+    // password can optionally be changed at the same time.
+    pub fn change_password(&mut self, old: &str, new: &str, iterations: Option<u32>) -> Result<(), Error> {
+        self.preamble.change_password(old, new, iterations)?;
+        self.header.last_master_password_update = Some(now());
+        Ok(())
+    }
+
+    // save marks the database as saved by this application now and returns the encrypted bytes
+    // ready to be written to a file. A new CBC IV is chosen on every save.
+    pub fn save(&mut self) -> Vec<u8> {
+        OsRng.fill_bytes(&mut self.preamble.cbciv);
+        self.header.last_save = Some(now());
+        self.dirty = false;
+        self.header.last_save_by = format!("pwapwdb V{}", env!("CARGO_PKG_VERSION"));
+        self.to_bytes()
+    }
+
+    // to_bytes encrypts the database into the Password Safe V3 format, the reverse of new.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut mac = HmacSha256::new_varkey(&self.preamble.hmac_key[..]).expect("Invalid hmac");
+
+        let mut data = Zeroizing::new(self.header.to_bytes(&mut mac));
+        for record in self.records.values() {
+            data.extend_from_slice(&Zeroizing::new(record.to_bytes(&mut mac)));
+        }
+
+        let cipher = TwoFishCbc::new_var(&self.preamble.encryption_key[..], &self.preamble.cbciv).unwrap();
+        let mut bytes = self.preamble.to_bytes();
+        bytes.extend(cipher.encrypt_vec(&data));
+        bytes.extend_from_slice(EOF.as_bytes());
+        bytes.extend_from_slice(&mac.finalize().into_bytes());
+        bytes
+    }
+
+    // is_dirty reports if there are record changes which have not been saved
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    // last_modified is the time of the last change to the records
+    pub fn last_modified(&self) -> DateTime<Utc> {
+        self.last_mod
+    }
+
+    pub fn records(&self) -> &HashMap<uuid::Uuid, Record> {
+        &self.records
+    }
+
+    pub fn record(&self, uuid: &uuid::Uuid) -> Option<&Record> {
+        self.records.get(uuid)
+    }
+
+    // expiring_within lists the records whose password has expired or will within the duration,
+    // soonest first. A duration beyond the range of times lists every record with an expiry.
+    pub fn expiring_within(&self, within: chrono::Duration) -> Vec<&Record> {
+        let end = now().checked_add_signed(within);
+        let mut records: Vec<&Record> = self.records.values()
+            .filter(|record| record.password_expiry_time.is_some_and(|expiry| end.is_none_or(|end| expiry <= end)))
+            .collect();
+        records.sort_by(|a, b| (a.password_expiry_time, &a.title).cmp(&(b.password_expiry_time, &b.title)));
+        records
+    }
+
+    // add_record adds a new record, typically made with a RecordBuilder, returning its UUID
+    pub fn add_record(&mut self, record: Record) -> Result<uuid::Uuid, Error> {
+        record.validate()?;
+        let uuid = record.uuid();
+        if self.records.contains_key(&uuid) {
+            return Err(Error::DuplicateRecord(uuid))
+        }
+        self.remove_empty_groups(&record.group);
+        self.records.insert(uuid, record);
+        self.modified();
+        Ok(uuid)
+    }
+
+    // update_record replaces the record with the same UUID, updating the modification times. When the
+    // password changes the old one is added to the record's password history.
+    pub fn update_record(&mut self, mut record: Record) -> Result<(), Error> {
+        record.validate()?;
+        let existing = match self.records.get(&record.uuid()) {
+            Some(existing) => existing,
+            None => return Err(Error::RecordNotFound(record.uuid())),
+        };
+        let now = now();
+        if existing.password != record.password {
+            // The old password was set at its last change, or when the record was created
+            let set_time = existing.password_mod_time.or(existing.create_time).unwrap_or(now);
+            record.password_history.push(set_time, &existing.password);
+            record.password_mod_time = Some(now);
+            record.roll_expiry(now);
+        }
+        record.mod_time = Some(now);
+        self.remove_empty_groups(&record.group);
+        self.records.insert(record.uuid(), record);
+        self.modified();
+        Ok(())
+    }
+
+    // delete_record removes the record with the given UUID returning it
+    pub fn delete_record(&mut self, uuid: &uuid::Uuid) -> Result<Record, Error> {
+        match self.records.remove(uuid) {
+            Some(record) => {
+                self.modified();
+                Ok(record)
+            },
+            None => Err(Error::RecordNotFound(*uuid)),
+        }
+    }
+
+    // remove_empty_groups drops the empty groups which a record in the given group fills
+    fn remove_empty_groups(&mut self, path: &str) {
+        let names = group::split_path(path);
+        self.header.empty_groups.retain(|group| !names.starts_with(&group::split_path(group)));
+    }
+
+    fn modified(&mut self) {
+        self.last_mod = now();
+        self.dirty = true;
+    }
+
+    // group_tree returns the hierarchy of groups of all records along with the empty groups
+    pub fn group_tree(&self) -> group::GroupTree {
+        group::GroupTree::new(self.records.values().map(|record| record.group.as_str()), &self.header.empty_groups)
+    }
+
+    // create_empty_group adds a group without records, it must not already exist
+    pub fn create_empty_group(&mut self, path: &str) -> Result<(), Error> {
+        if self.group_tree().get(path).is_some() {
+            return Err(Error::GroupExists(path.to_string()))
+        }
+        // Parents are implied by the new group so don't need to be listed separately
+        self.remove_empty_groups(path);
+        self.header.empty_groups.push(path.to_string());
+        self.modified();
+        Ok(())
+    }
+
+    // delete_empty_group removes a group and the groups below it, none of which may have records
+    pub fn delete_empty_group(&mut self, path: &str) -> Result<(), Error> {
+        match self.group_tree().get(path) {
+            Some(_) if path.is_empty() => return Err(Error::GroupNotFound(path.to_string())),
+            Some(group) if group.entry_count() > 0 => return Err(Error::GroupNotEmpty(path.to_string())),
+            Some(_) => (),
+            None => return Err(Error::GroupNotFound(path.to_string())),
+        }
+        let names = group::split_path(path);
+        self.header.empty_groups.retain(|group| !group::split_path(group).starts_with(&names));
+        self.modified();
+        Ok(())
+    }
+
+    // move_group renames a group or moves it elsewhere in the tree, along with all the records and
+    // groups below it. The destination must not already exist.
+    pub fn move_group(&mut self, from: &str, to: &str) -> Result<(), Error> {
+        if from.is_empty() || self.group_tree().get(from).is_none() {
+            return Err(Error::GroupNotFound(from.to_string()))
+        }
+        if self.group_tree().get(to).is_some() {
+            return Err(Error::GroupExists(to.to_string()))
+        }
+        let from = group::split_path(from);
+        let to = group::split_path(to);
+        let now = now();
+        for record in self.records.values_mut() {
+            if let Some(moved) = group::move_path(&record.group, &from, &to) {
+                record.group = moved;
+                record.mod_time = Some(now);
+            }
+        }
+        for empty_group in self.header.empty_groups.iter_mut() {
+            if let Some(moved) = group::move_path(empty_group, &from, &to) {
+                *empty_group = moved;
+            }
+        }
+        self.modified();
+        Ok(())
+    }
+
+    // password_policy returns the policy for generating the record's password. This is the record's
+    // own policy, else the named policy from the header it refers to, else the default policy.
+    pub fn password_policy(&self, record: &Record) -> policy::PasswordPolicy {
+        if let Some(policy) = &record.password_policy {
+            return policy.clone()
+        }
+        match self.header.password_policies.get(&record.password_policy_name) {
+            Some(policy) => policy.clone(),
+            None => policy::PasswordPolicy::default(),
+        }
+    }
+
+    // record_search returns just the records that match the given search, borrowed so the secret
+    // fields aren't copied
+    pub fn record_search(&self, search: &str) -> HashMap<uuid::Uuid, &Record> {
+        self.records.iter()
+            .filter(|(_, record)| record.matches(search))
+            .map(|(uuid, record)| (*uuid, record))
+            .collect()
     }
 }
 
@@ -116,33 +349,189 @@ struct Field {
     total_size: usize,
     // this is a multiplier of the block size
     type_id: u8,
-    data: Vec<u8>,
+    data: Zeroizing<Vec<u8>>,
 }
 
 impl Field {
     // new parses a field from the given bytes assuming a Twofish block size
-    fn new(bytes: &[u8]) -> Result<Field, String> {
+    fn new(bytes: &[u8]) -> Result<Field, Error> {
+        if bytes.len() < 5 {
+            return Err(Error::Truncated("No room for another field length and type".to_string()))
+        }
         let size = u32::from_le_bytes(copy_into_array(&bytes[..4])) as usize;
+        // Checked before adding to the size so a hostile length can't overflow on 32 bit targets
+        if size > bytes.len() - 5 {
+            return Err(Error::Truncated(format!("Data length of field {} is larger than the byte slice length {}", size, bytes.len())))
+        }
         let mut total_size = 5 + size;
         let remainder = total_size % TWOFISH_BLOCK_SIZE;
         if remainder != 0 {
             total_size += TWOFISH_BLOCK_SIZE - remainder;
         }
 
-        if total_size > bytes.len() + 1 {
-            return Err(format!("Data length of field {} is larger than the byte slice length {}", total_size, bytes.len()))
+        if total_size > bytes.len() {
+            return Err(Error::Truncated(format!("Padded length of field {} is larger than the byte slice length {}", total_size, bytes.len())))
         }
 
         Ok(Field {
-            data: bytes[5..5 + size].to_vec(),
+            data: Zeroizing::new(bytes[5..5 + size].to_vec()),
             total_size,
             type_id: bytes[4],
         })
     }
+
+    // string converts the field data to a String
+    fn string(&self) -> Result<String, Error> {
+        String::from_utf8(self.data.to_vec()).map_err(|_| Error::InvalidUtf8 { type_id: self.type_id })
+    }
+
+    // array copies the first N bytes of the field data, erroring if the field is shorter
+    fn array<const N: usize>(&self) -> Result<[u8; N], Error> {
+        match self.data.get(..N) {
+            Some(data) => {
+                let mut array = [0u8; N];
+                array.copy_from_slice(data);
+                Ok(array)
+            },
+            None => Err(Error::BadFieldLength { type_id: self.type_id, length: self.data.len() }),
+        }
+    }
+
+    // date converts the field data from a 32 bit little endian time_t
+    fn date(&self) -> Result<DateTime<Utc>, Error> {
+        if self.data.len() != 4 {
+            return Err(Error::BadFieldLength { type_id: self.type_id, length: self.data.len() })
+        }
+        Ok(DateTime::from_utc(
+            NaiveDateTime::from_timestamp(
+                u32::from_le_bytes(copy_into_array(&self.data)) as i64, 0,
+            ), Utc))
+    }
 }
 
-// TODO make sure I understand this and try out the try_into variants
-// Also make it so that returns an error rather than panic on failure
+// fuzz_db builds a DB file around arbitrary plain text, parsed as if it were the header and record
+// fields. This lets fuzzing reach the field parsers which are otherwise behind the encryption.
+// The HMAC is zeroed so a successful parse still fails the HMAC check.
+#[cfg(any(test, feature = "fuzzing"))]
+pub fn fuzz_db(password: &str, plaintext: &[u8]) -> Vec<u8> {
+    let preamble = Preamble::create(password, MIN_ITERATIONS);
+    let mut data = plaintext.to_vec();
+    let remainder = data.len() % TWOFISH_BLOCK_SIZE;
+    if remainder != 0 {
+        data.resize(data.len() + TWOFISH_BLOCK_SIZE - remainder, 0);
+    }
+
+    let cipher = TwoFishCbc::new_var(&preamble.encryption_key[..], &preamble.cbciv).unwrap();
+    let mut bytes = preamble.to_bytes();
+    bytes.extend(cipher.encrypt_vec(&data));
+    bytes.extend_from_slice(EOF.as_bytes());
+    bytes.extend_from_slice(&[0u8; 32]);
+    bytes
+}
+
+// TextReader reads the fixed width hex numbers and strings which make up some text fields, such as
+// the password history and policies. Widths are in characters rather than bytes.
+struct TextReader {
+    chars: Vec<char>,
+    pos: usize,
+    type_id: u8,
+}
+
+impl TextReader {
+    fn new(value: &str, type_id: u8) -> TextReader {
+        TextReader {
+            chars: value.chars().collect(),
+            pos: 0,
+            type_id,
+        }
+    }
+
+    fn string(&mut self, width: usize) -> Result<String, Error> {
+        let taken = self.chars.get(self.pos..self.pos + width).ok_or_else(|| self.invalid("truncated".to_string()))?;
+        self.pos += width;
+        Ok(taken.iter().collect())
+    }
+
+    // hex parses a fixed width hex number, unlike from_str_radix a leading sign is not allowed
+    fn hex(&mut self, width: usize) -> Result<u32, Error> {
+        let digits = self.string(width)?;
+        if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(self.invalid(format!("'{}' is not hex", digits)))
+        }
+        u32::from_str_radix(&digits, 16).map_err(|_| self.invalid(format!("'{}' is not hex", digits)))
+    }
+
+    // end errors if there is unread data
+    fn end(&self) -> Result<(), Error> {
+        if self.pos < self.chars.len() {
+            return Err(self.invalid("unexpected data after the last value".to_string()))
+        }
+        Ok(())
+    }
+
+    fn invalid(&self, reason: String) -> Error {
+        Error::InvalidField { type_id: self.type_id, reason }
+    }
+}
+
+// FieldWriter serializes header or record fields, the reverse of Field::new. As fields are written
+// the mac is updated with their data.
+struct FieldWriter<'a> {
+    bytes: Vec<u8>,
+    mac: &'a mut HmacSha256,
+}
+
+impl<'a> FieldWriter<'a> {
+    fn new(mac: &'a mut HmacSha256) -> FieldWriter<'a> {
+        FieldWriter {
+            bytes: Vec::new(),
+            mac,
+        }
+    }
+
+    // field writes the length, type and data padded with random bytes to the Twofish block size
+    fn field(&mut self, type_id: u8, data: &[u8]) {
+        let start = self.bytes.len();
+        self.bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        self.bytes.push(type_id);
+        self.bytes.extend_from_slice(data);
+
+        let remainder = (self.bytes.len() - start) % TWOFISH_BLOCK_SIZE;
+        if remainder != 0 {
+            let mut padding = vec![0u8; TWOFISH_BLOCK_SIZE - remainder];
+            OsRng.fill_bytes(&mut padding);
+            self.bytes.extend(padding);
+        }
+
+        self.mac.update(data);
+    }
+
+    // string writes a text field, empty strings are skipped as they are when not in the file
+    fn string(&mut self, type_id: u8, value: &str) {
+        if !value.is_empty() {
+            self.field(type_id, value.as_bytes());
+        }
+    }
+
+    fn date(&mut self, type_id: u8, value: Option<DateTime<Utc>>) {
+        if let Some(date) = value {
+            self.field(type_id, &pwsafe_date_bytes(&date));
+        }
+    }
+
+    // end writes the end field, which is not part of the mac, and returns the serialized fields
+    fn end(mut self) -> Vec<u8> {
+        self.bytes.extend_from_slice(&0u32.to_le_bytes());
+        self.bytes.push(0xff);
+        let mut padding = vec![0u8; TWOFISH_BLOCK_SIZE - 5];
+        OsRng.fill_bytes(&mut padding);
+        self.bytes.extend(padding);
+        self.bytes
+    }
+}
+
+// copy_into_array panics if the slice and array lengths differ so it must only be used where the
+// length is already known, Field::array is the fallible version for parsed data.
 // this code was copied from https://stackoverflow.com/questions/25428920/how-to-get-a-slice-as-an-array-in-rust
 fn copy_into_array<A, T>(slice: &[T]) -> A
     where
@@ -154,12 +543,11 @@ fn copy_into_array<A, T>(slice: &[T]) -> A
     a
 }
 
-fn pwsafe_date(bytes: &Vec<u8>) -> Result<DateTime<Utc>, String> {
-    if bytes.len() != 4 {
-        return Err("Unexpected field length for last master password update".to_string())
-    }
-    Ok(DateTime::from_utc(
-        NaiveDateTime::from_timestamp(
-            u32::from_le_bytes(crate::copy_into_array(&bytes)) as i64, 0,
-        ), Utc))
+// now returns the current time at the resolution of seconds used by pwsafe dates
+fn now() -> DateTime<Utc> {
+    Utc.timestamp(Utc::now().timestamp(), 0)
+}
+
+fn pwsafe_date_bytes(date: &DateTime<Utc>) -> [u8; 4] {
+    (date.timestamp() as u32).to_le_bytes()
 }
@@ -0,0 +1,102 @@
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::{now, Database};
+use crate::record::Record;
+
+// MergeReport describes the changes made by Database::merge
+#[derive(Debug, PartialEq)]
+pub struct MergeReport {
+    // When the merge was done, to be passed as last_merge to the next merge of the same copies
+    pub time: DateTime<Utc>,
+    // Records only in the other database which were added
+    pub added: Vec<Uuid>,
+    // Records replaced by the newer version from the other database
+    pub updated: Vec<Uuid>,
+    // Records edited in both databases, the merged record is in the database
+    pub conflicts: Vec<Conflict>,
+}
+
+// Conflict holds both versions of a record which was changed in both databases
+#[derive(Debug, PartialEq)]
+pub struct Conflict {
+    pub ours: Record,
+    pub theirs: Record,
+}
+
+impl Database {
+    // merge brings in the records of another copy of this database. Records are matched by UUID and
+    // the version with the newer modification time is kept, records only in the other database are
+    // added. The records are in conflict when both versions were changed since they were last the
+    // same. That is the time of the last merge of the two copies, from MergeReport::time, and both
+    // versions modified after it are in conflict. The format has no field for it so it is up to the
+    // caller to keep. Without it the conflicts are guessed: both versions have the same
+    // modification time but differ or the older version has the newer password. Conflicts keep the
+    // newer version with the newer password and are reported for review.
+    // Deletions can't be merged as there is no record of them, a record deleted in only one database
+    // comes back.
+    pub fn merge(&mut self, other: &Database, last_merge: Option<DateTime<Utc>>) -> MergeReport {
+        let mut report = MergeReport { time: now(), added: Vec::new(), updated: Vec::new(), conflicts: Vec::new() };
+        for (uuid, theirs) in &other.records {
+            let ours = match self.records.get(uuid) {
+                Some(ours) => ours,
+                None => {
+                    self.remove_empty_groups(&theirs.group);
+                    self.records.insert(*uuid, theirs.clone());
+                    report.added.push(*uuid);
+                    continue
+                },
+            };
+            if same_except_access(ours, theirs) {
+                continue
+            }
+
+            let theirs_newer = theirs.mod_time > ours.mod_time;
+            let (newer, older) = if theirs_newer { (theirs, ours) } else { (ours, theirs) };
+            let mut merged = newer.clone();
+            let older_password_newer = older.password_mod_time > newer.password_mod_time;
+            let conflict = match last_merge {
+                Some(_) => ours.mod_time > last_merge && theirs.mod_time > last_merge,
+                None => ours.mod_time == theirs.mod_time || older_password_newer,
+            };
+            if conflict && older_password_newer {
+                merged.password = older.password.clone();
+                merged.password_mod_time = older.password_mod_time;
+                merged.password_history = older.password_history.clone();
+            }
+            merged.access_time = ours.access_time.max(theirs.access_time);
+
+            if conflict {
+                report.conflicts.push(Conflict { ours: ours.clone(), theirs: theirs.clone() });
+            } else if theirs_newer {
+                report.updated.push(*uuid);
+            }
+            self.remove_empty_groups(&merged.group);
+            self.records.insert(*uuid, merged);
+        }
+
+        let tree = self.group_tree();
+        let mut groups_added = false;
+        for group in &other.header.empty_groups {
+            if tree.get(group).is_none() {
+                self.header.empty_groups.push(group.clone());
+                groups_added = true;
+            }
+        }
+
+        report.added.sort();
+        report.updated.sort();
+        report.conflicts.sort_by_key(|conflict| conflict.ours.uuid());
+        if groups_added || !report.added.is_empty() || !report.updated.is_empty() || !report.conflicts.is_empty() {
+            self.modified();
+        }
+        report
+    }
+}
+
+// same_except_access compares records ignoring the access time which changes on every view
+fn same_except_access(ours: &Record, theirs: &Record) -> bool {
+    let mut theirs = theirs.clone();
+    theirs.access_time = ours.access_time;
+    *ours == theirs
+}
@@ -0,0 +1,241 @@
+use std::convert::TryInto;
+use std::io::Read;
+
+use chrono::{DateTime, TimeZone, Utc};
+use flate2::read::DeflateDecoder;
+
+use crate::{Database, Error};
+use crate::group;
+use crate::history::PasswordHistory;
+use crate::import::ImportReport;
+use crate::json::{self, Value};
+use crate::otp::Totp;
+use crate::record::{self, Record};
+
+// The most an archive entry can decompress to, a small deflated entry can otherwise inflate without bound
+const MAX_ENTRY_BYTES: u64 = 1 << 30;
+
+// The item categories of a 1Password export which are imported
+const LOGIN: &str = "001";
+const CREDIT_CARD: &str = "002";
+const SECURE_NOTE: &str = "003";
+const PASSWORD: &str = "005";
+
+// The names of the other categories, for reporting the items skipped
+const CATEGORIES: [(&str, &str); 17] = [
+    ("004", "identity"), ("006", "document"), ("100", "software license"), ("101", "bank account"),
+    ("102", "database"), ("103", "driver license"), ("104", "outdoor license"), ("105", "membership"),
+    ("106", "passport"), ("107", "reward program"), ("108", "social security number"),
+    ("109", "wireless router"), ("110", "server"), ("111", "email account"), ("112", "API credential"),
+    ("113", "medical record"), ("114", "SSH key"),
+];
+
+impl Database {
+    // import_1pux adds the items of a 1Password .1pux export, each vault becomes a group. Logins,
+    // passwords, credit cards and secure notes are imported. Section fields which aren't one of the
+    // record fields, extra URLs and tags are added to the notes. Items without a password, such as
+    // cards and notes, are given a generated one. Other categories are reported as skipped.
+    // Attached files aren't imported.
+    pub fn import_1pux(&mut self, data: &[u8]) -> Result<ImportReport, Error> {
+        let export = zip_entry(data, "export.data")?;
+        let export = String::from_utf8(export).map_err(|_| invalid("export.data is not UTF-8"))?;
+        let root = json::parse(&export)?;
+        if !matches!(root.get("accounts"), Value::Array(_)) {
+            return Err(invalid("export has no accounts list"))
+        }
+
+        let mut report = ImportReport::default();
+        for vault in root.get("accounts").array().iter().flat_map(|account| account.get("vaults").array()) {
+            let vault_name = vault.get("attrs").get("name").str().to_string();
+            let group = group::join_path(std::slice::from_ref(&vault_name));
+            for item in vault.get("items").array() {
+                match self.onepassword_item(item, &group).and_then(|record| self.add_imported(record, &mut report)) {
+                    Ok(()) => (),
+                    Err(err) => {
+                        let names = [vault_name.clone(), item.get("overview").get("title").str().to_string()];
+                        report.skipped.push((group::join_path(&names), err));
+                    },
+                }
+            }
+        }
+        self.modified();
+        Ok(report)
+    }
+
+    fn onepassword_item(&self, item: &Value, group: &str) -> Result<Record, Error> {
+        let category = item.get("categoryUuid").str();
+        if ![LOGIN, CREDIT_CARD, SECURE_NOTE, PASSWORD].contains(&category) {
+            let name = CATEGORIES.iter().find(|(uuid, _)| *uuid == category)
+                .map_or_else(|| format!("category {}", category), |(_, name)| name.to_string());
+            return Err(Error::UnsupportedItem(format!("1Password {}", name)))
+        }
+
+        let overview = item.get("overview");
+        let details = item.get("details");
+        let mut record = Record {
+            group: group.to_string(),
+            title: overview.get("title").str().to_string(),
+            url: overview.get("url").str().to_string(),
+            notes: details.get("notesPlain").str().to_string(),
+            password: details.get("password").str().into(),
+            create_time: time(item.get("createdAt")),
+            mod_time: time(item.get("updatedAt")),
+            ..Default::default()
+        };
+        let mut notes = Vec::new();
+        for field in details.get("loginFields").array() {
+            let value = field.get("value").str();
+            match field.get("designation").str() {
+                "username" => record.username = value.to_string(),
+                "password" => record.password = value.into(),
+                _ if !value.is_empty() => notes.push(format!("{}: {}", field.get("name").str(), value)),
+                _ => (),
+            }
+        }
+        for section in details.get("sections").array() {
+            for field in section.get("fields").array() {
+                self::field(&mut record, &mut notes, category, field);
+            }
+        }
+        for url in overview.get("urls").array().iter().map(|url| url.get("url").str()) {
+            if record.url.is_empty() {
+                record.url = url.to_string();
+            } else if url != record.url && !url.is_empty() {
+                notes.push(format!("URL: {}", url));
+            }
+        }
+        let tags: Vec<&str> = overview.get("tags").array().iter().map(|tag| tag.str()).collect();
+        if !tags.is_empty() {
+            notes.push(format!("Tags: {}", tags.join(", ")));
+        }
+        record.append_notes(&notes);
+        if record.title.is_empty() {
+            record.title = record.url.clone();
+        }
+        record.password_mod_time = record.create_time;
+
+        let history = details.get("passwordHistory").array().iter()
+            .map(|entry| (time(entry.get("time")).unwrap_or_else(crate::now), entry.get("value").str().to_string()))
+            .collect();
+        record.password_history = PasswordHistory::imported(history);
+        Ok(record)
+    }
+}
+
+// field maps a section field onto the record, the card fields by their ids and the first TOTP and
+// email fields of any item. Everything else is added to the notes.
+fn field(record: &mut Record, notes: &mut Vec<String>, category: &str, field: &Value) {
+    // The value is an object with a single member named for the type of field
+    let (kind, value) = match field.get("value") {
+        Value::Object(members) if !members.is_empty() => (members[0].0.as_str(), &members[0].1),
+        _ => return,
+    };
+    let text = match (kind, value) {
+        ("email", email) => email.get("email_address").str().to_string(),
+        ("monthYear", Value::Number(month_year)) => format!("{:02}/{}", *month_year as u32 % 100, *month_year as u32 / 100),
+        ("date", date) => time(date).map(|date| date.format("%Y-%m-%d").to_string()).unwrap_or_default(),
+        ("address", address) => ["street", "city", "state", "zip", "country"].iter()
+            .map(|part| address.get(part).str())
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<&str>>()
+            .join(", "),
+        (_, Value::String(text)) => text.clone(),
+        (_, Value::Number(number)) => number.to_string(),
+        (_, Value::Bool(flag)) => flag.to_string(),
+        _ => String::new(),
+    };
+    let id = field.get("id").str();
+    if category == CREDIT_CARD && ["cardholder", "ccnum", "cvv", "pin", "expiry"].contains(&id) {
+        match (id, value.number()) {
+            ("cardholder", _) => record.username = text,
+            ("ccnum", _) => record.credit_card_number = text.into(),
+            ("cvv", _) => record.credit_card_verify = text.into(),
+            ("pin", _) => record.credit_card_pin = text.into(),
+            // The expiry is stored as the number YYYYMM
+            (_, Some(month_year)) => record.credit_card_expiration = record::card_expiration(month_year as u32 % 100, month_year as u32 / 100),
+            _ => record.credit_card_expiration = text,
+        }
+        return
+    }
+    if kind == "totp" && record.totp.is_none() {
+        if let Some(totp) = Totp::from_secret(&text) {
+            record.totp = Some(totp);
+            return
+        }
+    }
+    if kind == "email" && record.email.is_empty() {
+        record.email = text;
+        return
+    }
+    if !text.is_empty() {
+        let name = if field.get("title").str().is_empty() { id } else { field.get("title").str() };
+        notes.push(format!("{}: {}", name, text));
+    }
+}
+
+// time converts the Unix times of the export, missing or invalid times are treated as unset
+fn time(value: &Value) -> Option<DateTime<Utc>> {
+    Utc.timestamp_opt(value.number()? as i64, 0).single()
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::InvalidExport(format!("1Password {}", reason))
+}
+
+// zip_entry reads a file from a zip archive, which may be stored or deflated. The central directory
+// at the end of the archive is used to find it.
+fn zip_entry(data: &[u8], name: &str) -> Result<Vec<u8>, Error> {
+    const END_SIGNATURE: [u8; 4] = [0x50, 0x4b, 0x05, 0x06];
+    const CENTRAL_SIGNATURE: [u8; 4] = [0x50, 0x4b, 0x01, 0x02];
+    const LOCAL_SIGNATURE: [u8; 4] = [0x50, 0x4b, 0x03, 0x04];
+    // The offsets are read from the archive so they are added with overflow checks
+    let offset = |values: &[usize]| values.iter()
+        .try_fold(0usize, |sum, value| sum.checked_add(*value))
+        .ok_or_else(|| invalid("archive offsets are out of range"));
+    let bytes = |pos: usize, length: usize| data.get(pos..offset(&[pos, length]).ok()?);
+    let u16_at = |pos: usize, at: usize| bytes(offset(&[pos, at]).ok()?, 2).map(|b| u16::from_le_bytes(b.try_into().unwrap()) as usize);
+    let u32_at = |pos: usize, at: usize| bytes(offset(&[pos, at]).ok()?, 4).map(|b| u32::from_le_bytes(b.try_into().unwrap()) as usize);
+    let truncated = || invalid("archive is truncated");
+
+    // The end record is followed by a comment of up to 64KiB
+    let end = (0..data.len().saturating_sub(21)).rev()
+        .take(0x10000)
+        .find(|pos| data[*pos..].starts_with(&END_SIGNATURE))
+        .ok_or_else(|| invalid("file is not a zip archive"))?;
+    let count = u16_at(end, 10).ok_or_else(truncated)?;
+    let mut pos = u32_at(end, 16).ok_or_else(truncated)?;
+    for _ in 0..count {
+        if bytes(pos, 4) != Some(&CENTRAL_SIGNATURE[..]) {
+            return Err(invalid("archive directory is corrupt"))
+        }
+        let name_length = u16_at(pos, 28).ok_or_else(truncated)?;
+        let entry_name = bytes(offset(&[pos, 46])?, name_length).ok_or_else(truncated)?;
+        if entry_name != name.as_bytes() {
+            pos = offset(&[pos, 46, name_length, u16_at(pos, 30).ok_or_else(truncated)?, u16_at(pos, 32).ok_or_else(truncated)?])?;
+            continue
+        }
+
+        let method = u16_at(pos, 10).ok_or_else(truncated)?;
+        let size = u32_at(pos, 20).ok_or_else(truncated)?;
+        let local = u32_at(pos, 42).ok_or_else(truncated)?;
+        if bytes(local, 4) != Some(&LOCAL_SIGNATURE[..]) {
+            return Err(invalid("archive is corrupt"))
+        }
+        let start = offset(&[local, 30, u16_at(local, 26).ok_or_else(truncated)?, u16_at(local, 28).ok_or_else(truncated)?])?;
+        let compressed = bytes(start, size).ok_or_else(truncated)?;
+        return match method {
+            0 => Ok(compressed.to_vec()),
+            8 => {
+                let mut contents = Vec::new();
+                DeflateDecoder::new(compressed).take(MAX_ENTRY_BYTES + 1).read_to_end(&mut contents)
+                    .map_err(|_| invalid(&format!("{} can't be decompressed", name)))?;
+                if contents.len() as u64 > MAX_ENTRY_BYTES {
+                    return Err(invalid(&format!("{} is over the limit of {} bytes", name, MAX_ENTRY_BYTES)))
+                }
+                Ok(contents)
+            },
+            _ => Err(invalid(&format!("{} is compressed with unsupported method {}", name, method))),
+        }
+    }
+    Err(invalid(&format!("archive has no {}", name)))
+}
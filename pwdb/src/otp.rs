@@ -0,0 +1,307 @@
+use std::fmt;
+
+use chrono::{DateTime, TimeZone, Utc};
+use hmac::{Hmac, Mac, NewMac};
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+use zeroize::Zeroizing;
+
+use crate::Error;
+
+pub(super) const DEFAULT_DIGITS: u8 = 6;
+pub(super) const DEFAULT_PERIOD: u8 = 30;
+
+// Algorithm is the HMAC hash used for one time codes. The pwsafe format only defines SHA1 so keys
+// using SHA256 or SHA512 can generate codes and be read from otpauth URIs but can't be stored.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Algorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl Algorithm {
+    // from_config reads the algorithm from the low bits of the TOTP config field, None if it isn't
+    // one pwsafe defines
+    pub(super) fn from_config(config: u8) -> Option<Algorithm> {
+        match config & 0x03 {
+            0 => Some(Algorithm::Sha1),
+            _ => None,
+        }
+    }
+}
+
+// Totp generates time based one time codes, RFC 6238, from a record's two factor key. The key is
+// wiped from memory when dropped.
+#[derive(Clone, PartialEq)]
+pub struct Totp {
+    pub key: Zeroizing<Vec<u8>>,
+    pub algorithm: Algorithm,
+    // The number of digits in a code, from 1 to 10
+    pub digits: u8,
+    // The number of seconds each code is valid for
+    pub period: u8,
+    // Time from which periods are counted, normally the Unix epoch
+    pub start: DateTime<Utc>,
+}
+
+// Debug leaves out the key
+impl fmt::Debug for Totp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Totp")
+            .field("algorithm", &self.algorithm)
+            .field("digits", &self.digits)
+            .field("period", &self.period)
+            .field("start", &self.start)
+            .finish_non_exhaustive()
+    }
+}
+
+// OneTimeCode is a generated code along with how long it remains valid
+#[derive(Clone, Debug, PartialEq)]
+pub struct OneTimeCode {
+    pub code: String,
+    pub seconds_remaining: u32,
+}
+
+impl Totp {
+    // new uses the settings most services expect, SHA1 with 6 digit codes every 30 seconds
+    pub fn new(key: &[u8]) -> Totp {
+        Totp {
+            key: Zeroizing::new(key.to_vec()),
+            algorithm: Algorithm::Sha1,
+            digits: DEFAULT_DIGITS,
+            period: DEFAULT_PERIOD,
+            start: Utc.timestamp(0, 0),
+        }
+    }
+
+    // from_secret reads a two factor key exported by another password manager, either an otpauth
+    // URI or just the base32 secret
+    pub(super) fn from_secret(value: &str) -> Option<Totp> {
+        let value = value.trim();
+        if value.starts_with("otpauth://") {
+            return OtpAuth::parse(value).ok().map(|otpauth| otpauth.totp).filter(|totp| totp.validate().is_ok())
+        }
+        match base32_decode(value) {
+            Some(key) if !key.is_empty() => Some(Totp::new(&key)),
+            _ => None,
+        }
+    }
+
+    // validate checks the settings read from a record are usable and can be stored
+    pub(super) fn validate(&self) -> Result<(), Error> {
+        if self.algorithm != Algorithm::Sha1 {
+            return Err(Error::InvalidField { type_id: 0x21, reason: format!("two factor keys using {:?} can't be stored, pwsafe only supports SHA1", self.algorithm) })
+        }
+        if !(1..=10).contains(&self.digits) {
+            return Err(Error::InvalidField { type_id: 0x22, reason: format!("{} digits is out of range", self.digits) })
+        }
+        if self.period == 0 {
+            return Err(Error::InvalidField { type_id: 0x23, reason: "the period is zero".to_string() })
+        }
+        Ok(())
+    }
+
+    // code returns the code for the current time
+    pub fn code(&self) -> OneTimeCode {
+        self.code_at(Utc::now())
+    }
+
+    // code_at returns the code valid at the given time, times before the start use the first period
+    pub fn code_at(&self, time: DateTime<Utc>) -> OneTimeCode {
+        let period = u64::from(self.period.max(1));
+        let elapsed = (time.timestamp() - self.start.timestamp()).max(0) as u64;
+        OneTimeCode {
+            code: hotp(&self.key, elapsed / period, self.digits, self.algorithm),
+            seconds_remaining: (period - elapsed % period) as u32,
+        }
+    }
+}
+
+// hotp generates the counter based one time code, RFC 4226. Digits are limited to 1 to 10.
+pub fn hotp(key: &[u8], counter: u64, digits: u8, algorithm: Algorithm) -> String {
+    let counter = counter.to_be_bytes();
+    let hash = match algorithm {
+        Algorithm::Sha1 => hmac::<Hmac<Sha1>>(key, &counter),
+        Algorithm::Sha256 => hmac::<Hmac<Sha256>>(key, &counter),
+        Algorithm::Sha512 => hmac::<Hmac<Sha512>>(key, &counter),
+    };
+
+    // Dynamic truncation, the low 4 bits of the last byte are the offset of a 31 bit number
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let value = u32::from_be_bytes(crate::copy_into_array(&hash[offset..offset + 4])) & 0x7fff_ffff;
+    let digits = digits.clamp(1, 10) as usize;
+    format!("{:0width$}", u64::from(value) % 10u64.pow(digits as u32), width = digits)
+}
+
+fn hmac<M: Mac + NewMac>(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = M::new_varkey(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+// OtpAuth is the otpauth URI format used to share two factor keys between apps, for example
+// otpauth://totp/Example:alice@example.com?secret=JBSWY3DPEHPK3PXP&issuer=Example
+// See https://github.com/google/google-authenticator/wiki/Key-Uri-Format
+#[derive(Clone, Debug, PartialEq)]
+pub struct OtpAuth {
+    pub totp: Totp,
+    pub issuer: String,
+    pub account: String,
+}
+
+impl OtpAuth {
+    // parse reads a TOTP URI, HOTP URIs are rejected as pwsafe has no field for the counter
+    pub fn parse(uri: &str) -> Result<OtpAuth, Error> {
+        let invalid = |reason: &str| Error::InvalidOtpAuth(reason.to_string());
+        let scheme = "otpauth://";
+        if uri.len() < scheme.len() || !uri.is_char_boundary(scheme.len()) || !uri[..scheme.len()].eq_ignore_ascii_case(scheme) {
+            return Err(invalid("the scheme is not otpauth"))
+        }
+        let rest = &uri[scheme.len()..];
+        let (kind, rest) = rest.split_at(rest.find('/').ok_or_else(|| invalid("there is no label"))?);
+        if !kind.eq_ignore_ascii_case("totp") {
+            return Err(invalid("only totp is supported"))
+        }
+        let (label, query) = match rest[1..].find('?') {
+            Some(i) => (&rest[1..i + 1], &rest[i + 2..]),
+            None => (&rest[1..], ""),
+        };
+
+        let label = percent_decode(label, false)?;
+        let (mut issuer, account) = match label.find(':') {
+            Some(i) => (label[..i].to_string(), label[i + 1..].trim_start().to_string()),
+            None => (String::new(), label),
+        };
+
+        let mut totp = Totp::new(&[]);
+        for parameter in query.split('&').filter(|p| !p.is_empty()) {
+            let (name, value) = match parameter.find('=') {
+                Some(i) => (&parameter[..i], percent_decode(&parameter[i + 1..], true)?),
+                None => (parameter, String::new()),
+            };
+            match name.to_ascii_lowercase().as_str() {
+                "secret" => totp.key = base32_decode(&value).ok_or_else(|| invalid("the secret is not base32"))?.into(),
+                // The parameter is preferred over the label prefix when both are set
+                "issuer" => issuer = value,
+                "algorithm" => totp.algorithm = match value.to_ascii_uppercase().as_str() {
+                    "SHA1" => Algorithm::Sha1,
+                    "SHA256" => Algorithm::Sha256,
+                    "SHA512" => Algorithm::Sha512,
+                    _ => return Err(invalid(&format!("unknown algorithm {}", value))),
+                },
+                "digits" => totp.digits = value.parse().ok().filter(|d| (1..=10).contains(d))
+                    .ok_or_else(|| invalid(&format!("invalid digits {}", value)))?,
+                "period" => totp.period = value.parse().ok().filter(|p| *p > 0)
+                    .ok_or_else(|| invalid(&format!("invalid period {}, it must be from 1 to 255", value)))?,
+                // Other parameters such as image don't map to pwsafe fields
+                _ => (),
+            }
+        }
+        if totp.key.is_empty() {
+            return Err(invalid("the secret is missing"))
+        }
+
+        Ok(OtpAuth {
+            totp,
+            issuer,
+            account,
+        })
+    }
+}
+
+// Display formats the URI, only parameters which differ from the defaults are included
+impl fmt::Display for OtpAuth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "otpauth://totp/")?;
+        if !self.issuer.is_empty() {
+            write!(f, "{}:", percent_encode(&self.issuer))?;
+        }
+        write!(f, "{}?secret={}", percent_encode(&self.account), base32_encode(&self.totp.key))?;
+        if !self.issuer.is_empty() {
+            write!(f, "&issuer={}", percent_encode(&self.issuer))?;
+        }
+        match self.totp.algorithm {
+            Algorithm::Sha1 => (),
+            Algorithm::Sha256 => write!(f, "&algorithm=SHA256")?,
+            Algorithm::Sha512 => write!(f, "&algorithm=SHA512")?,
+        }
+        if self.totp.digits != DEFAULT_DIGITS {
+            write!(f, "&digits={}", self.totp.digits)?;
+        }
+        if self.totp.period != DEFAULT_PERIOD {
+            write!(f, "&period={}", self.totp.period)?;
+        }
+        Ok(())
+    }
+}
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+// base32_decode is RFC 4648 base32 ignoring case, padding and spaces as apps often show keys in groups
+pub(super) fn base32_decode(value: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in value.chars().filter(|c| *c != '=' && *c != ' ') {
+        let index = BASE32_ALPHABET.iter().position(|a| *a as char == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | index as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Some(bytes)
+}
+
+// base32_encode is RFC 4648 base32 without padding, as used in otpauth URIs
+pub(super) fn base32_encode(bytes: &[u8]) -> String {
+    let mut value = String::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for byte in bytes {
+        buffer = (buffer << 8) | u32::from(*byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            value.push(BASE32_ALPHABET[(buffer >> bits) as usize & 0x1f] as char);
+        }
+        buffer &= (1 << bits) - 1;
+    }
+    if bits > 0 {
+        value.push(BASE32_ALPHABET[(buffer << (5 - bits)) as usize & 0x1f] as char);
+    }
+    value
+}
+
+// percent_decode decodes %XX escapes, in query values + is also a space
+fn percent_decode(value: &str, query: bool) -> Result<String, Error> {
+    let invalid = || Error::InvalidOtpAuth(format!("invalid escape in '{}'", value));
+    let mut bytes = Vec::new();
+    let mut input = value.bytes();
+    while let Some(byte) = input.next() {
+        match byte {
+            b'%' => {
+                let hex: String = input.by_ref().take(2).map(char::from).collect();
+                if hex.len() != 2 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(invalid())
+                }
+                bytes.push(u8::from_str_radix(&hex, 16).map_err(|_| invalid())?);
+            },
+            b'+' if query => bytes.push(b' '),
+            _ => bytes.push(byte),
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| invalid())
+}
+
+// percent_encode escapes everything except the unreserved characters of RFC 3986
+fn percent_encode(value: &str) -> String {
+    value.bytes().map(|byte| match byte {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+        _ => format!("%{:02X}", byte),
+    }).collect()
+}
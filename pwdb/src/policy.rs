@@ -0,0 +1,271 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use rand::Rng;
+use rand::rngs::OsRng;
+use rand::seq::SliceRandom;
+
+use crate::Error;
+
+// Both the record policy and the header named policies use this field type
+const TYPE_ID: u8 = 0x10;
+
+const USE_LOWERCASE: u16 = 0x8000;
+const USE_UPPERCASE: u16 = 0x4000;
+const USE_DIGITS: u16 = 0x2000;
+const USE_SYMBOLS: u16 = 0x1000;
+const USE_HEX_DIGITS: u16 = 0x0800;
+const USE_EASY_VISION: u16 = 0x0400;
+const MAKE_PRONOUNCEABLE: u16 = 0x0200;
+
+// The character sets match those of the Password Safe desktop client
+const LOWERCASE: &str = "abcdefghijklmnopqrstuvwxyz";
+const UPPERCASE: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGITS: &str = "0123456789";
+const SYMBOLS: &str = "+-=_@#$%^&;:,.<>/~\\[](){}?!|*";
+const HEX_DIGITS: &str = "0123456789abcdef";
+// Easy vision leaves out characters which are easily confused with each other
+const EASY_VISION_LOWERCASE: &str = "abcdefghijkmnopqrstuvwxyz";
+const EASY_VISION_UPPERCASE: &str = "ABCDEFGHJKLMNPQRTUVWXY";
+const EASY_VISION_DIGITS: &str = "346789";
+const EASY_VISION_SYMBOLS: &str = "+-=_@#$%^&<>/~\\?*";
+const PRONOUNCEABLE_SYMBOLS: &str = "@&(#!|$+";
+const VOWELS: &str = "aeiou";
+const CONSONANTS: &str = "bcdfghjklmnprstvwz";
+
+// PasswordPolicy describes how passwords are generated for a record.
+// It is stored as "ffffnnnllluuudddsss", the flags followed by the length and the minimum number of
+// lowercase, uppercase, digits and symbols, all in hex. The symbols are stored in a separate field
+// for records and alongside the policy for the named policies in the header.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PasswordPolicy {
+    pub use_lowercase: bool,
+    pub use_uppercase: bool,
+    pub use_digits: bool,
+    pub use_symbols: bool,
+    // Only hex digits are used, the other character settings are ignored
+    pub hex_only: bool,
+    pub easy_vision: bool,
+    pub pronounceable: bool,
+    pub length: u16,
+    pub min_lowercase: u16,
+    pub min_uppercase: u16,
+    pub min_digits: u16,
+    pub min_symbols: u16,
+    // The symbols to use in place of the default set, empty for the default
+    pub symbols: String,
+}
+
+// The default matches the default policy of the Password Safe desktop client
+impl Default for PasswordPolicy {
+    fn default() -> PasswordPolicy {
+        PasswordPolicy {
+            use_lowercase: true,
+            use_uppercase: true,
+            use_digits: true,
+            use_symbols: true,
+            hex_only: false,
+            easy_vision: false,
+            pronounceable: false,
+            length: 12,
+            min_lowercase: 1,
+            min_uppercase: 1,
+            min_digits: 1,
+            min_symbols: 1,
+            symbols: String::new(),
+        }
+    }
+}
+
+impl PasswordPolicy {
+    // parse reads a record policy from its stored string form
+    pub(super) fn parse(value: &str) -> Result<PasswordPolicy, Error> {
+        let mut reader = crate::TextReader::new(value, TYPE_ID);
+        let policy = PasswordPolicy::read(&mut reader)?;
+        reader.end()?;
+        Ok(policy)
+    }
+
+    fn read(reader: &mut crate::TextReader) -> Result<PasswordPolicy, Error> {
+        let flags = reader.hex(4)? as u16;
+        Ok(PasswordPolicy {
+            use_lowercase: flags & USE_LOWERCASE != 0,
+            use_uppercase: flags & USE_UPPERCASE != 0,
+            use_digits: flags & USE_DIGITS != 0,
+            use_symbols: flags & USE_SYMBOLS != 0,
+            hex_only: flags & USE_HEX_DIGITS != 0,
+            easy_vision: flags & USE_EASY_VISION != 0,
+            pronounceable: flags & MAKE_PRONOUNCEABLE != 0,
+            length: reader.hex(3)? as u16,
+            min_lowercase: reader.hex(3)? as u16,
+            min_uppercase: reader.hex(3)? as u16,
+            min_digits: reader.hex(3)? as u16,
+            min_symbols: reader.hex(3)? as u16,
+            symbols: String::new(),
+        })
+    }
+
+    fn flags(&self) -> u16 {
+        let mut flags = 0;
+        for (set, flag) in [
+            (self.use_lowercase, USE_LOWERCASE),
+            (self.use_uppercase, USE_UPPERCASE),
+            (self.use_digits, USE_DIGITS),
+            (self.use_symbols, USE_SYMBOLS),
+            (self.hex_only, USE_HEX_DIGITS),
+            (self.easy_vision, USE_EASY_VISION),
+            (self.pronounceable, MAKE_PRONOUNCEABLE),
+        ].iter() {
+            if *set {
+                flags |= flag;
+            }
+        }
+        flags
+    }
+
+    // symbol_set is the symbols used for generation, the policy's own symbols if it has them
+    fn symbol_set(&self) -> &str {
+        if !self.symbols.is_empty() {
+            &self.symbols
+        } else if self.pronounceable {
+            PRONOUNCEABLE_SYMBOLS
+        } else if self.easy_vision {
+            EASY_VISION_SYMBOLS
+        } else {
+            SYMBOLS
+        }
+    }
+
+    // classes returns the characters and minimum count of each enabled character class
+    fn classes(&self) -> Vec<(Vec<char>, usize)> {
+        let (lowercase, uppercase, digits) = if self.easy_vision {
+            (EASY_VISION_LOWERCASE, EASY_VISION_UPPERCASE, EASY_VISION_DIGITS)
+        } else {
+            (LOWERCASE, UPPERCASE, DIGITS)
+        };
+        [
+            (self.use_lowercase, lowercase, self.min_lowercase),
+            (self.use_uppercase, uppercase, self.min_uppercase),
+            (self.use_digits, digits, self.min_digits),
+            (self.use_symbols, self.symbol_set(), self.min_symbols),
+        ].iter()
+            .filter(|(used, _, _)| *used)
+            .map(|(_, chars, min)| (chars.chars().collect(), *min as usize))
+            .collect()
+    }
+}
+
+// Display formats the policy as it is stored in the record field, without the symbols
+impl fmt::Display for PasswordPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04x}{:03x}{:03x}{:03x}{:03x}{:03x}", self.flags(), self.length, self.min_lowercase,
+               self.min_uppercase, self.min_digits, self.min_symbols)
+    }
+}
+
+// parse_named reads the named policies stored in the header. The format is "NN", the number of
+// policies, followed by "LL" and the name, the policy, then "LL" and its symbols for each policy.
+// NN and LL are hex.
+pub(super) fn parse_named(value: &str) -> Result<BTreeMap<String, PasswordPolicy>, Error> {
+    let mut reader = crate::TextReader::new(value, TYPE_ID);
+    let mut policies = BTreeMap::new();
+    let count = reader.hex(2)?;
+    for _ in 0..count {
+        let length = reader.hex(2)? as usize;
+        let name = reader.string(length)?;
+        let mut policy = PasswordPolicy::read(&mut reader)?;
+        let length = reader.hex(2)? as usize;
+        policy.symbols = reader.string(length)?;
+        policies.insert(name, policy);
+    }
+    reader.end()?;
+    Ok(policies)
+}
+
+// format_named is the reverse of parse_named
+pub(super) fn format_named(policies: &BTreeMap<String, PasswordPolicy>) -> String {
+    let mut value = format!("{:02x}", policies.len());
+    for (name, policy) in policies {
+        value += &format!("{:02x}{}{}{:02x}{}", name.chars().count(), name, policy,
+                          policy.symbols.chars().count(), policy.symbols);
+    }
+    value
+}
+
+// generate makes a new random password following the policy, using the OS random number generator
+pub fn generate(policy: &PasswordPolicy) -> Result<String, Error> {
+    let length = policy.length as usize;
+    if length == 0 {
+        return Err(Error::InvalidPolicy("the length is zero".to_string()))
+    }
+    if policy.hex_only {
+        let hex: Vec<char> = HEX_DIGITS.chars().collect();
+        return Ok((0..length).map(|_| random_char(&hex)).collect())
+    }
+
+    let classes = policy.classes();
+    if classes.is_empty() {
+        return Err(Error::InvalidPolicy("no character types are enabled".to_string()))
+    }
+    if classes.iter().map(|(_, min)| min).sum::<usize>() > length {
+        return Err(Error::InvalidPolicy(format!("the minimum counts add up to more than the length {}", length)))
+    }
+    if policy.pronounceable {
+        return pronounceable(policy, length)
+    }
+
+    // Fill the minimums of each class, then the rest from any class, then shuffle
+    let mut password = Vec::with_capacity(length);
+    for (chars, min) in &classes {
+        password.extend((0..*min).map(|_| random_char(chars)));
+    }
+    let all: Vec<char> = classes.iter().flat_map(|(chars, _)| chars.iter().copied()).collect();
+    while password.len() < length {
+        password.push(random_char(&all));
+    }
+    password.shuffle(&mut OsRng);
+    Ok(password.into_iter().collect())
+}
+
+// pronounceable alternates consonants and vowels, then replaces randomly chosen positions to meet
+// the minimum digits, symbols and uppercase letters.
+fn pronounceable(policy: &PasswordPolicy, length: usize) -> Result<String, Error> {
+    if !policy.use_lowercase && !policy.use_uppercase {
+        return Err(Error::InvalidPolicy("pronounceable passwords need lowercase or uppercase letters".to_string()))
+    }
+    let vowels: Vec<char> = VOWELS.chars().collect();
+    let consonants: Vec<char> = CONSONANTS.chars().collect();
+    let digits: Vec<char> = if policy.easy_vision { EASY_VISION_DIGITS } else { DIGITS }.chars().collect();
+    let symbols: Vec<char> = policy.symbol_set().chars().collect();
+
+    let start_vowel = OsRng.gen::<bool>();
+    let mut password: Vec<char> = (0..length)
+        .map(|i| random_char(if (i % 2 == 0) == start_vowel { &vowels } else { &consonants }))
+        .collect();
+
+    let mut positions: Vec<usize> = (0..length).collect();
+    positions.shuffle(&mut OsRng);
+    let mut positions = positions.into_iter();
+    let min = |used: bool, min: u16| if used { min as usize } else { 0 };
+    for position in positions.by_ref().take(min(policy.use_digits, policy.min_digits)) {
+        password[position] = random_char(&digits);
+    }
+    for position in positions.by_ref().take(min(policy.use_symbols, policy.min_symbols)) {
+        password[position] = random_char(&symbols);
+    }
+    for position in positions.by_ref().take(min(policy.use_uppercase, policy.min_uppercase)) {
+        password[position] = password[position].to_ascii_uppercase();
+    }
+    // Skip the positions which must stay lowercase then set the case of the rest
+    for position in positions.skip(min(policy.use_lowercase, policy.min_lowercase)) {
+        if !policy.use_lowercase || (policy.use_uppercase && OsRng.gen::<bool>()) {
+            password[position] = password[position].to_ascii_uppercase();
+        }
+    }
+    Ok(password.into_iter().collect())
+}
+
+// random_char picks a character, the set must not be empty
+fn random_char(chars: &[char]) -> char {
+    chars[OsRng.gen_range(0, chars.len())]
+}
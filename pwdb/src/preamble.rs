@@ -1,82 +1,161 @@
+use std::fmt;
+
 use block_modes::{BlockMode, Ecb};
 use block_modes::block_padding::NoPadding;
+use rand::RngCore;
+use rand::rngs::OsRng;
 use sha2::{Digest, Sha256};
 use twofish::Twofish;
+use zeroize::Zeroizing;
+
+use crate::Error;
 
 // TODO If this is in a crypto library that would be better than here
 // I see a blocksize trait I may be able to use
 const SHA256_SIZE: usize = 32;
+// The spec requires at least 2048 iterations, more make brute forcing the password slower
+pub(super) const MIN_ITERATIONS: u32 = 2048;
+pub(super) const DEFAULT_ITERATIONS: u32 = 10000;
+const MAX_ITERATIONS: u32 = 100000;
 
 // Preamble is all the fields in the Password Safe V3 format which are not part of the encrypted block.
-#[derive(Debug)]
+// The keys are wiped from memory when it is dropped.
 pub(super) struct Preamble {
     ///Random initial value for CBC
     pub(super) cbciv: [u8; 16],
-    pub(super) encryption_key: [u8; 32],
-    pub(super) hmac_key: [u8; 32],
+    pub(super) encryption_key: Zeroizing<[u8; 32]>,
+    pub(super) hmac_key: Zeroizing<[u8; 32]>,
     //the number of iterations on the hash function to create the stretched key
-    iter: u32,
+    pub(super) iter: u32,
     salt: [u8; 32],
-    stretched_key: [u8; SHA256_SIZE],
+    stretched_key: Zeroizing<[u8; SHA256_SIZE]>,
+}
+
+// Debug leaves out the keys
+impl fmt::Debug for Preamble {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Preamble")
+            .field("cbciv", &self.cbciv)
+            .field("iter", &self.iter)
+            .field("salt", &self.salt)
+            .finish_non_exhaustive()
+    }
 }
 
 impl Preamble {
     // new extracts the preamble fields from the given bytes. As part of this it does initial password verification.
-    pub(super) fn new(bytes: Vec<u8>, password: &str) -> Result<Preamble, String> {
+    pub(super) fn new(bytes: Vec<u8>, password: &str) -> Result<Preamble, Error> {
         if bytes.len() != 152 {
-            return Err("Expected a preamble to be exactly 152 bytes".to_string());
+            return Err(Error::Truncated("Expected a preamble to be exactly 152 bytes".to_string()));
+        }
+        if &bytes[0..4] != b"PWS3" {
+            return Err(Error::NotPwsafe3);
         }
-        match std::str::from_utf8(&bytes[0..4]) {
-            Ok(tag) => {
-                if tag != "PWS3" {
-                    return Err("Data is not a Password Safe V3 DB".to_string());
-                }
-            }
-            Err(_) => return Err("Data is not a Password Safe V3 DB".to_string()),
-        };
 
         let key_hash = &bytes[40..72];
         let salt: [u8; 32] = crate::copy_into_array(&bytes[4..36]);
         let iter = u32::from_le_bytes(crate::copy_into_array(&bytes[36..40]));
-        if iter > 100000 {
-            return Err(format!("hash function iterations seems excessive: {}", iter).to_string());
+        if iter > MAX_ITERATIONS {
+            return Err(Error::InvalidIterations(iter));
         }
         let cbciv: [u8; 16] = crate::copy_into_array(&bytes[136..152]);
 
         let stretched_key = calculate_stretch_key(password, iter, salt);
         if key_hash[..] != Sha256::digest(&stretched_key[..])[..] {
-            return Err("Invalid Password".to_string());
+            return Err(Error::WrongPassword);
         }
 
-        let keys = extract_keys(&bytes[72..136], &stretched_key);
+        let (encryption_key, hmac_key) = extract_keys(&bytes[72..136], &stretched_key);
 
         Ok(Preamble {
             cbciv,
-            encryption_key: keys.0,
-            hmac_key: keys.1,
+            encryption_key,
+            hmac_key,
             iter,
             salt,
             stretched_key,
         })
     }
+
+    // create makes a preamble for a new database with a random salt, keys and CBC IV.
+    pub(super) fn create(password: &str, iter: u32) -> Preamble {
+        let mut salt = [0u8; 32];
+        OsRng.fill_bytes(&mut salt);
+        let mut cbciv = [0u8; 16];
+        OsRng.fill_bytes(&mut cbciv);
+        let mut encryption_key = Zeroizing::new([0u8; 32]);
+        OsRng.fill_bytes(&mut encryption_key[..]);
+        let mut hmac_key = Zeroizing::new([0u8; 32]);
+        OsRng.fill_bytes(&mut hmac_key[..]);
+
+        Preamble {
+            cbciv,
+            encryption_key,
+            hmac_key,
+            iter,
+            salt,
+            stretched_key: calculate_stretch_key(password, iter, salt),
+        }
+    }
+
+    // change_password verifies the old password then stretches the new one with a fresh salt and
+    // optionally a new iteration count. The encryption and hmac keys are unchanged, they are
+    // wrapped with the new stretched key when the preamble is serialized.
+    pub(super) fn change_password(&mut self, old: &str, new: &str, iter: Option<u32>) -> Result<(), Error> {
+        if calculate_stretch_key(old, self.iter, self.salt) != self.stretched_key {
+            return Err(Error::WrongPassword);
+        }
+        if new.is_empty() {
+            return Err(Error::EmptyPassword);
+        }
+        // Files from older versions may use fewer iterations than the spec now requires, they're
+        // raised to the minimum rather than keeping the DB stuck on its old password
+        let iter = iter.unwrap_or_else(|| self.iter.max(MIN_ITERATIONS));
+        if !(MIN_ITERATIONS..=MAX_ITERATIONS).contains(&iter) {
+            return Err(Error::InvalidIterations(iter));
+        }
+
+        OsRng.fill_bytes(&mut self.salt);
+        self.iter = iter;
+        self.stretched_key = calculate_stretch_key(new, self.iter, self.salt);
+        Ok(())
+    }
+
+    // to_bytes serializes the preamble, wrapping the encryption and hmac keys with the stretched key.
+    pub(super) fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(152);
+        bytes.extend_from_slice(b"PWS3");
+        bytes.extend_from_slice(&self.salt);
+        bytes.extend_from_slice(&self.iter.to_le_bytes());
+        bytes.extend_from_slice(&Sha256::digest(&self.stretched_key[..]));
+        bytes.extend(wrap_keys(&self.encryption_key, &self.hmac_key, &self.stretched_key));
+        bytes.extend_from_slice(&self.cbciv);
+        bytes
+    }
 }
 
-fn calculate_stretch_key(password: &str, iterations: u32, salt: [u8; 32]) -> [u8; SHA256_SIZE] {
-    let salted = [password.as_bytes(), &salt].concat();
+fn calculate_stretch_key(password: &str, iterations: u32, salt: [u8; 32]) -> Zeroizing<[u8; SHA256_SIZE]> {
+    let salted = Zeroizing::new([password.as_bytes(), &salt].concat());
     let mut stretched = Sha256::digest(&salted);
     for _ in 0..iterations {
         stretched = Sha256::digest(&stretched[..]);
     }
-    stretched.into()
+    Zeroizing::new(stretched.into())
 }
 
-fn extract_keys(data: &[u8], stretched_key: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
+fn extract_keys(data: &[u8], stretched_key: &[u8; 32]) -> (Zeroizing<[u8; 32]>, Zeroizing<[u8; 32]>) {
     type TwoFishEcb = Ecb<Twofish, NoPadding>;
     let cipher = TwoFishEcb::new_var(&stretched_key[..], Default::default()).unwrap();
-    let result = cipher.decrypt_vec(data).unwrap();
-    let mut encryption_key = [0u8; 32];
-    encryption_key[..32].copy_from_slice(&result[0..32]);
-    let mut hmac_key = [0u8; 32];
-    hmac_key[..32].copy_from_slice(&result[32..64]);
+    let result = Zeroizing::new(cipher.decrypt_vec(data).unwrap());
+    let mut encryption_key = Zeroizing::new([0u8; 32]);
+    encryption_key.copy_from_slice(&result[0..32]);
+    let mut hmac_key = Zeroizing::new([0u8; 32]);
+    hmac_key.copy_from_slice(&result[32..64]);
     (encryption_key, hmac_key)
 }
+
+fn wrap_keys(encryption_key: &[u8; 32], hmac_key: &[u8; 32], stretched_key: &[u8; 32]) -> Vec<u8> {
+    type TwoFishEcb = Ecb<Twofish, NoPadding>;
+    let cipher = TwoFishEcb::new_var(&stretched_key[..], Default::default()).unwrap();
+    cipher.encrypt_vec(&Zeroizing::new([&encryption_key[..], &hmac_key[..]].concat()))
+}
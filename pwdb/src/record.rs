@@ -1,48 +1,60 @@
 use std::collections::HashMap;
+use std::fmt;
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use hmac::Mac;
 use uuid::Uuid;
 
-#[derive(Clone, Default, Debug)]
+use crate::Error;
+use crate::history::PasswordHistory;
+use crate::otp::{self, Algorithm, OneTimeCode, OtpAuth, Totp};
+use crate::policy::PasswordPolicy;
+use crate::secret::SecretString;
+
+#[derive(Clone, Default, Debug, PartialEq)]
 pub struct Record {
     pub access_time: Option<DateTime<Utc>>,
-    autotype: String,
+    pub(super) autotype: String,
     pub create_time: Option<DateTime<Utc>>,
-    credit_card_expiration: String,
-    credit_card_number: String,
-    credit_card_pin: String,
-    credit_card_verify: String,
-    double_click_action: [u8; 2],
+    pub(super) credit_card_expiration: String,
+    pub(super) credit_card_number: SecretString,
+    pub(super) credit_card_pin: SecretString,
+    pub(super) credit_card_verify: SecretString,
+    pub(super) double_click_action: [u8; 2],
     pub email: String,
     pub group: String,
-    keyboard_shortcut: [u8; 4],
+    pub(super) keyboard_shortcut: [u8; 4],
     pub mod_time: Option<DateTime<Utc>>,
     pub notes: String,
-    pub password: String,
-    password_expiry: u32,
-    password_expiry_interval: [u8; 4],
-    password_expiry_time: Option<DateTime<Utc>>,
-    password_history: String,
-    password_mod_time: Option<DateTime<Utc>>,
-    password_policy: String,
-    password_policy_name: String,
-    password_symbols: String,
-    protected_entry: u8,
-    qr_code: String,
-    run_command: String,
-    shift_double_click_action: [u8; 2],
+    pub password: SecretString,
+    pub(super) password_expiry: u32,
+    // The days from a password change until it expires, 0 if the password doesn't expire
+    pub password_expiry_interval: u32,
+    pub password_expiry_time: Option<DateTime<Utc>>,
+    pub password_history: PasswordHistory,
+    pub password_mod_time: Option<DateTime<Utc>>,
+    // The record's own policy including its symbols, which are stored in a separate field
+    pub password_policy: Option<PasswordPolicy>,
+    // The name of a policy in the header, used when the record has no policy of its own
+    pub password_policy_name: String,
+    pub(super) protected_entry: u8,
+    pub(super) qr_code: SecretString,
+    pub(super) run_command: String,
+    pub(super) shift_double_click_action: [u8; 2],
     pub title: String,
-    two_factor_key: Vec<u8>,
+    // The two factor key and its settings, which are stored in separate fields
+    pub totp: Option<Totp>,
+    // Fields this application doesn't interpret, kept so they are written back unchanged
+    pub(super) unknown_fields: Vec<(u8, Vec<u8>)>,
     pub username: String,
     pub url: String,
-    uuid: Uuid,
+    pub(super) uuid: Uuid,
 }
 
 impl Record {
     // New Parses a set of records from the given data. As data is parsed out the mac is updated with
     // the string values of the records.
-    pub(super) fn new_records(bytes: &[u8], mac: &mut crate::HmacSha256) -> Result<HashMap<Uuid, Record>, String> {
+    pub(super) fn new_records(bytes: &[u8], mac: &mut crate::HmacSha256) -> Result<HashMap<Uuid, Record>, Error> {
         let mut records: HashMap<Uuid, Record> = HashMap::new();
         let mut i: usize = 0;
         while i < bytes.len() {
@@ -55,8 +67,12 @@ impl Record {
 
     // new parses a single record from the given bytes returning the record and end position for that
     // record in the byte array
-    fn new(bytes: &[u8], mac: &mut crate::HmacSha256) -> Result<(Record, usize), String> {
+    fn new(bytes: &[u8], mac: &mut crate::HmacSha256) -> Result<(Record, usize), Error> {
         let mut r = Record::default();
+        let mut symbols = String::new();
+        let mut totp = Totp::new(&[]);
+        let mut totp_fields = Vec::new();
+        let mut totp_usable = true;
         let mut i: usize = 0;
         while i <= bytes.len() { // Generally the loop should break before this condition is hit
             let field = crate::Field::new(&bytes[i..])?;
@@ -64,101 +80,325 @@ impl Record {
             i += field.total_size;
             if i + 1 >= bytes.len() {
                 if field.type_id != 0xff {
-                    return Err("Final Header field is not expected end type".to_string())
+                    return Err(Error::Truncated("Final Record field is not expected end type".to_string()))
                 }
                 break
             }
 
             match field.type_id {
-                0x01 => r.uuid = Uuid::from_slice(&field.data).expect("Failed ot conver record UUID"),
-                0x02 => r.group = String::from_utf8(
-                    field.data.clone()
-                ).expect("Failed to convert group field to a string"),
-                0x03 => r.title = String::from_utf8(
-                    field.data.clone()
-                ).expect("Failed to convert title field to a string"),
-                0x04 => r.username = String::from_utf8(
-                    field.data.clone()
-                ).expect("Failed to convert username field to a string"),
-                0x05 => r.notes = String::from_utf8(
-                    field.data.clone()
-                ).expect("Failed to convert notes field to a string"),
-                // TODO add support for password alias and shortcuts as defined in the spec
-                0x06 => r.password = String::from_utf8(
-                    field.data.clone()
-                ).expect("Failed to convert password field to a string"),
-                0x07 => r.create_time = Some(crate::pwsafe_date(&field.data)?),
-                0x08 => r.password_mod_time = Some(crate::pwsafe_date(&field.data)?),
-                0x09 => r.access_time = Some(crate::pwsafe_date(&field.data)?),
-                0x0a => r.password_expiry_time = Some(crate::pwsafe_date(&field.data)?),
-                0x0b => continue,
-                0x0c => r.mod_time = Some(crate::pwsafe_date(&field.data)?),
-                0x0d => r.url = String::from_utf8(
-                    field.data.clone()
-                ).expect("Failed to convert url field to a string"),
-                0x0e => r.autotype = String::from_utf8(
-                    field.data.clone()
-                ).expect("Failed to convert autotype field to a string"),
-                0x0f => r.password_history = String::from_utf8(
-                    field.data.clone()
-                ).expect("Failed to convert password history field to a string"),
-                0x10 => r.password_policy = String::from_utf8(
-                    field.data.clone()
-                ).expect("Failed to convert password policy field to a string"),
-                0x11 => r.password_expiry_interval = crate::copy_into_array(&field.data[..4]),
-                0x12 => r.run_command = String::from_utf8(
-                    field.data.clone()
-                ).expect("Failed to convert run command field to a string"),
-                0x13 => r.double_click_action = crate::copy_into_array(&field.data[..2]),
-                0x14 => r.email = String::from_utf8(
-                    field.data.clone()
-                ).expect("Failed to convert email field to a string"),
-                0x15 => r.protected_entry = field.data[0],
-                0x16 => r.password_symbols = String::from_utf8(
-                    field.data.clone()
-                ).expect("Failed to convert password symbols field to a string"),
-                0x17 => r.shift_double_click_action = crate::copy_into_array(&field.data[..2]),
-                0x18 => r.password_policy_name = String::from_utf8(
-                    field.data.clone()
-                ).expect("Failed to convert password policy name field to a string"),
-                0x19 => r.keyboard_shortcut = crate::copy_into_array(&field.data[..4]),
-                0x1a => continue,
-                0x1b => r.two_factor_key = field.data.clone(),
-                0x1c => r.credit_card_number = String::from_utf8(
-                    field.data.clone()
-                ).expect("Failed to convert credit card number field to a string"),
-                0x1d => r.credit_card_expiration = String::from_utf8(
-                    field.data.clone()
-                ).expect("Failed to convert credit card expiration field to a string"),
-                0x1e => r.credit_card_verify = String::from_utf8(
-                    field.data.clone()
-                ).expect("Failed to convert credit card verify field to a string"),
-                0x1f => r.credit_card_pin = String::from_utf8(
-                    field.data.clone()
-                ).expect("Failed to convert credit card pin field to a string"),
-                0x20 => r.qr_code = String::from_utf8(
-                    field.data.clone()
-                ).expect("Failed to convert QR code field to a string"),
+                0x01 => r.uuid = Uuid::from_slice(&field.data).map_err(
+                    |_| Error::BadFieldLength { type_id: field.type_id, length: field.data.len() }
+                )?,
+                0x02 => r.group = field.string()?,
+                0x03 => r.title = field.string()?,
+                0x04 => r.username = field.string()?,
+                0x05 => r.notes = field.string()?,
+                // Aliases and shortcuts are stored in the password, see Reference
+                0x06 => r.password = field.string()?.into(),
+                0x07 => r.create_time = Some(field.date()?),
+                0x08 => r.password_mod_time = Some(field.date()?),
+                0x09 => r.access_time = Some(field.date()?),
+                0x0a => r.password_expiry_time = Some(field.date()?),
+                0x0c => r.mod_time = Some(field.date()?),
+                0x0d => r.url = field.string()?,
+                0x0e => r.autotype = field.string()?,
+                // A history which can't be parsed is kept as is but not used
+                0x0f => match field.string().ok().map(SecretString::from).and_then(|value| PasswordHistory::parse(&value).ok()) {
+                    Some(history) => r.password_history = history,
+                    None => r.unknown_fields.push((field.type_id, field.data.to_vec())),
+                },
+                // As with the history a policy which can't be parsed is kept but not used
+                0x10 => match field.string().ok().and_then(|value| PasswordPolicy::parse(&value).ok()) {
+                    Some(policy) => r.password_policy = Some(policy),
+                    None => r.unknown_fields.push((field.type_id, field.data.to_vec())),
+                },
+                // An interval pwsafe wouldn't set is kept as is but not used
+                0x11 => match u32::from_le_bytes(field.array()?) {
+                    days @ 1..=MAX_EXPIRY_INTERVAL => r.password_expiry_interval = days,
+                    _ => r.unknown_fields.push((field.type_id, field.data.to_vec())),
+                },
+                0x12 => r.run_command = field.string()?,
+                0x13 => r.double_click_action = field.array()?,
+                0x14 => r.email = field.string()?,
+                0x15 => r.protected_entry = field.array::<1>()?[0],
+                0x16 => symbols = field.string()?,
+                0x17 => r.shift_double_click_action = field.array()?,
+                0x18 => r.password_policy_name = field.string()?,
+                0x19 => r.keyboard_shortcut = field.array()?,
+                0x1b => totp.key = field.data.clone(),
+                0x1c => r.credit_card_number = field.string()?.into(),
+                0x1d => r.credit_card_expiration = field.string()?,
+                0x1e => r.credit_card_verify = field.string()?.into(),
+                0x1f => r.credit_card_pin = field.string()?.into(),
+                0x20 => r.qr_code = field.string()?.into(),
+                // TOTP settings which can't be used make the key unusable rather than the record
+                0x21 => {
+                    match field.array::<1>().ok().and_then(|[config]| Algorithm::from_config(config)) {
+                        Some(algorithm) => totp.algorithm = algorithm,
+                        None => totp_usable = false,
+                    }
+                    totp_fields.push((field.type_id, field.data.to_vec()));
+                },
+                0x22 => {
+                    match field.array::<1>() {
+                        Ok([digits]) => totp.digits = digits,
+                        Err(_) => totp_usable = false,
+                    }
+                    totp_fields.push((field.type_id, field.data.to_vec()));
+                },
+                0x23 => {
+                    match field.array::<1>() {
+                        Ok([period]) => totp.period = period,
+                        Err(_) => totp_usable = false,
+                    }
+                    totp_fields.push((field.type_id, field.data.to_vec()));
+                },
+                0x24 => {
+                    match field.date() {
+                        Ok(start) => totp.start = start,
+                        Err(_) => totp_usable = false,
+                    }
+                    totp_fields.push((field.type_id, field.data.to_vec()));
+                },
                 0xff => break,
-                _ => return Err(format!("Unknown record field type {}", field.type_id)),
+                // Includes the reserved 0x0b and 0x1a fields
+                _ => r.unknown_fields.push((field.type_id, field.data.to_vec())),
             }
 
             // For every field except end add the data to the hmac
             mac.update(&field.data);
         }
 
+        // Symbols without a policy have no meaning but are kept so they are written back unchanged
+        match &mut r.password_policy {
+            Some(policy) => policy.symbols = symbols,
+            None if !symbols.is_empty() => r.unknown_fields.push((0x16, symbols.into_bytes())),
+            None => (),
+        }
+
+        // As with symbols the TOTP settings are kept as is when there is no key, as is the key when
+        // the settings can't be used
+        if totp.key.is_empty() {
+            r.unknown_fields.extend(totp_fields);
+        } else if !totp_usable || totp.validate().is_err() {
+            r.unknown_fields.push((0x1b, totp.key.to_vec()));
+            r.unknown_fields.extend(totp_fields);
+        } else {
+            r.totp = Some(totp);
+        }
+
         // Verify required fields
         if r.uuid.is_nil() {
             r.uuid = Uuid::new_v4();
             // TODO it would be ideal to log less than perfect DBs like this
         }
-        if r.title == "" {
-            return Err("record is missing title".to_string())
+        r.validate()?;
+        Ok((r, i))
+    }
+
+    // validate checks the fields required by the spec are set
+    pub(super) fn validate(&self) -> Result<(), Error> {
+        if self.title.is_empty() {
+            return Err(Error::MissingField("record title".to_string()))
         }
-        if r.password == "" {
-            return Err(format!("record is missing password, title: '{}'", r.title))
+        if self.password.is_empty() {
+            return Err(Error::MissingField(format!("password of record '{}'", self.title)))
         }
-        Ok((r, i))
+        if let Some(totp) = &self.totp {
+            totp.validate()?;
+        }
+        Ok(())
+    }
+
+    pub fn uuid(&self) -> Uuid {
+        self.uuid
+    }
+
+    // append_notes adds lines after the existing notes, separated from them by a blank line
+    pub(super) fn append_notes(&mut self, lines: &[String]) {
+        if lines.is_empty() {
+            return
+        }
+        if !self.notes.is_empty() {
+            self.notes.push_str("\n\n");
+        }
+        self.notes.push_str(&lines.join("\n"));
+    }
+
+    // reference returns the alias or shortcut if this record's password refers to a base record
+    pub fn reference(&self) -> Option<Reference> {
+        Reference::parse(&self.password)
+    }
+
+    // base follows aliases and shortcuts to the record holding the actual password. A record which
+    // isn't a reference is its own base.
+    pub fn base<'a>(&'a self, records: &'a HashMap<Uuid, Record>) -> Result<&'a Record, Error> {
+        let mut base = self;
+        let mut seen = vec![self.uuid];
+        while let Some(reference) = base.reference() {
+            let uuid = reference.uuid();
+            if seen.contains(&uuid) {
+                return Err(Error::CyclicReference(self.uuid))
+            }
+            base = records.get(&uuid).ok_or(Error::DanglingReference(uuid))?;
+            seen.push(uuid);
+        }
+        Ok(base)
+    }
+
+    // effective_password is the password of the base record, see base
+    pub fn effective_password<'a>(&'a self, records: &'a HashMap<Uuid, Record>) -> Result<&'a str, Error> {
+        Ok(&self.base(records)?.password)
+    }
+
+    // expiry_status is whether the password has expired or will within DUE_SOON_DAYS
+    pub fn expiry_status(&self) -> ExpiryStatus {
+        let now = crate::now();
+        match self.password_expiry_time {
+            Some(expiry) if expiry <= now => ExpiryStatus::Expired,
+            Some(expiry) if expiry <= now + Duration::days(DUE_SOON_DAYS) => ExpiryStatus::DueSoon,
+            _ => ExpiryStatus::Ok,
+        }
+    }
+
+    // roll_expiry sets the expiry the interval on from now, as is done whenever the password is set.
+    // An interval beyond the range of times leaves the password without an expiry.
+    pub(super) fn roll_expiry(&mut self, now: DateTime<Utc>) {
+        if self.password_expiry_interval != 0 {
+            self.password_expiry_time = now.checked_add_signed(Duration::days(self.password_expiry_interval.into()));
+        }
+    }
+
+    // one_time_code is the current two factor code, if the record has a two factor key
+    pub fn one_time_code(&self) -> Option<OneTimeCode> {
+        self.totp.as_ref().map(|totp| totp.code())
+    }
+
+    // otpauth_uri exports the two factor key as an otpauth URI, the title is used as the issuer and
+    // the username, or title if there isn't one, as the account
+    pub fn otpauth_uri(&self) -> Option<String> {
+        let totp = self.totp.as_ref()?;
+        let account = if self.username.is_empty() { &self.title } else { &self.username };
+        Some(OtpAuth {
+            totp: totp.clone(),
+            issuer: self.title.clone(),
+            account: account.clone(),
+        }.to_string())
+    }
+
+    // set_otpauth_uri imports the two factor key from an otpauth URI. The parsed URI is returned so
+    // the issuer and account can be used for the other record fields.
+    pub fn set_otpauth_uri(&mut self, uri: &str) -> Result<OtpAuth, Error> {
+        let otpauth = OtpAuth::parse(uri)?;
+        otpauth.totp.validate()?;
+        self.totp = Some(otpauth.totp.clone());
+        Ok(otpauth)
+    }
+
+    // display_fields lists the name, value and whether it is secret for each field shown to users,
+    // this is used to compare records. The values are SecretStrings so the copies of secrets are wiped.
+    pub(super) fn display_fields(&self) -> Vec<(&'static str, SecretString, bool)> {
+        let date = |date: Option<DateTime<Utc>>| date.map(|d| d.to_rfc3339()).unwrap_or_default().into();
+        let history = self.password_history.entries.iter()
+            .map(|(time, password)| SecretString::from(format!("{} {}", time.to_rfc3339(), password.as_str())))
+            .collect::<Vec<SecretString>>();
+        let history = history.iter().map(|entry| entry.as_str()).collect::<Vec<&str>>().join(", ");
+        vec![
+            ("group", self.group.as_str().into(), false),
+            ("title", self.title.as_str().into(), false),
+            ("username", self.username.as_str().into(), false),
+            ("password", self.password.clone(), true),
+            ("notes", self.notes.as_str().into(), false),
+            ("url", self.url.as_str().into(), false),
+            ("email", self.email.as_str().into(), false),
+            ("created", date(self.create_time), false),
+            ("modified", date(self.mod_time), false),
+            ("password modified", date(self.password_mod_time), false),
+            ("password expiry", date(self.password_expiry_time), false),
+            ("password expiry interval", if self.password_expiry_interval == 0 { SecretString::default() } else { self.password_expiry_interval.to_string().into() }, false),
+            ("password history", history.into(), true),
+            ("password policy", self.password_policy.as_ref().map(|p| format!("{} {}", p, p.symbols)).unwrap_or_default().into(), false),
+            ("password policy name", self.password_policy_name.as_str().into(), false),
+            ("autotype", self.autotype.as_str().into(), false),
+            ("run command", self.run_command.as_str().into(), false),
+            ("two factor key", self.otpauth_uri().unwrap_or_default().into(), true),
+            ("credit card number", self.credit_card_number.clone(), true),
+            ("credit card expiration", self.credit_card_expiration.as_str().into(), false),
+            ("credit card verification", self.credit_card_verify.clone(), true),
+            ("credit card pin", self.credit_card_pin.clone(), true),
+            ("qr code", self.qr_code.clone(), true),
+        ]
+    }
+
+    // to_bytes serializes the record fields followed by the end field, the reverse of new.
+    // As with parsing the mac is updated with the data of each field.
+    pub(super) fn to_bytes(&self, mac: &mut crate::HmacSha256) -> Vec<u8> {
+        let mut writer = crate::FieldWriter::new(mac);
+        writer.field(0x01, self.uuid.as_bytes());
+        writer.string(0x02, &self.group);
+        writer.string(0x03, &self.title);
+        writer.string(0x04, &self.username);
+        writer.string(0x05, &self.notes);
+        writer.string(0x06, &self.password);
+        writer.date(0x07, self.create_time);
+        writer.date(0x08, self.password_mod_time);
+        writer.date(0x09, self.access_time);
+        writer.date(0x0a, self.password_expiry_time);
+        writer.date(0x0c, self.mod_time);
+        writer.string(0x0d, &self.url);
+        writer.string(0x0e, &self.autotype);
+        if self.password_history != PasswordHistory::default() {
+            writer.string(0x0f, &self.password_history.to_stored());
+        }
+        if let Some(policy) = &self.password_policy {
+            writer.string(0x10, &policy.to_string());
+        }
+        if self.password_expiry_interval != 0 {
+            writer.field(0x11, &self.password_expiry_interval.to_le_bytes());
+        }
+        writer.string(0x12, &self.run_command);
+        if self.double_click_action != [0; 2] {
+            writer.field(0x13, &self.double_click_action);
+        }
+        writer.string(0x14, &self.email);
+        if self.protected_entry != 0 {
+            writer.field(0x15, &[self.protected_entry]);
+        }
+        if let Some(policy) = &self.password_policy {
+            writer.string(0x16, &policy.symbols);
+        }
+        if self.shift_double_click_action != [0; 2] {
+            writer.field(0x17, &self.shift_double_click_action);
+        }
+        writer.string(0x18, &self.password_policy_name);
+        if self.keyboard_shortcut != [0; 4] {
+            writer.field(0x19, &self.keyboard_shortcut);
+        }
+        if let Some(totp) = &self.totp {
+            writer.field(0x1b, &totp.key);
+        }
+        writer.string(0x1c, &self.credit_card_number);
+        writer.string(0x1d, &self.credit_card_expiration);
+        writer.string(0x1e, &self.credit_card_verify);
+        writer.string(0x1f, &self.credit_card_pin);
+        writer.string(0x20, &self.qr_code);
+        // Only settings which differ from the defaults are written. SHA1, the only algorithm pwsafe
+        // defines and which validate requires, is the default so the config field is never needed.
+        if let Some(totp) = &self.totp {
+            if totp.digits != otp::DEFAULT_DIGITS {
+                writer.field(0x22, &[totp.digits]);
+            }
+            if totp.period != otp::DEFAULT_PERIOD {
+                writer.field(0x23, &[totp.period]);
+            }
+            if totp.start.timestamp() != 0 {
+                writer.date(0x24, Some(totp.start));
+            }
+        }
+        for (type_id, data) in &self.unknown_fields {
+            writer.field(*type_id, data);
+        }
+        writer.end()
     }
 
     // matches is a case insensitive search across a set of identifying record fields
@@ -171,3 +411,130 @@ impl Record {
         false
     }
 }
+
+// Reference is stored in place of the password of a record which refers to a base record.
+// The format is the base record UUID as 32 hex digits surrounded by [[ ]] for an alias or [~ ~] for
+// a shortcut.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Reference {
+    // Only the password comes from the base record
+    Alias(Uuid),
+    // Everything but the group, title and username comes from the base record
+    Shortcut(Uuid),
+}
+
+impl Reference {
+    fn parse(password: &str) -> Option<Reference> {
+        if password.len() != 36 || !password.is_ascii() {
+            return None
+        }
+        let uuid = Uuid::parse_str(&password[2..34]).ok()?;
+        match (&password[..2], &password[34..]) {
+            ("[[", "]]") => Some(Reference::Alias(uuid)),
+            ("[~", "~]") => Some(Reference::Shortcut(uuid)),
+            _ => None,
+        }
+    }
+
+    pub fn uuid(&self) -> Uuid {
+        match self {
+            Reference::Alias(uuid) | Reference::Shortcut(uuid) => *uuid,
+        }
+    }
+}
+
+// Display formats the reference as it is stored in the password field
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Reference::Alias(uuid) => write!(f, "[[{}]]", uuid.to_simple()),
+            Reference::Shortcut(uuid) => write!(f, "[~{}~]", uuid.to_simple()),
+        }
+    }
+}
+
+// card_expiration formats a credit card expiry as MM/YY, the format of the record field
+pub(super) fn card_expiration(month: u32, year: u32) -> String {
+    format!("{:02}/{:02}", month, year % 100)
+}
+
+// Passwords expiring within this many days are due soon
+pub const DUE_SOON_DAYS: i64 = 14;
+
+// The longest password expiry interval in days, as allowed by pwsafe
+pub const MAX_EXPIRY_INTERVAL: u32 = 3650;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExpiryStatus {
+    // The password doesn't expire or not within DUE_SOON_DAYS
+    Ok,
+    DueSoon,
+    Expired,
+}
+
+// RecordBuilder constructs a new Record, on build it is given a new UUID and its times are set to now.
+pub struct RecordBuilder {
+    record: Record,
+}
+
+impl RecordBuilder {
+    pub fn new(title: &str, password: &str) -> RecordBuilder {
+        RecordBuilder {
+            record: Record {
+                title: title.to_string(),
+                password: password.into(),
+                ..Default::default()
+            }
+        }
+    }
+
+    pub fn email(mut self, email: &str) -> RecordBuilder {
+        self.record.email = email.to_string();
+        self
+    }
+
+    pub fn group(mut self, group: &str) -> RecordBuilder {
+        self.record.group = group.to_string();
+        self
+    }
+
+    // password_history keeps up to max_entries previous passwords when the password is updated
+    pub fn password_history(mut self, max_entries: u8) -> RecordBuilder {
+        self.record.password_history = PasswordHistory::new(max_entries);
+        self
+    }
+
+    // password_expiry_interval makes the password expire this many days after each change, up to
+    // MAX_EXPIRY_INTERVAL
+    pub fn password_expiry_interval(mut self, days: u32) -> RecordBuilder {
+        self.record.password_expiry_interval = days.min(MAX_EXPIRY_INTERVAL);
+        self
+    }
+
+    pub fn notes(mut self, notes: &str) -> RecordBuilder {
+        self.record.notes = notes.to_string();
+        self
+    }
+
+    pub fn url(mut self, url: &str) -> RecordBuilder {
+        self.record.url = url.to_string();
+        self
+    }
+
+    pub fn username(mut self, username: &str) -> RecordBuilder {
+        self.record.username = username.to_string();
+        self
+    }
+
+    pub fn build(self) -> Result<Record, Error> {
+        let mut r = self.record;
+        r.validate()?;
+        let now = crate::now();
+        r.uuid = Uuid::new_v4();
+        r.create_time = Some(now);
+        r.mod_time = Some(now);
+        r.password_mod_time = Some(now);
+        r.roll_expiry(now);
+        Ok(r)
+    }
+}
@@ -0,0 +1,542 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use chrono::Datelike;
+
+use crate::record::Record;
+
+// Passwords are estimated by this many leading characters, longer ones are far beyond guessing anyway
+const MAX_LENGTH: usize = 100;
+// The DB key is stretched so an offline attacker is assumed to manage this many guesses a second
+const GUESSES_PER_SECOND: f64 = 1e4;
+// Each extra pattern a password is split into must cost at least this many more guesses, otherwise
+// splitting into single characters would always look cheapest
+const MIN_GUESSES_BEFORE_GROWING_SEQUENCE: f64 = 1e4;
+// Years close to now are all assumed as likely as one this many years away
+const MIN_YEAR_SPACE: i32 = 20;
+const DATE_SEPARATORS: &str = " /\\_.-";
+
+// Ranked from most to least common, one per line
+const PASSWORDS: &str = include_str!("common_passwords.txt");
+const WORDS: &str = include_str!("common_words.txt");
+
+// The characters commonly substituted for letters
+const LEET: [(char, &str); 12] = [
+    ('a', "4@"), ('b', "8"), ('c', "({[<"), ('e', "3"), ('g', "69"), ('i', "1!|"),
+    ('l', "1|7"), ('o', "0"), ('s', "$5"), ('t', "+7"), ('x', "%"), ('z', "2"),
+];
+
+// The rows of a US keyboard, each key as its unshifted then shifted character. The rows below the
+// first start one key in and each is offset half a key right of the one above.
+const QWERTY: [&str; 4] = [
+    "`~1!2@3#4$5%6^7&8*9(0)-_=+",
+    "qQwWeErRtTyYuUiIoOpP[{]}\\|",
+    "aAsSdDfFgGhHjJkKlL;:'\"",
+    "zZxXcCvVbBnNmM,<.>/?",
+];
+// The offsets of the neighbours of a key on the slanted keyboard, the index is the direction
+const NEIGHBOURS: [(i32, i32); 6] = [(-1, 0), (0, -1), (1, -1), (1, 0), (0, 1), (-1, 1)];
+
+// Strength is a zxcvbn style estimate of how guessable a password is
+#[derive(Clone, Debug, PartialEq)]
+pub struct Strength {
+    // The estimated number of guesses an attacker needs to find the password
+    pub guesses: f64,
+    // From 0, guessable in under a thousand tries, to 4, needing more than ten billion
+    pub score: u8,
+    // What makes the password guessable, only given for scores below 3
+    pub warning: Option<&'static str>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Pattern {
+    CommonPassword(usize),
+    Word,
+    UserInput,
+    Spatial,
+    Sequence,
+    Repeat,
+    Date,
+    BruteForce,
+}
+
+// Match is a pattern found in the characters from i to j inclusive
+#[derive(Clone, Debug)]
+struct Match {
+    i: usize,
+    j: usize,
+    guesses: f64,
+    pattern: Pattern,
+}
+
+struct Estimator<'a> {
+    dictionaries: Vec<(Pattern, HashMap<&'a str, usize>)>,
+    // The length of the longest dictionary entry
+    longest: usize,
+    // The keyboard position of each character and whether it is shifted
+    keys: HashMap<char, (i32, i32, bool)>,
+    // The average number of neighbours of a key
+    degree: f64,
+    year: i32,
+}
+
+// estimate scores a password, the user inputs are strings such as the record title or username an
+// attacker would try first
+pub fn estimate(password: &str, user_inputs: &[&str]) -> Strength {
+    let mut inputs: Vec<String> = Vec::new();
+    for input in user_inputs {
+        let input = input.to_lowercase();
+        if input.is_empty() {
+            continue
+        }
+        inputs.extend(input.split(|c: char| !c.is_alphanumeric()).filter(|word| word.len() > 2).map(String::from));
+        inputs.push(input);
+    }
+    let estimator = Estimator::new(&inputs);
+    let chars: Vec<char> = password.chars().take(MAX_LENGTH).collect();
+    let (guesses, sequence) = estimator.most_guessable(&chars);
+    let score = score(guesses);
+    let warning = if score < 3 { warning(&sequence) } else { None };
+    Strength { guesses, score, warning }
+}
+
+impl Record {
+    // strength estimates the record's password, trying the other fields of the record as words
+    pub fn strength(&self) -> Strength {
+        estimate(&self.password, &[self.title.as_str(), self.username.as_str(), self.email.as_str(), self.url.as_str(), self.group.as_str()])
+    }
+}
+
+impl Strength {
+    // crack_seconds is how long an offline attack on a stolen DB would take to find the password
+    pub fn crack_seconds(&self) -> f64 {
+        self.guesses / GUESSES_PER_SECOND
+    }
+
+    pub fn crack_time(&self) -> String {
+        let seconds = self.crack_seconds();
+        let units = [("second", 1.0), ("minute", 60.0), ("hour", 3600.0), ("day", 86400.0), ("month", 2_678_400.0), ("year", 32_140_800.0)];
+        if seconds < 1.0 {
+            return "less than a second".to_string()
+        }
+        if seconds >= 100.0 * 32_140_800.0 {
+            return "centuries".to_string()
+        }
+        let (unit, size) = units.iter().rev().find(|(_, size)| seconds >= *size).expect("seconds is at least 1");
+        let count = (seconds / size).round();
+        format!("{} {}{}", count, unit, if count == 1.0 { "" } else { "s" })
+    }
+
+    pub fn label(&self) -> &'static str {
+        ["very weak", "weak", "fair", "good", "strong"][self.score as usize]
+    }
+}
+
+// Display gives the label and crack time followed by the warning if there is one
+impl fmt::Display for Strength {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}, could be cracked in {}", self.label(), self.crack_time())?;
+        if let Some(warning) = self.warning {
+            write!(f, ". {}", warning)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Estimator<'a> {
+    fn new(user_inputs: &'a [String]) -> Estimator<'a> {
+        let ranked = |words: &'a str| words.lines().enumerate().map(|(rank, word)| (word, rank + 1)).collect();
+        let dictionaries: Vec<(Pattern, HashMap<&str, usize>)> = vec![
+            (Pattern::CommonPassword(0), ranked(PASSWORDS)),
+            (Pattern::Word, ranked(WORDS)),
+            (Pattern::UserInput, user_inputs.iter().enumerate().map(|(rank, word)| (word.as_str(), rank + 1)).collect()),
+        ];
+        let longest = dictionaries.iter().flat_map(|(_, words)| words.keys()).map(|word| word.chars().count()).max().unwrap_or(0);
+
+        let mut keys = HashMap::new();
+        for (y, row) in QWERTY.iter().enumerate() {
+            let start = if y == 0 { 0 } else { 1 };
+            for (index, key) in row.chars().enumerate() {
+                keys.insert(key, (start + index as i32 / 2, y as i32, index % 2 == 1));
+            }
+        }
+
+        let neighbours: usize = keys.values().filter(|key| !key.2)
+            .map(|key| NEIGHBOURS.iter().filter(|(x, y)| keys.values().any(|other| (other.0, other.1) == (key.0 + x, key.1 + y))).count())
+            .sum();
+        let degree = neighbours as f64 / (keys.len() / 2) as f64;
+
+        Estimator { dictionaries, longest, keys, degree, year: crate::now().year() }
+    }
+
+    // most_guessable finds the sequence of non-overlapping matches covering the password which needs
+    // the fewest guesses in total, filling the gaps with brute force
+    fn most_guessable(&self, chars: &[char]) -> (f64, Vec<Match>) {
+        let n = chars.len();
+        if n == 0 {
+            return (1.0, Vec::new())
+        }
+        let mut matches = self.matches(chars);
+        for m in &mut matches {
+            let length = m.j - m.i + 1;
+            if length < n {
+                m.guesses = m.guesses.max(if length == 1 { 10.0 } else { 50.0 });
+            }
+        }
+        for i in 0..n {
+            for j in i..n {
+                let guesses = 10f64.powi((j - i + 1) as i32).max(if i == j { 11.0 } else { 51.0 });
+                matches.push(Match { i, j, guesses, pattern: Pattern::BruteForce });
+            }
+        }
+
+        // best[k][l] is the smallest product of guesses of l matches covering the first k + 1
+        // characters along with the index of the last match
+        let mut best: Vec<Vec<Option<(f64, usize)>>> = vec![vec![None; n + 1]; n];
+        let mut by_end: Vec<Vec<usize>> = vec![Vec::new(); n];
+        for (index, m) in matches.iter().enumerate() {
+            by_end[m.j].push(index);
+        }
+        for k in 0..n {
+            for &index in &by_end[k] {
+                let m = &matches[index];
+                let mut candidates = Vec::new();
+                if m.i == 0 {
+                    candidates.push((1, m.guesses));
+                } else {
+                    for (l, previous) in best[m.i - 1].iter().enumerate() {
+                        if let Some((product, _)) = previous {
+                            candidates.push((l + 1, product * m.guesses));
+                        }
+                    }
+                }
+                for (l, product) in candidates {
+                    if best[k][l].is_none_or(|(current, _)| product < current) {
+                        best[k][l] = Some((product, index));
+                    }
+                }
+            }
+        }
+
+        let total = |l: usize, product: f64| factorial(l) * product + MIN_GUESSES_BEFORE_GROWING_SEQUENCE.powi(l as i32 - 1);
+        let (mut l, guesses) = best[n - 1].iter().enumerate()
+            .filter_map(|(l, entry)| entry.map(|(product, _)| (l, total(l, product))))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .expect("brute force covers the whole password");
+        let mut sequence = Vec::new();
+        let mut k = n - 1;
+        loop {
+            let m = &matches[best[k][l].expect("the sequence was recorded").1];
+            sequence.push(m.clone());
+            if m.i == 0 {
+                break
+            }
+            k = m.i - 1;
+            l -= 1;
+        }
+        sequence.reverse();
+        (guesses, sequence)
+    }
+
+    fn matches(&self, chars: &[char]) -> Vec<Match> {
+        let mut matches = self.dictionary_matches(chars);
+        let reversed: Vec<char> = chars.iter().rev().cloned().collect();
+        for m in self.dictionary_matches(&reversed) {
+            let n = chars.len();
+            matches.push(Match { i: n - 1 - m.j, j: n - 1 - m.i, guesses: m.guesses * 2.0, pattern: m.pattern });
+        }
+        matches.extend(self.spatial_matches(chars));
+        matches.extend(sequence_matches(chars));
+        matches.extend(self.repeat_matches(chars));
+        matches.extend(self.date_matches(chars));
+        matches
+    }
+
+    // dictionary_matches finds the dictionary words in the password, in any case and with letters
+    // swapped for look-alike characters
+    fn dictionary_matches(&self, chars: &[char]) -> Vec<Match> {
+        let lower: Vec<char> = chars.iter().map(|c| c.to_lowercase().next().unwrap_or(*c)).collect();
+        let mut matches = Vec::new();
+        for i in 0..chars.len() {
+            for j in (i + 2)..chars.len().min(i + self.longest) {
+                let token = &lower[i..=j];
+                let mut candidates = vec![(token.iter().collect::<String>(), 1.0)];
+                candidates.extend(unleet(token));
+                for (word, leet_variations) in candidates {
+                    for (pattern, words) in &self.dictionaries {
+                        if let Some(rank) = words.get(word.as_str()) {
+                            let pattern = match pattern {
+                                Pattern::CommonPassword(_) => Pattern::CommonPassword(*rank),
+                                pattern => pattern.clone(),
+                            };
+                            let guesses = *rank as f64 * uppercase_variations(&chars[i..=j]) * leet_variations;
+                            matches.push(Match { i, j, guesses, pattern });
+                        }
+                    }
+                }
+            }
+        }
+        matches
+    }
+
+    // spatial_matches finds runs of three or more neighbouring keys
+    fn spatial_matches(&self, chars: &[char]) -> Vec<Match> {
+        let starts = self.keys.len() as f64;
+        let mut matches = Vec::new();
+        let mut i = 0;
+        while i + 2 < chars.len() {
+            let (mut j, mut turns, mut direction) = (i, 0, None);
+            let mut shifted = self.keys.get(&chars[i]).is_some_and(|key| key.2) as usize;
+            while let Some(next) = chars.get(j + 1).and_then(|next| self.direction(chars[j], *next)) {
+                if direction != Some(next) {
+                    turns += 1;
+                    direction = Some(next);
+                }
+                shifted += self.keys[&chars[j + 1]].2 as usize;
+                j += 1;
+            }
+            let length = j - i + 1;
+            if length > 2 {
+                let mut guesses = 0.0;
+                for l in 2..=length {
+                    for t in 1..=turns.min(l - 1) {
+                        guesses += binomial(l - 1, t - 1) * starts * self.degree.powi(t as i32);
+                    }
+                }
+                guesses *= case_variations(shifted, length - shifted);
+                matches.push(Match { i, j, guesses, pattern: Pattern::Spatial });
+            }
+            i = if j > i { j } else { i + 1 };
+        }
+        matches
+    }
+
+    // direction is the direction from the key of one character to the key of the next if they are
+    // neighbours
+    fn direction(&self, from: char, to: char) -> Option<usize> {
+        let (from, to) = (self.keys.get(&from)?, self.keys.get(&to)?);
+        NEIGHBOURS.iter().position(|(x, y)| (from.0 + x, from.1 + y) == (to.0, to.1))
+    }
+
+    // repeat_matches finds the longest run of a repeated string starting at each character
+    fn repeat_matches(&self, chars: &[char]) -> Vec<Match> {
+        let mut matches = Vec::new();
+        for i in 0..chars.len() {
+            let mut longest: Option<(usize, usize)> = None;
+            for base in 1..=(chars.len() - i) / 2 {
+                let mut count = 1;
+                while chars.len() >= i + (count + 1) * base && chars[i + count * base..i + (count + 1) * base] == chars[i..i + base] {
+                    count += 1;
+                }
+                if count > 1 && longest.is_none_or(|(b, c)| base * count > b * c) {
+                    longest = Some((base, count));
+                }
+            }
+            if let Some((base, count)) = longest {
+                let (base_guesses, _) = self.most_guessable(&chars[i..i + base]);
+                matches.push(Match { i, j: i + base * count - 1, guesses: base_guesses * count as f64, pattern: Pattern::Repeat });
+            }
+        }
+        matches
+    }
+
+    // date_matches finds recent years and dates of day, month and year in any order, with or without
+    // separators
+    fn date_matches(&self, chars: &[char]) -> Vec<Match> {
+        let mut matches = Vec::new();
+        for i in 0..chars.len() {
+            for j in (i + 3)..chars.len().min(i + 10) {
+                let token: String = chars[i..=j].iter().collect();
+                let years = if token.bytes().all(|c| c.is_ascii_digit()) {
+                    let mut years = Vec::new();
+                    if let Some(year) = token.parse().ok().filter(|year| token.len() == 4 && (1900..2100).contains(year)) {
+                        matches.push(Match { i, j, guesses: self.year_space(year), pattern: Pattern::Date });
+                    }
+                    for split in date_splits(token.len()) {
+                        let parts = [&token[..split.0], &token[split.0..split.1], &token[split.1..]];
+                        years.extend(date_year(parts.map(|part| part.parse().unwrap_or(0))));
+                    }
+                    years.into_iter().map(|year| self.year_space(year) * 365.0).collect()
+                } else {
+                    let separators: Vec<(usize, char)> = token.char_indices().filter(|(_, c)| !c.is_ascii_digit()).collect();
+                    match separators[..] {
+                        [(first, a), (second, b)] if a == b && DATE_SEPARATORS.contains(a) => {
+                            let parts = [&token[..first], &token[first + 1..second], &token[second + 1..]];
+                            let lengths_valid = parts[0].len() <= 4 && parts[1].len() <= 2 && parts[2].len() <= 4;
+                            if lengths_valid && parts.iter().all(|part| !part.is_empty()) {
+                                date_year(parts.map(|part| part.parse().unwrap_or(0))).map(|year| self.year_space(year) * 365.0 * 4.0).into_iter().collect()
+                            } else {
+                                Vec::new()
+                            }
+                        },
+                        _ => Vec::new(),
+                    }
+                };
+                if let Some(guesses) = years.into_iter().min_by(|a: &f64, b| a.total_cmp(b)) {
+                    matches.push(Match { i, j, guesses, pattern: Pattern::Date });
+                }
+            }
+        }
+        matches
+    }
+
+    fn year_space(&self, year: i32) -> f64 {
+        (year - self.year).abs().max(MIN_YEAR_SPACE) as f64
+    }
+}
+
+// sequence_matches finds runs of three or more letters or digits with the same step between each,
+// such as abc, 7531 or ZYX
+fn sequence_matches(chars: &[char]) -> Vec<Match> {
+    let class = |c: char| if c.is_ascii_lowercase() { 1 } else if c.is_ascii_uppercase() { 2 } else if c.is_ascii_digit() { 3 } else { 0 };
+    let step = |i: usize| chars[i + 1] as i64 - chars[i] as i64;
+    let mut matches = Vec::new();
+    let mut i = 0;
+    while i + 2 < chars.len() {
+        let delta = step(i);
+        let mut j = i + 1;
+        while j + 1 < chars.len() && step(j) == delta {
+            j += 1;
+        }
+        let same_class = class(chars[i]) != 0 && chars[i..=j].iter().all(|c| class(*c) == class(chars[i]));
+        if j - i >= 2 && delta != 0 && delta.abs() <= 5 && same_class {
+            let first = chars[i];
+            let mut base = if "aAzZ019".contains(first) { 4.0 } else if first.is_ascii_digit() { 10.0 } else { 26.0 };
+            if delta < 0 {
+                base *= 2.0;
+            }
+            matches.push(Match { i, j, guesses: base * (j - i + 1) as f64, pattern: Pattern::Sequence });
+        }
+        i = j;
+    }
+    matches
+}
+
+// unleet gives the words the token could be with its look-alike characters swapped back to letters,
+// each with the number of variations the substitutions add
+fn unleet(token: &[char]) -> Vec<(String, f64)> {
+    let mut subs: Vec<(char, Vec<char>)> = Vec::new();
+    for c in token {
+        let letters: Vec<char> = LEET.iter().filter(|(_, similar)| similar.contains(*c)).map(|(letter, _)| *letter).collect();
+        if !letters.is_empty() && !subs.iter().any(|(sub, _)| sub == c) {
+            subs.push((*c, letters));
+        }
+    }
+    // Each character with two possible letters doubles the candidates, limit them for odd passwords
+    if subs.is_empty() || subs.len() > 6 {
+        return Vec::new()
+    }
+
+    let mut candidates: Vec<Vec<(char, char)>> = vec![Vec::new()];
+    for (sub, letters) in &subs {
+        candidates = candidates.iter()
+            .flat_map(|chosen| letters.iter().map(move |letter| {
+                let mut chosen = chosen.clone();
+                chosen.push((*sub, *letter));
+                chosen
+            }))
+            .collect();
+    }
+    candidates.into_iter().map(|chosen| {
+        let word = token.iter().map(|c| chosen.iter().find(|(sub, _)| sub == c).map_or(*c, |(_, letter)| *letter)).collect();
+        let variations = chosen.iter().map(|(sub, letter)| {
+            let subbed = token.iter().filter(|c| *c == sub).count();
+            let unsubbed = token.iter().filter(|c| *c == letter).count();
+            case_variations(subbed, unsubbed)
+        }).product();
+        (word, variations)
+    }).collect()
+}
+
+// uppercase_variations is the number of ways the word could have been capitalised, first or last
+// letter and all caps being the most common
+fn uppercase_variations(token: &[char]) -> f64 {
+    let upper = token.iter().filter(|c| c.is_uppercase()).count();
+    let lower = token.iter().filter(|c| c.is_lowercase()).count();
+    if upper == 0 {
+        return 1.0
+    }
+    let first_or_last = upper == 1 && (token[0].is_uppercase() || token[token.len() - 1].is_uppercase());
+    if lower == 0 || first_or_last {
+        return 2.0
+    }
+    case_variations(upper, lower)
+}
+
+// case_variations is the number of ways of changing up to half of the characters of a token where
+// changed of them were, 2 if all or none were changed
+fn case_variations(changed: usize, unchanged: usize) -> f64 {
+    if changed == 0 || unchanged == 0 {
+        return 2.0
+    }
+    (1..=changed.min(unchanged)).map(|i| binomial(changed + unchanged, i)).sum()
+}
+
+// date_splits are the ways a date of this many digits splits into three parts, as the end of the
+// first two parts
+fn date_splits(length: usize) -> &'static [(usize, usize)] {
+    match length {
+        4 => &[(1, 2), (2, 3)],
+        5 => &[(1, 3), (2, 3)],
+        6 => &[(1, 2), (2, 4), (4, 5)],
+        7 => &[(1, 3), (2, 3), (4, 5), (4, 6)],
+        8 => &[(2, 4), (4, 6)],
+        _ => &[],
+    }
+}
+
+// date_year gives the year of three numbers if they are a valid day, month and year with the year
+// first or last
+fn date_year(parts: [i32; 3]) -> Option<i32> {
+    if parts[1] < 1 || parts[1] > 31 {
+        return None
+    }
+    let over_31 = parts.iter().filter(|part| **part > 31).count();
+    let over_12 = parts.iter().filter(|part| **part > 12).count();
+    let under_1 = parts.iter().filter(|part| **part < 1).count();
+    if over_31 >= 2 || over_12 == 3 || under_1 >= 2 {
+        return None
+    }
+    let day_month = |a: i32, b: i32| (1..=31).contains(&a) && (1..=12).contains(&b) || (1..=31).contains(&b) && (1..=12).contains(&a);
+    let splits = [(parts[2], parts[0], parts[1]), (parts[0], parts[1], parts[2])];
+    for (year, a, b) in splits {
+        if (1000..=2050).contains(&year) {
+            return if day_month(a, b) { Some(year) } else { None }
+        }
+    }
+    splits.iter()
+        .find(|(year, a, b)| *year <= 99 && day_month(*a, *b))
+        .map(|(year, _, _)| if *year > 50 { 1900 + year } else { 2000 + year })
+}
+
+fn score(guesses: f64) -> u8 {
+    [1e3, 1e6, 1e8, 1e10].iter().filter(|threshold| guesses >= *threshold + 5.0).count() as u8
+}
+
+// warning explains the longest pattern of a guessable password
+fn warning(sequence: &[Match]) -> Option<&'static str> {
+    let longest = sequence.iter()
+        .filter(|m| m.pattern != Pattern::BruteForce)
+        .max_by_key(|m| m.j - m.i)?;
+    match longest.pattern {
+        Pattern::CommonPassword(rank) if sequence.len() == 1 && rank <= 10 => Some("This is a top-10 common password"),
+        Pattern::CommonPassword(_) if sequence.len() == 1 => Some("This is a very common password"),
+        Pattern::CommonPassword(_) => Some("This is similar to a commonly used password"),
+        Pattern::Word if sequence.len() == 1 => Some("A word by itself is easy to guess"),
+        Pattern::Word => Some("Common words are easy to guess"),
+        Pattern::UserInput => Some("The record's title, username, email or URL is easy to guess"),
+        Pattern::Spatial => Some("Rows and patterns of keys on the keyboard are easy to guess"),
+        Pattern::Sequence => Some("Sequences like abc or 6543 are easy to guess"),
+        Pattern::Repeat => Some("Repeats like aaa or abcabc are easy to guess"),
+        Pattern::Date => Some("Dates and years are easy to guess"),
+        Pattern::BruteForce => None,
+    }
+}
+
+fn binomial(n: usize, k: usize) -> f64 {
+    (1..=k).fold(1.0, |result, i| result * (n + 1 - i) as f64 / i as f64)
+}
+
+fn factorial(n: usize) -> f64 {
+    (1..=n).map(|i| i as f64).product()
+}
@@ -1,23 +1,18 @@
+use proptest::prelude::*;
+
 use crate::*;
+use crate::record::{RecordBuilder, Reference};
 
 #[test]
 fn new_empty_db() {
     let db = Database::new(&Vec::new(), "123");
-    let msg = match db {
-        Ok(_) => "success".to_string(),
-        Err(error) => error,
-    };
-    assert_eq!(msg, "DB data is less than minimum size")
+    assert_eq!(db.err(), Some(Error::Truncated("DB data is less than minimum size".to_string())))
 }
 
 #[test]
 fn new_invalid_data() {
     let db = Database::new(&(0..200).collect(), "123");
-    let msg = match db {
-        Ok(_) => "success".to_string(),
-        Err(error) => error,
-    };
-    assert_eq!(msg, "Data is not a Password Safe V3 DB")
+    assert_eq!(db.err(), Some(Error::NotPwsafe3))
 }
 
 #[test]
@@ -25,11 +20,7 @@ fn wrong_passphrase() {
     let encrypted = include_bytes!("../test_dbs/simple.dat");
 
     let db = Database::new(&encrypted.to_vec(), "wrong");
-    let msg = match db {
-        Ok(_) => "success".to_string(),
-        Err(error) => error,
-    };
-    assert_eq!(msg, "Invalid Password")
+    assert_eq!(db.err(), Some(Error::WrongPassword))
 }
 
 #[test]
@@ -37,11 +28,7 @@ fn bad_hmac() {
     let encrypted = include_bytes!("../test_dbs/badHMAC.dat");
 
     let db = Database::new(&encrypted.to_vec(), "password");
-    let msg = match db {
-        Ok(_) => "success".to_string(),
-        Err(error) => error,
-    };
-    assert_eq!(msg, "HMAC mismatch!")
+    assert_eq!(db.err(), Some(Error::HmacMismatch))
 }
 
 #[test]
@@ -105,4 +92,1033 @@ fn record_search() {
     assert_eq!(db.record_search("https").len(), 1);
 }
 
+#[test]
+fn round_trip_small_db() {
+    let encrypted = include_bytes!("../test_dbs/three.dat");
+
+    let db = Database::new(&encrypted.to_vec(), "three3#;").unwrap();
+    let db2 = Database::new(&db.to_bytes(), "three3#;").unwrap();
+    assert_eq!(db.records, db2.records);
+    assert_eq!(db.header.name, db2.header.name);
+    assert_eq!(db.header.last_save, db2.header.last_save);
+
+    // The original file has no version field so compare from the first re-encrypted copy
+    let db3 = Database::new(&db2.to_bytes(), "three3#;").unwrap();
+    assert_eq!(db2.header, db3.header);
+    assert_eq!(db2.records, db3.records);
+}
+
+#[test]
+fn save_db() {
+    let encrypted = include_bytes!("../test_dbs/simple.dat");
+
+    let mut db = Database::new(&encrypted.to_vec(), "password").unwrap();
+    let last_save = db.header.last_save;
+    let saved = db.save();
+    assert_ne!(db.header.last_save, last_save);
+
+    let db2 = Database::new(&saved, "password").unwrap();
+    assert_eq!(db.header.last_save, db2.header.last_save);
+    assert_eq!(db.records, db2.records);
+    assert_eq!(&saved[..4], b"PWS3");
+}
+
+#[test]
+fn create_db() {
+    let db = Database::create("new password", "new db", "a brand new DB").unwrap();
+    let encrypted = db.to_bytes();
+
+    assert_eq!(Database::new(&encrypted, "wrong").err(), Some(Error::WrongPassword));
+
+    let db2 = Database::new(&encrypted, "new password").unwrap();
+    assert_eq!(db.header, db2.header);
+    assert_eq!(db2.header.name, "new db");
+    assert_eq!(db2.header.description, "a brand new DB");
+    assert!(db2.records.is_empty());
+
+    assert_eq!(Database::create("", "new db", "").err(), Some(Error::EmptyPassword));
+}
+
+#[test]
+fn change_password() {
+    let encrypted = include_bytes!("../test_dbs/three.dat");
+
+    let mut db = Database::new(&encrypted.to_vec(), "three3#;").unwrap();
+    assert_eq!(db.change_password("wrong", "new password", None).err(), Some(Error::WrongPassword));
+
+    assert_eq!(db.change_password("three3#;", "new password", Some(100)).err(), Some(Error::InvalidIterations(100)));
+
+    db.change_password("three3#;", "new password", Some(20000)).unwrap();
+    assert!(db.header.last_master_password_update.is_some());
+
+    let reencrypted = db.to_bytes();
+    assert_eq!(u32::from_le_bytes(copy_into_array(&reencrypted[36..40])), 20000);
+    assert_eq!(Database::new(&reencrypted, "three3#;").err(), Some(Error::WrongPassword));
+
+    let db2 = Database::new(&reencrypted, "new password").unwrap();
+    assert_eq!(db.header.last_master_password_update, db2.header.last_master_password_update);
+    assert_eq!(db.records, db2.records);
+
+    // A legacy DB with fewer iterations than the minimum is raised to it when they aren't given
+    let mut legacy = Database::create("old password", "legacy", "").unwrap();
+    legacy.preamble = Preamble::create("old password", 1000);
+    let mut legacy = Database::new(&legacy.save(), "old password").unwrap();
+    legacy.change_password("old password", "new password", None).unwrap();
+    let reencrypted = legacy.to_bytes();
+    assert_eq!(u32::from_le_bytes(copy_into_array(&reencrypted[36..40])), MIN_ITERATIONS);
+    assert!(Database::new(&reencrypted, "new password").is_ok());
+}
+
+#[test]
+fn record_crud() {
+    let mut db = Database::create("password", "crud", "").unwrap();
+    assert!(!db.is_dirty());
+
+    assert_eq!(RecordBuilder::new("no password", "").build().err(), Some(Error::MissingField("password of record 'no password'".to_string())));
+
+    let record = RecordBuilder::new("new entry", "secret")
+        .group("group1")
+        .username("user")
+        .url("https://example.com")
+        .build().unwrap();
+    assert!(record.create_time.is_some());
+    let uuid = db.add_record(record.clone()).unwrap();
+    assert!(db.is_dirty());
+    assert!(db.add_record(record).is_err());
+
+    let mut record = db.record(&uuid).unwrap().clone();
+    record.password = "new secret".into();
+    record.password_mod_time = None;
+    db.update_record(record).unwrap();
+    assert!(db.record(&uuid).unwrap().password_mod_time.is_some());
+
+    let mut record = db.record(&uuid).unwrap().clone();
+    record.title = String::new();
+    assert_eq!(db.update_record(record).err(), Some(Error::MissingField("record title".to_string())));
+
+    let saved = db.save();
+    assert!(!db.is_dirty());
+    let mut db2 = Database::new(&saved, "password").unwrap();
+    assert_eq!(db.records(), db2.records());
+    assert_eq!(db2.record(&uuid).unwrap().password, "new secret");
+
+    let deleted = db2.delete_record(&uuid).unwrap();
+    assert_eq!(deleted.title, "new entry");
+    assert!(db2.records().is_empty());
+    assert!(db2.delete_record(&uuid).is_err());
+}
+
+#[test]
+fn debug_redacts_secrets() {
+    let encrypted = include_bytes!("../test_dbs/three.dat");
+    let mut db = Database::new(&encrypted.to_vec(), "three3#;").unwrap();
+    let uuid = *db.records().keys().next().unwrap();
+    let mut record = db.record(&uuid).unwrap().clone();
+    record.password = "new secret".into();
+    record.credit_card_number = "4111111111111111".into();
+    record.totp = Some(otp::Totp::new(b"12345678901234567890"));
+    db.update_record(record).unwrap();
+
+    let debug = format!("{:?}", db);
+    for secret in &["three1!@$%^&*()", "new secret", "4111111111111111", "49, 50, 51"] {
+        assert!(!debug.contains(secret), "{} is in the Debug output", secret);
+    }
+    assert!(!debug.contains("encryption_key") && !debug.contains("hmac_key"));
+    assert!(debug.contains("password: <redacted>") && debug.contains("title: \"three entry 1\""));
+}
+
+#[test]
+fn truncated_fields() {
+    // A field claiming more data than remains
+    let db = Database::new(&fuzz_db("password", &[0xff, 0xff, 0xff, 0xff, 0x09]), "password");
+    assert!(matches!(db.err(), Some(Error::Truncated(_))));
+
+    // A record field too short for its type, a 1 byte double click action, then a header end
+    // followed by a record containing only the short field
+    let mut data = vec![4, 0, 0, 0, 0x04, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    data.extend(&[0, 0, 0, 0, 0xff, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    data.extend(&[1, 0, 0, 0, 0x13, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    data.extend(&[0, 0, 0, 0, 0xff, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    let db = Database::new(&fuzz_db("password", &data), "password");
+    assert_eq!(db.err(), Some(Error::BadFieldLength { type_id: 0x13, length: 1 }));
+
+    // Invalid UTF-8 in a record title
+    let mut data = vec![4, 0, 0, 0, 0x04, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    data.extend(&[0, 0, 0, 0, 0xff, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    data.extend(&[2, 0, 0, 0, 0x03, 0xc3, 0x28, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    data.extend(&[0, 0, 0, 0, 0xff, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    let db = Database::new(&fuzz_db("password", &data), "password");
+    assert_eq!(db.err(), Some(Error::InvalidUtf8 { type_id: 0x03 }));
+}
+
+#[test]
+fn missing_hmac() {
+    let encrypted = include_bytes!("../test_dbs/simple.dat");
+
+    let db = Database::new(&encrypted[..encrypted.len() - 32].to_vec(), "password");
+    assert!(matches!(db.err(), Some(Error::Truncated(_))));
+}
+
+#[test]
+fn preserve_unknown_fields() {
+    let mut db = Database::create("password", "unknown", "").unwrap();
+    db.header.unknown_fields = vec![(0x05, b"deprecated".to_vec()), (0x30, vec![1, 2, 3])];
+    let mut record = RecordBuilder::new("entry", "secret").build().unwrap();
+    record.unknown_fields = vec![(0x0b, vec![0]), (0x1a, vec![]), (0x40, b"from a newer client".to_vec())];
+    let uuid = db.add_record(record).unwrap();
+
+    let db2 = Database::new(&db.to_bytes(), "password").unwrap();
+    assert_eq!(db.header, db2.header);
+    assert_eq!(db2.record(&uuid).unwrap().unknown_fields.len(), 3);
+    assert_eq!(db.records(), db2.records());
+}
+
+#[test]
+fn alias_and_shortcut() {
+    let mut db = Database::create("password", "references", "").unwrap();
+    let base = db.add_record(RecordBuilder::new("base", "base password").url("https://base.com").build().unwrap()).unwrap();
+    let alias_password = Reference::Alias(base).to_string();
+    assert_eq!(alias_password.len(), 36);
+    let alias = db.add_record(RecordBuilder::new("alias", &alias_password).build().unwrap()).unwrap();
+    let shortcut_password = Reference::Shortcut(base).to_string();
+    let shortcut = db.add_record(RecordBuilder::new("shortcut", &shortcut_password).build().unwrap()).unwrap();
+
+    let db = Database::new(&db.to_bytes(), "password").unwrap();
+    let records = db.records();
+    let record = db.record(&alias).unwrap();
+    assert_eq!(record.reference(), Some(Reference::Alias(base)));
+    assert_eq!(record.effective_password(records).unwrap(), "base password");
+    let record = db.record(&shortcut).unwrap();
+    assert_eq!(record.reference(), Some(Reference::Shortcut(base)));
+    assert_eq!(record.base(records).unwrap().url, "https://base.com");
+    let record = db.record(&base).unwrap();
+    assert_eq!(record.reference(), None);
+    assert_eq!(record.effective_password(records).unwrap(), "base password");
+
+    let mut db = db;
+    db.delete_record(&base).unwrap();
+    let record = db.record(&alias).unwrap();
+    assert_eq!(record.effective_password(db.records()).err(), Some(Error::DanglingReference(base)));
+}
+
+#[test]
+fn cyclic_alias() {
+    let mut db = Database::create("password", "references", "").unwrap();
+    let mut first = RecordBuilder::new("first", "placeholder").build().unwrap();
+    let mut second = RecordBuilder::new("second", "placeholder").build().unwrap();
+    first.password = Reference::Shortcut(second.uuid()).to_string().into();
+    second.password = Reference::Alias(first.uuid()).to_string().into();
+    let first = db.add_record(first).unwrap();
+    db.add_record(second).unwrap();
+
+    let record = db.record(&first).unwrap();
+    assert_eq!(record.effective_password(db.records()).err(), Some(Error::CyclicReference(first)));
+}
+
+#[test]
+fn password_history() {
+    let history = history::PasswordHistory::parse("10302000000010005abcd35f5e10000002ìé").unwrap();
+    assert!(history.enabled);
+    assert_eq!(history.max_entries, 3);
+    assert_eq!(history.entries, vec![
+        (Utc.timestamp(1, 0), "abcd3".into()),
+        (Utc.timestamp(0x5f5e1000, 0), "ìé".into()),
+    ]);
+    assert_eq!(history.to_stored().as_str(), "10302000000010005abcd35f5e10000002ìé");
+    assert!(history::PasswordHistory::parse("10301000000010005abc").is_err());
+    assert!(history::PasswordHistory::parse("2030000").is_err());
+
+    let mut db = Database::create("password", "history", "").unwrap();
+    let uuid = db.add_record(RecordBuilder::new("entry", "first").password_history(2).build().unwrap()).unwrap();
+    for password in ["second", "third", "fourth"].iter() {
+        let mut record = db.record(&uuid).unwrap().clone();
+        record.password = (*password).into();
+        db.update_record(record).unwrap();
+    }
+    let passwords: Vec<&str> = db.record(&uuid).unwrap().password_history.entries.iter().map(|(_, pw)| pw.as_str()).collect();
+    assert_eq!(passwords, vec!["second", "third"]);
+
+    let db2 = Database::new(&db.save(), "password").unwrap();
+    assert_eq!(db2.record(&uuid).unwrap().password_history, db.record(&uuid).unwrap().password_history);
+
+    // A malformed history doesn't stop the database opening and is written back unchanged
+    let mut record = RecordBuilder::new("malformed", "secret").build().unwrap();
+    record.unknown_fields = vec![(0x0f, b"2030000".to_vec())];
+    let malformed = db.add_record(record).unwrap();
+    let db2 = Database::new(&db.save(), "password").unwrap();
+    let record = db2.record(&malformed).unwrap();
+    assert_eq!((&record.password_history, &record.unknown_fields), (&history::PasswordHistory::default(), &vec![(0x0f, b"2030000".to_vec())]));
+}
+
+#[test]
+fn password_policy() {
+    let policy = policy::PasswordPolicy::parse("f000014002003001004").unwrap();
+    assert!(policy.use_lowercase && policy.use_uppercase && policy.use_digits && policy.use_symbols);
+    assert!(!policy.easy_vision && !policy.pronounceable && !policy.hex_only);
+    assert_eq!((policy.length, policy.min_lowercase, policy.min_uppercase, policy.min_digits, policy.min_symbols), (20, 2, 3, 1, 4));
+    assert_eq!(policy.to_string(), "f000014002003001004");
+    assert!(policy::PasswordPolicy::parse("f00001400200300100").is_err());
+
+    let named = policy::parse_named("0204pins20000040000000040000006strong940001400100000000202!?").unwrap();
+    assert_eq!(named["pins"].length, 4);
+    assert!(named["pins"].use_digits && !named["pins"].use_lowercase);
+    assert_eq!(named["strong"].symbols, "!?");
+    assert!(named["strong"].easy_vision);
+    assert_eq!(policy::format_named(&named), "0204pins20000040000000040000006strong940001400100000000202!?");
+
+    for _ in 0..20 {
+        let password = generate(&policy).unwrap();
+        assert_eq!(password.len(), 20);
+        assert!(password.chars().filter(|c| c.is_ascii_lowercase()).count() >= 2);
+        assert!(password.chars().filter(|c| c.is_ascii_uppercase()).count() >= 3);
+        assert!(password.chars().filter(|c| c.is_ascii_digit()).count() >= 1);
+        assert!(password.chars().filter(|c| !c.is_ascii_alphanumeric()).count() >= 4);
+
+        let pin = generate(&named["pins"]).unwrap();
+        assert!(pin.len() == 4 && pin.chars().all(|c| c.is_ascii_digit()));
+
+        let password = generate(&named["strong"]).unwrap();
+        assert!(password.chars().all(|c| c.is_ascii_alphanumeric() || "!?".contains(c)));
+        assert!(!password.contains('l') && !password.contains('0'));
+    }
+
+    let pronounceable = policy::PasswordPolicy { pronounceable: true, length: 10, ..Default::default() };
+    let password = generate(&pronounceable).unwrap();
+    assert_eq!(password.chars().count(), 10);
+    assert!(password.chars().any(|c| c.is_ascii_digit()));
+
+    let hex = policy::PasswordPolicy { hex_only: true, length: 16, ..Default::default() };
+    assert!(generate(&hex).unwrap().chars().all(|c| c.is_ascii_hexdigit()));
+    let impossible = policy::PasswordPolicy { length: 3, ..Default::default() };
+    assert!(matches!(generate(&impossible), Err(Error::InvalidPolicy(_))));
+
+    // Records use their own policy, else the named policy
+    let mut db = Database::create("password", "policy", "").unwrap();
+    db.header.password_policies = named.clone();
+    let mut record = RecordBuilder::new("entry", "secret").build().unwrap();
+    assert_eq!(db.password_policy(&record), policy::PasswordPolicy::default());
+    record.password_policy_name = "pins".to_string();
+    assert_eq!(db.password_policy(&record), named["pins"]);
+    record.password_policy = Some(named["strong"].clone());
+    let uuid = db.add_record(record).unwrap();
+    let db2 = Database::new(&db.save(), "password").unwrap();
+    assert_eq!(db2.header.password_policies, named);
+    assert_eq!(db2.password_policy(db2.record(&uuid).unwrap()), named["strong"]);
+
+    // A malformed policy doesn't stop the database opening, it and its symbols are written back unchanged
+    let mut record = RecordBuilder::new("malformed", "secret").build().unwrap();
+    let fields = vec![(0x10, b"f00001400200300100".to_vec()), (0x16, b"!?".to_vec())];
+    record.unknown_fields = fields.clone();
+    let malformed = db.add_record(record).unwrap();
+    let db2 = Database::new(&db.save(), "password").unwrap();
+    let record = db2.record(&malformed).unwrap();
+    assert_eq!((&record.password_policy, &record.unknown_fields), (&None, &fields));
+    assert_eq!(db2.password_policy(record), policy::PasswordPolicy::default());
+
+    // As are malformed named policies in the header
+    let mut db = Database::create("password", "policy", "").unwrap();
+    db.header.unknown_fields = vec![(0x10, b"0204pins".to_vec())];
+    let db2 = Database::new(&db.save(), "password").unwrap();
+    assert!(db2.header.password_policies.is_empty());
+    assert_eq!(db2.header.unknown_fields, vec![(0x10, b"0204pins".to_vec())]);
+}
+
+#[test]
+fn one_time_codes() {
+    // Test vectors from RFC 4226 and RFC 6238
+    assert_eq!(otp::hotp(b"12345678901234567890", 0, 6, otp::Algorithm::Sha1), "755224");
+    assert_eq!(otp::hotp(b"12345678901234567890", 9, 6, otp::Algorithm::Sha1), "520489");
+    let mut totp = otp::Totp::new(b"12345678901234567890");
+    totp.digits = 8;
+    assert_eq!(totp.code_at(Utc.timestamp(59, 0)), otp::OneTimeCode { code: "94287082".to_string(), seconds_remaining: 1 });
+    assert_eq!(totp.code_at(Utc.timestamp(1111111109, 0)).code, "07081804");
+    totp.key = b"12345678901234567890123456789012".to_vec().into();
+    totp.algorithm = otp::Algorithm::Sha256;
+    assert_eq!(totp.code_at(Utc.timestamp(1234567890, 0)).code, "91819424");
+    totp.key = b"1234567890123456789012345678901234567890123456789012345678901234".to_vec().into();
+    totp.algorithm = otp::Algorithm::Sha512;
+    assert_eq!(totp.code_at(Utc.timestamp(20000000000, 0)).code, "47863826");
+
+    totp.period = 60;
+    let mut db = Database::create("password", "otp", "").unwrap();
+    let mut record = RecordBuilder::new("entry", "secret").build().unwrap();
+    assert!(record.one_time_code().is_none());
+    record.totp = Some(totp.clone());
+    assert!(record.one_time_code().unwrap().seconds_remaining <= 60);
+    // pwsafe only stores SHA1 keys
+    assert!(matches!(db.add_record(record.clone()), Err(Error::InvalidField { type_id: 0x21, .. })));
+    totp.algorithm = otp::Algorithm::Sha1;
+    record.totp = Some(totp.clone());
+    let uuid = db.add_record(record).unwrap();
+    let db2 = Database::new(&db.save(), "password").unwrap();
+    assert_eq!(db2.record(&uuid).unwrap().totp, Some(totp));
+    assert!(db2.record(&uuid).unwrap().unknown_fields.is_empty());
+}
+
+#[test]
+fn unusable_totp_fields() {
+    let mut db = Database::create("password", "otp", "").unwrap();
+    let mut record = RecordBuilder::new("entry", "secret").build().unwrap();
+    // An algorithm pwsafe doesn't define and a zero period
+    let fields = vec![(0x1b, b"12345678901234567890".to_vec()), (0x21, vec![1]), (0x23, vec![0])];
+    record.unknown_fields = fields.clone();
+    let uuid = db.add_record(record).unwrap();
+
+    // The record is read without a key and the fields are written back unchanged
+    let mut db = Database::new(&db.save(), "password").unwrap();
+    let record = db.record(&uuid).unwrap();
+    assert_eq!((record.totp.as_ref(), &record.unknown_fields), (None, &fields));
+    let db = Database::new(&db.save(), "password").unwrap();
+    assert_eq!(db.record(&uuid).unwrap().unknown_fields, fields);
+}
+
+#[test]
+fn otpauth_uri() {
+    let otpauth = otp::OtpAuth::parse("otpauth://totp/ACME%20Co:john.doe@email.com?secret=HXDMVJECJJWSRB3HWIZR4IFUGFTMXBOZ&issuer=ACME%20Co&algorithm=SHA256&digits=8&period=60").unwrap();
+    assert_eq!(otpauth.issuer, "ACME Co");
+    assert_eq!(otpauth.account, "john.doe@email.com");
+    assert_eq!(*otpauth.totp.key, vec![0x3d, 0xc6, 0xca, 0xa4, 0x82, 0x4a, 0x6d, 0x28, 0x87, 0x67, 0xb2, 0x33, 0x1e, 0x20, 0xb4, 0x31, 0x66, 0xcb, 0x85, 0xd9]);
+    assert_eq!((otpauth.totp.algorithm, otpauth.totp.digits, otpauth.totp.period), (otp::Algorithm::Sha256, 8, 60));
+    assert_eq!(otpauth.to_string(), "otpauth://totp/ACME%20Co:john.doe%40email.com?secret=HXDMVJECJJWSRB3HWIZR4IFUGFTMXBOZ&issuer=ACME%20Co&algorithm=SHA256&digits=8&period=60");
+    assert_eq!(otp::OtpAuth::parse(&otpauth.to_string()).unwrap(), otpauth);
+
+    let minimal = otp::OtpAuth::parse("otpauth://totp/alice?secret=jbsw%20y3dp").unwrap();
+    assert_eq!((minimal.issuer.as_str(), minimal.account.as_str()), ("", "alice"));
+    assert_eq!(minimal.totp, otp::Totp::new(b"Hello"));
+
+    for uri in ["otpauth://hotp/alice?secret=JBSWY3DP&counter=1", "https://totp/alice?secret=JBSWY3DP", "otpauth://totp/alice",
+                "otpauth://totp/alice?secret=JBSWY3D1", "otpauth://totp/alice?secret=JBSWY3DP&period=0",
+                "otpauth://totp/alice?secret=JBSWY3DP&digits=12", "otpauth://totp/alice?secret=JBSWY3DP&algorithm=MD5", "otpauth://totp/%zz"].iter() {
+        assert!(matches!(otp::OtpAuth::parse(uri), Err(Error::InvalidOtpAuth(_))), "{}", uri);
+    }
+
+    let mut record = RecordBuilder::new("Example", "secret").username("alice").build().unwrap();
+    assert!(record.otpauth_uri().is_none());
+    record.set_otpauth_uri("otpauth://totp/Other:bob?secret=JBSWY3DPEHPK3PXP").unwrap();
+    assert!(matches!(record.set_otpauth_uri("otpauth://totp/bob?secret=JBSWY3DP&algorithm=SHA256"), Err(Error::InvalidField { type_id: 0x21, .. })));
+    assert_eq!(record.otpauth_uri().unwrap(), "otpauth://totp/Example:alice?secret=JBSWY3DPEHPK3PXP&issuer=Example");
+}
+
+#[test]
+fn group_tree() {
+    assert_eq!(group::split_path(r"a.b\.c.d"), vec!["a", "b.c", "d"]);
+    assert_eq!(group::join_path(&group::split_path(r"a.b\.c.d")), r"a.b\.c.d");
+    assert!(group::split_path("").is_empty());
+
+    let mut db = Database::create("password", "groups", "").unwrap();
+    for (title, group) in [("root", ""), ("mail", "personal.email"), ("bank", "personal.finance"), ("vpn", r"work.example\.com")].iter() {
+        db.add_record(RecordBuilder::new(title, "secret").group(group).build().unwrap()).unwrap();
+    }
+    db.create_empty_group("work.projects.old").unwrap();
+    assert_eq!(db.create_empty_group("personal").err(), Some(Error::GroupExists("personal".to_string())));
+
+    let tree = db.group_tree();
+    assert_eq!(tree.entries, 1);
+    assert_eq!(tree.entry_count(), 4);
+    assert_eq!(tree.get("personal").unwrap().children.keys().collect::<Vec<_>>(), vec!["email", "finance"]);
+    assert_eq!(tree.get("work").unwrap().children.keys().collect::<Vec<_>>(), vec!["example.com", "projects"]);
+    assert_eq!(tree.get("work.projects").unwrap().entry_count(), 0);
+    assert!(tree.get("missing").is_none());
+
+    db.move_group("personal", r"home.mine\.old").unwrap();
+    let moved: Vec<&str> = db.records().values().map(|record| record.group.as_str()).filter(|group| group.starts_with("home")).collect();
+    assert_eq!(moved.len(), 2);
+    assert!(moved.contains(&r"home.mine\.old.email"));
+    assert!(db.group_tree().get("personal").is_none());
+    assert_eq!(db.move_group("home", "work").err(), Some(Error::GroupExists("work".to_string())));
+    assert_eq!(db.move_group("personal", "other").err(), Some(Error::GroupNotFound("personal".to_string())));
+    db.move_group("work.projects", "archive").unwrap();
+
+    assert_eq!(db.delete_empty_group("work").err(), Some(Error::GroupNotEmpty("work".to_string())));
+    let saved = Database::new(&db.save(), "password").unwrap();
+    assert_eq!(saved.group_tree(), db.group_tree());
+    db.delete_empty_group("archive").unwrap();
+    assert!(db.group_tree().get("archive").is_none());
+
+    // Adding a record to an empty group means it is no longer listed as empty
+    db.create_empty_group("new").unwrap();
+    db.add_record(RecordBuilder::new("new", "secret").group("new.sub").build().unwrap()).unwrap();
+    db.delete_record(&db.record_search("new").keys().next().copied().unwrap()).unwrap();
+    assert!(db.group_tree().get("new").is_none());
+}
+
+#[test]
+fn merge() {
+    let mut laptop = Database::create("password", "merge", "").unwrap();
+    let mut uuids = Vec::new();
+    for title in ["unchanged", "updated", "conflict", "password conflict"].iter() {
+        uuids.push(laptop.add_record(RecordBuilder::new(title, "secret").build().unwrap()).unwrap());
+    }
+    let mut phone = Database::new(&laptop.save(), "password").unwrap();
+
+    let edit = |db: &mut Database, uuid: &uuid::Uuid, edit: &dyn Fn(&mut Record), mod_time: i64| {
+        let mut record = db.record(uuid).unwrap().clone();
+        edit(&mut record);
+        db.update_record(record.clone()).unwrap();
+        // Set the times directly so the order of edits doesn't depend on the clock
+        record.mod_time = Some(Utc.timestamp(mod_time, 0));
+        db.records.insert(*uuid, record);
+    };
+    edit(&mut phone, &uuids[1], &|r| r.notes = "phone".to_string(), 2000);
+    edit(&mut laptop, &uuids[1], &|r| r.notes = "laptop".to_string(), 1000);
+    edit(&mut phone, &uuids[2], &|r| r.notes = "phone".to_string(), 3000);
+    edit(&mut laptop, &uuids[2], &|r| r.notes = "laptop".to_string(), 3000);
+    edit(&mut phone, &uuids[3], &|r| { r.password = "phone".into(); r.password_mod_time = Some(Utc.timestamp(4000, 0)) }, 4000);
+    edit(&mut laptop, &uuids[3], &|r| { r.notes = "laptop".to_string(); r.password_mod_time = Some(Utc.timestamp(1000, 0)) }, 5000);
+    let added = phone.add_record(RecordBuilder::new("added", "secret").build().unwrap()).unwrap();
+    phone.create_empty_group("phone.only").unwrap();
+
+    let report = laptop.merge(&phone, None);
+    assert_eq!(report.added, vec![added]);
+    assert_eq!(report.updated, vec![uuids[1]]);
+    let mut conflicts: Vec<uuid::Uuid> = report.conflicts.iter().map(|conflict| conflict.ours.uuid()).collect();
+    conflicts.sort();
+    let mut expected = vec![uuids[2], uuids[3]];
+    expected.sort();
+    assert_eq!(conflicts, expected);
+
+    assert_eq!(laptop.record(&uuids[0]).unwrap().notes, "");
+    assert_eq!(laptop.record(&uuids[1]).unwrap().notes, "phone");
+    assert_eq!(laptop.record(&uuids[2]).unwrap().notes, "laptop");
+    let merged = laptop.record(&uuids[3]).unwrap();
+    assert_eq!((merged.notes.as_str(), merged.password.as_str()), ("laptop", "phone"));
+    assert!(laptop.record(&added).is_some());
+    assert!(laptop.group_tree().get("phone.only").is_some());
+
+    // Merging again changes nothing
+    let report = laptop.merge(&phone, None);
+    assert!(report.added.is_empty() && report.updated.is_empty());
+}
+
+#[test]
+fn merge_since_last_merge() {
+    let mut laptop = Database::create("password", "merge", "").unwrap();
+    let both = laptop.add_record(RecordBuilder::new("both", "secret").build().unwrap()).unwrap();
+    let phone_only = laptop.add_record(RecordBuilder::new("phone only", "secret").build().unwrap()).unwrap();
+    let phone = Database::new(&laptop.save(), "password").unwrap();
+    // The report has the time the copies were the same, once the merged copy is shared
+    let report = laptop.merge(&phone, None);
+    assert!(report.conflicts.is_empty());
+    let mut phone = Database::new(&laptop.save(), "password").unwrap();
+
+    let edit = |db: &mut Database, uuid: &uuid::Uuid, edit: &dyn Fn(&mut Record), seconds: i64| {
+        let mut record = db.record(uuid).unwrap().clone();
+        edit(&mut record);
+        record.mod_time = Some(crate::now() + chrono::Duration::seconds(seconds));
+        db.records.insert(*uuid, record);
+    };
+    // Different fields edited at different times are still a conflict
+    edit(&mut phone, &both, &|r| r.username = "phone".to_string(), 10);
+    edit(&mut laptop, &both, &|r| r.notes = "laptop".to_string(), 20);
+    edit(&mut phone, &phone_only, &|r| r.notes = "phone".to_string(), 30);
+
+    let report = laptop.merge(&phone, Some(report.time));
+    assert_eq!(report.updated, vec![phone_only]);
+    assert_eq!(report.conflicts.len(), 1);
+    let conflict = &report.conflicts[0];
+    assert_eq!((conflict.ours.notes.as_str(), conflict.theirs.username.as_str()), ("laptop", "phone"));
+    assert_eq!(laptop.record(&both).unwrap().notes, "laptop");
+    assert_eq!(laptop.record(&phone_only).unwrap().notes, "phone");
+}
+
+#[test]
+fn diff_databases() {
+    let mut old = Database::create("password", "diff", "").unwrap();
+    let changed = old.add_record(RecordBuilder::new("changed", "secret").username("alice").build().unwrap()).unwrap();
+    let removed = old.add_record(RecordBuilder::new("removed", "secret").group("old").build().unwrap()).unwrap();
+    let mut new = Database::new(&old.save(), "password").unwrap();
+    assert!(diff(&old, &new).is_empty());
+
+    let mut record = new.record(&changed).unwrap().clone();
+    record.username = "bob".to_string();
+    record.password = "new secret".into();
+    new.update_record(record).unwrap();
+    new.delete_record(&removed).unwrap();
+    let added = new.add_record(RecordBuilder::new("added", "secret").build().unwrap()).unwrap();
+    new.header.name = "renamed".to_string();
+    new.header.last_save_by = "pwsafe V3.56".to_string();
+
+    let changes = diff(&old, &new);
+    let fields: Vec<&str> = changes.header.iter().map(|change| change.field).collect();
+    assert!(fields.contains(&"name") && fields.contains(&"last save by"));
+    assert_eq!(changes.added.len(), 1);
+    assert_eq!(changes.added[0].uuid, added);
+    assert_eq!(changes.removed.len(), 1);
+    assert_eq!((changes.removed[0].uuid, changes.removed[0].group.as_str()), (removed, "old"));
+    assert!(changes.removed[0].changes.iter().all(|change| change.new.is_empty()));
+    assert_eq!(changes.modified.len(), 1);
+    let modified = &changes.modified[0];
+    assert!(modified.changes.contains(&diff::FieldChange { field: "username", old: "alice".to_string(), new: "bob".to_string() }));
+    assert!(modified.changes.contains(&diff::FieldChange { field: "password", old: "********".to_string(), new: "********".to_string() }));
+    assert!(!changes.to_string().contains("secret"));
+    assert!(changes.to_string().contains("~ changed []"));
+
+    let revealed = diff::diff_showing_passwords(&old, &new);
+    let password = revealed.modified[0].changes.iter().find(|change| change.field == "password").unwrap();
+    assert_eq!((password.old.as_str(), password.new.as_str()), ("secret", "new secret"));
+}
+
+#[test]
+fn csv_import_export() {
+    let mut db = Database::create("password", "csv", "").unwrap();
+    let existing = RecordBuilder::new("bank", "old").username("alice").email("alice@example.com").notes("pin").url("https://bank.example");
+    let existing = db.add_record(existing.build().unwrap()).unwrap();
+    let data = "\u{feff}name,url,username,password,note\r\n\
+        bank,https://bank.example,alice,new,\r\n\
+        ,https://mail.example,bob,\"p,a\"\"ss\",\"two\nlines\"\r\n\
+        \r\n\
+        nopassword,,carol,,\r\n";
+    let report = db.import_csv(data, &csv::CsvImport::default()).unwrap();
+    assert_eq!(report.added.len(), 1);
+    assert_eq!(report.skipped.len(), 2);
+    assert_eq!(report.skipped[0], ("row 2".to_string(), Error::DuplicateRecord(existing)));
+    assert_eq!(report.skipped[1].0, "row 5");
+    let mail = db.record(&report.added[0]).unwrap();
+    assert_eq!((mail.title.as_str(), mail.username.as_str()), ("https://mail.example", "bob"));
+    assert_eq!((mail.password.as_str(), mail.notes.as_str()), ("p,a\"ss", "two\nlines"));
+
+    let options = csv::CsvImport {
+        columns: Some(vec![csv::Column::Title, csv::Column::Username, csv::Column::Password, csv::Column::Group]),
+        has_header: false,
+        duplicates: csv::Duplicates::Replace,
+    };
+    let report = db.import_csv("bank,alice,new\nshop,alice,secret,web", &options).unwrap();
+    assert_eq!(report.replaced, vec![existing]);
+    assert_eq!(db.record(&existing).unwrap().password, "new");
+    assert_eq!(db.record(&existing).unwrap().password_history.entries.len(), 0);
+    let shop = db.record(&report.added[0]).unwrap().clone();
+    assert_eq!(shop.group, "web");
+    // Only the mapped columns with a value replace the existing fields
+    let bank = db.record(&existing).unwrap();
+    assert_eq!((bank.email.as_str(), bank.notes.as_str(), bank.url.as_str()), ("alice@example.com", "pin", "https://bank.example"));
+    let replace_url = csv::CsvImport {
+        columns: Some(vec![csv::Column::Title, csv::Column::Username, csv::Column::Password, csv::Column::Url, csv::Column::Notes]),
+        has_header: false,
+        duplicates: csv::Duplicates::Replace,
+    };
+    db.import_csv("bank,alice,newer,https://new.bank.example,", &replace_url).unwrap();
+    let bank = db.record(&existing).unwrap();
+    assert_eq!((bank.notes.as_str(), bank.url.as_str()), ("pin", "https://new.bank.example"));
+
+    let options = csv::CsvImport { has_header: true, duplicates: csv::Duplicates::Add, ..options };
+    assert!(matches!(db.import_csv("\"unclosed", &options), Err(Error::InvalidCsv(_))));
+    let report = db.import_csv("title,username,password,group\nshop,alice,secret,web", &options).unwrap();
+    assert_eq!(report.added.len(), 1);
+    assert!(matches!(db.import_csv("a,b\n", &csv::CsvImport::default()), Err(Error::InvalidCsv(_))));
+
+    let search = db.record_search("shop");
+    let exported = db.export_csv(search.values().copied(), csv::PasswordExport::Omit).unwrap();
+    assert_eq!(exported, "group,title,username,url,notes,email\r\nweb,shop,alice,,,\r\nweb,shop,alice,,,\r\n");
+    let exported = db.export_csv(db.records().values(), csv::PasswordExport::Plaintext).unwrap();
+    assert!(exported.contains(",https://mail.example,bob,\"p,a\"\"ss\",https://mail.example,\"two\nlines\",\r\n"));
+
+    let mut copy = Database::create("password", "copy", "").unwrap();
+    let report = copy.import_csv(&exported, &csv::CsvImport::default()).unwrap();
+    // The two shop records are duplicates of each other
+    assert_eq!((report.added.len(), report.skipped.len()), (3, 1));
+}
+
+#[test]
+fn xml_export_import() {
+    let mut db = Database::create("password", "xml", "").unwrap();
+    let mut record = RecordBuilder::new("bank", "first").group("money.accounts").username("alice")
+        .notes("line one\nline <two> ]]> & three").password_history(3).build().unwrap();
+    record.password_policy = Some(policy::PasswordPolicy { length: 20, symbols: "!?".to_string(), ..Default::default() });
+    record.totp = Some(otp::Totp { digits: 8, ..otp::Totp::new(b"12345678901234567890") });
+    record.credit_card_number = "4111111111111111".into();
+    record.password_expiry_interval = 90;
+    record.keyboard_shortcut = [0x41, 0, 0x03, 0];
+    let bank = db.add_record(record).unwrap();
+    let mut record = db.record(&bank).unwrap().clone();
+    record.password = "second".into();
+    db.update_record(record).unwrap();
+    let mut alias = RecordBuilder::new("bank alias", "x").build().unwrap();
+    alias.password = Reference::Alias(bank).to_string().into();
+    let alias = db.add_record(alias).unwrap();
+    db.create_empty_group("empty.group").unwrap();
+    db.header.password_policies.insert("pins".to_string(), policy::PasswordPolicy { length: 4, ..Default::default() });
+
+    let xml = db.export_xml();
+    assert!(xml.contains("<title><![CDATA[bank]]></title>"));
+    assert!(xml.contains("<![CDATA[line one\nline <two> ]]]]><![CDATA[> & three]]>"));
+
+    let mut copy = Database::create("password", "copy", "").unwrap();
+    let added = copy.import_xml(&xml).unwrap();
+    assert_eq!(added.len(), 2);
+    // Times are only kept to the second
+    let truncate = |time: Option<chrono::DateTime<chrono::Utc>>| time.map(|t| t.timestamp());
+    for uuid in [bank, alias].iter() {
+        let (original, imported) = (db.record(uuid).unwrap(), copy.record(uuid).unwrap());
+        assert_eq!(truncate(imported.mod_time), truncate(original.mod_time));
+        let mut imported = imported.clone();
+        imported.create_time = original.create_time;
+        imported.mod_time = original.mod_time;
+        imported.password_mod_time = original.password_mod_time;
+        for (entry, original) in imported.password_history.entries.iter_mut().zip(&original.password_history.entries) {
+            entry.0 = original.0;
+        }
+        assert_eq!(&imported, original);
+    }
+    assert_eq!(copy.header.password_policies, db.header.password_policies);
+    assert!(copy.group_tree().get("empty.group").is_some());
+
+    // Importing into the same database gives the records new UUIDs and updates the alias
+    let added = db.import_xml(&xml).unwrap();
+    assert!(!added.contains(&bank) && !added.contains(&alias));
+    let new_alias = added.iter().map(|uuid| db.record(uuid).unwrap()).find(|r| r.title == "bank alias").unwrap();
+    let new_bank = new_alias.base(db.records()).unwrap();
+    assert!(added.contains(&new_bank.uuid()));
+    assert_eq!(new_bank.password, "second");
+
+    let pwsafe = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!-- exported -->
+<passwordsafe delimiter="&#x25ba;" Database='test.psafe3'>
+  <entry id="1">
+    <title>a &amp; b</title>
+    <password><![CDATA[pass]]>word</password>
+    <uuid>0123456789abcdef0123456789abcdef</uuid>
+    <ctimex>2020-08-01T10:00:00Z</ctimex>
+    <unknown/>
+  </entry>
+</passwordsafe>"#;
+    let mut db = Database::create("password", "pwsafe", "").unwrap();
+    let uuid = db.import_xml(pwsafe).unwrap()[0];
+    let record = db.record(&uuid).unwrap();
+    assert_eq!((record.title.as_str(), record.password.as_str()), ("a & b", "password"));
+    assert_eq!(uuid.to_simple().to_string(), "0123456789abcdef0123456789abcdef");
+    assert_eq!(record.create_time.unwrap().to_rfc3339(), "2020-08-01T10:00:00+00:00");
+
+    for invalid in ["<passwordsafe>", "<other/>", "<passwordsafe><entry><title>t</title></entry></passwordsafe>",
+                    "<passwordsafe></safe>", "<passwordsafe>&bad;</passwordsafe>"].iter() {
+        assert!(matches!(db.import_xml(invalid), Err(Error::InvalidXml(_)) | Err(Error::MissingField(_))), "{}", invalid);
+    }
+    // Deep nesting is an error rather than a stack overflow
+    assert!(matches!(db.import_xml(&"<a>".repeat(100_000)), Err(Error::InvalidXml(_))));
+}
+
+#[test]
+fn kdbx_import() {
+    let kdbx3 = include_bytes!("../test_dbs/keepass3.kdbx");
+    let kdbx4 = include_bytes!("../test_dbs/keepass4.kdbx");
+    let key_file = include_bytes!("../test_dbs/keepass4.keyx");
+    for (data, key_file) in [(&kdbx3[..], None), (&kdbx4[..], Some(&key_file[..]))].iter() {
+        let mut db = Database::create("password", "kdbx", "").unwrap();
+        let report = db.import_kdbx(data, "keepass", *key_file).unwrap();
+        assert_eq!(report.added.len(), 3);
+        assert!(report.skipped.is_empty());
+        // Entries without a password are given one, as items of the other importers are
+        assert_eq!(report.generated.len(), 1);
+        let generated = db.record(&report.generated[0]).unwrap();
+        assert_eq!((generated.group.as_str(), generated.title.as_str()), ("Email", "no password"));
+        assert!(!generated.password.is_empty());
+
+        let root = db.records().values().find(|record| record.title == "root entry").unwrap();
+        assert_eq!((root.group.as_str(), root.username.as_str(), root.password.as_str()), ("", "root", "root pass"));
+        assert_eq!((root.url.as_str(), root.email.as_str()), ("https://root.example", "root@example.com"));
+        // The TOTP uses SHA256 which pwsafe can't store so its settings are kept in the notes
+        assert_eq!(root.notes, "root notes\n\nPIN: 1234\nSecurity question: blue\nTimeOtp-Secret-Base32: GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ\n\
+                                TimeOtp-Length: 8\nTimeOtp-Algorithm: HMAC-SHA-256");
+        assert!(root.totp.is_none());
+        assert_eq!(root.uuid().to_string(), "11111111-1111-1111-1111-111111111111");
+        assert_eq!(root.password_expiry_time.unwrap().to_rfc3339(), "2021-01-01T00:00:00+00:00");
+
+        let mail = db.records().values().find(|record| record.title == "mail").unwrap();
+        assert_eq!((mail.group.as_str(), mail.password.as_str()), ("Email", "third <&>"));
+        assert_eq!(mail.totp.as_ref().unwrap().period, 60);
+        assert_eq!(mail.mod_time.unwrap().to_rfc3339(), "2019-09-01T00:00:00+00:00");
+        assert_eq!(mail.password_mod_time, mail.mod_time);
+        let history: Vec<(String, &str)> = mail.password_history.entries.iter()
+            .map(|(time, password)| (time.to_rfc3339(), password.as_str())).collect();
+        assert_eq!(history, vec![("2019-01-01T00:00:00+00:00".to_string(), "first"), ("2019-06-01T00:00:00+00:00".to_string(), "second")]);
+        assert_eq!(mail.password_history.max_entries, 5);
+
+        // The recycle bin is left out and groups without entries are kept
+        assert!(db.records().values().all(|record| record.title != "deleted"));
+        let tree = db.group_tree();
+        assert!(tree.get("Recycle Bin").is_none());
+        assert!(tree.get("Banking\\.old.Cards").is_some());
+
+        // It can be saved as a V3 safe
+        let saved = Database::new(&db.save(), "password").unwrap();
+        assert_eq!(saved.records().len(), 3);
+    }
+
+    let mut db = Database::create("password", "kdbx", "").unwrap();
+    assert_eq!(db.import_kdbx(kdbx3, "wrong", None).err(), Some(Error::WrongPassword));
+    assert_eq!(db.import_kdbx(kdbx4, "keepass", None).err(), Some(Error::WrongPassword));
+    assert!(matches!(db.import_kdbx(include_bytes!("../test_dbs/simple.dat"), "password", None), Err(Error::InvalidKdbx(_))));
+    assert!(matches!(db.import_kdbx(&kdbx4[..200], "keepass", Some(key_file)), Err(Error::Truncated(_))));
+
+    // A file asking for more key derivation work than the limit is rejected before doing any
+    let mut hostile = kdbx3.to_vec();
+    let mut i = 12;
+    while hostile[i] != 6 {
+        i += 3 + u16::from_le_bytes([hostile[i + 1], hostile[i + 2]]) as usize;
+    }
+    hostile[i + 3..i + 11].copy_from_slice(&u64::MAX.to_le_bytes());
+    assert!(matches!(db.import_kdbx(&hostile, "keepass", None), Err(Error::InvalidKdbx(_))));
+    assert!(db.records().is_empty());
+}
+
+#[test]
+fn bitwarden_import() {
+    let mut db = Database::create("password", "bitwarden", "").unwrap();
+    let report = db.import_bitwarden(include_str!("../test_dbs/bitwarden.json")).unwrap();
+    assert_eq!(report.added.len(), 4);
+    assert_eq!(report.skipped, vec![("Me".to_string(), Error::UnsupportedItem("Bitwarden identity".to_string()))]);
+    let mut generated: Vec<&str> = report.generated.iter().map(|uuid| db.record(uuid).unwrap().title.as_str()).collect();
+    generated.sort_unstable();
+    assert_eq!(generated, vec!["No password", "Visa", "Wifi"]);
+
+    let mail = db.records().values().find(|record| record.title == "Webmail").unwrap();
+    assert_eq!(mail.uuid().to_string(), "0b6e3a9c-1d2e-4f50-9a7b-8c9d0e1f2a3b");
+    assert_eq!((mail.group.as_str(), mail.username.as_str(), mail.password.as_str()), ("Work.Mail", "alice", "s3cret é"));
+    assert_eq!(mail.url, "https://mail.example.com");
+    assert_eq!(mail.notes, "work account\n\nURL: https://webmail.example.com\nRecovery code: abc-123\nAdmin: true");
+    assert_eq!(mail.totp.as_ref().unwrap().digits, 8);
+    assert_eq!(mail.password_mod_time.unwrap().to_rfc3339(), "2021-03-04T05:06:07+00:00");
+    let history: Vec<&str> = mail.password_history.entries.iter().map(|(_, password)| password.as_str()).collect();
+    assert_eq!(history, vec!["oldest", "older"]);
+
+    // Cards, notes and logins without a password get a generated one
+    let card = db.records().values().find(|record| record.title == "Visa").unwrap();
+    assert_eq!((card.username.as_str(), card.credit_card_number.as_str()), ("Alice Smith", "4111111111111111"));
+    assert_eq!((card.credit_card_expiration.as_str(), card.credit_card_verify.as_str()), ("03/27", "123"));
+    assert_eq!(card.notes, "Brand: Visa");
+    assert!(!card.password.is_empty());
+    let note = db.records().values().find(|record| record.title == "Wifi").unwrap();
+    assert_eq!(note.notes, "the wifi key is on the router");
+    let saved = Database::new(&db.save(), "password").unwrap();
+    assert_eq!(saved.records().len(), 4);
+
+    assert!(matches!(db.import_bitwarden(r#"{"encrypted": true, "data": "..."}"#), Err(Error::InvalidExport(_))));
+    assert!(matches!(db.import_bitwarden(r#"{"items": [}"#), Err(Error::InvalidJson(_))));
+    let report = db.import_bitwarden(r#"{"items": [{"type": 2, "name": "😀 \"quoted\"\n"}]}"#).unwrap();
+    assert_eq!(db.record(&report.added[0]).unwrap().title, "\u{1f600} \"quoted\"\n");
+    assert!(matches!(db.import_bitwarden(r#"{"items": [{"name": "\ud83d"}]}"#), Err(Error::InvalidJson(_))));
+}
+
+#[test]
+fn onepassword_import() {
+    let data = include_bytes!("../test_dbs/onepassword.1pux");
+    let mut db = Database::create("password", "1password", "").unwrap();
+    let report = db.import_1pux(data).unwrap();
+    assert_eq!(report.added.len(), 2);
+    assert_eq!(report.skipped, vec![("Private.Passport scan".to_string(), Error::UnsupportedItem("1Password document".to_string()))]);
+    assert_eq!(report.generated.len(), 1);
+
+    let login = db.records().values().find(|record| record.title == "Example").unwrap();
+    assert_eq!((login.group.as_str(), login.username.as_str(), login.password.as_str()), ("Private", "alice", "hunter2"));
+    assert_eq!((login.url.as_str(), login.email.as_str()), ("https://example.com", "alice@example.com"));
+    assert_eq!(login.notes, "login notes\n\nPIN: 9876\nrenewal: 2022-01-01\nURL: https://login.example.com\nTags: work, web");
+    assert_eq!(login.totp.as_ref().unwrap().key.as_slice(), b"Hello!\xde\xad\xbe\xef");
+    assert_eq!(login.create_time.unwrap().to_rfc3339(), "2020-01-01T00:00:00+00:00");
+    assert_eq!(login.password_history.entries.len(), 1);
+
+    let card = db.records().values().find(|record| record.title == "Mastercard").unwrap();
+    assert_eq!((card.username.as_str(), card.credit_card_number.as_str()), ("Alice Smith", "5555555555554444"));
+    assert_eq!((card.credit_card_verify.as_str(), card.credit_card_pin.as_str()), ("321", "4321"));
+    assert_eq!(card.credit_card_expiration, "11/28");
+    assert_eq!(card.notes, "type: mc");
+    assert_eq!(report.generated, vec![card.uuid()]);
+
+    assert!(matches!(db.import_1pux(&data[..data.len() - 30]), Err(Error::InvalidExport(_))));
+    assert!(matches!(db.import_1pux(include_bytes!("../test_dbs/simple.dat")), Err(Error::InvalidExport(_))));
+
+    // Offsets at the end of the address space are rejected rather than wrapping
+    let mut corrupt = data.to_vec();
+    let end = corrupt.windows(4).rposition(|window| window == [0x50, 0x4b, 0x05, 0x06]).unwrap();
+    corrupt[end + 16..end + 20].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert!(matches!(db.import_1pux(&corrupt), Err(Error::InvalidExport(_))));
+}
+
+#[test]
+fn audit_report() {
+    let mut db = Database::create("password", "audit", "").unwrap();
+    let strong = "x7#Kq9!vLm2@Wp4$Tz8&";
+    let email = db.add_record(RecordBuilder::new("email", strong).url("https://mail.example.com").build().unwrap()).unwrap();
+    let bank = db.add_record(RecordBuilder::new("bank", strong).group("money").url("https://bank.example.com").build().unwrap()).unwrap();
+    db.add_record(RecordBuilder::new("alias", &Reference::Alias(email).to_string()).build().unwrap()).unwrap();
+    let fine = db.add_record(RecordBuilder::new("fine", "Rk4$8vN!q2Lz@7Wm#x9Pt").url("https://fine.example.com").build().unwrap()).unwrap();
+    let router = db.add_record(RecordBuilder::new("router", "admin").username("Admin").build().unwrap()).unwrap();
+    let mut old = RecordBuilder::new("old", "Zq8!mT3#vK9@pL2$wN7&").url("https://old.example.com").build().unwrap();
+    old.password_mod_time = Some(crate::now() - chrono::Duration::days(400));
+    old.password_expiry_time = Some(Utc.timestamp(1_600_000_000, 0));
+    let old = db.add_record(old).unwrap();
+
+    let report = db.audit(&audit::AuditOptions::default());
+    let uuids: Vec<uuid::Uuid> = report.records.iter().map(|record| record.uuid).collect();
+    assert_eq!(uuids, vec![email, old, router, bank]);
+    assert!(!uuids.contains(&fine));
+    assert_eq!(report.reused, vec![vec![email, bank]]);
+    assert_eq!(report.records[0].issues, vec![audit::Issue::Reused(vec![bank])]);
+    assert_eq!(report.records[1].issues, vec![audit::Issue::Old(400), audit::Issue::Expired(Utc.timestamp(1_600_000_000, 0))]);
+    let issues = &report.records[2].issues;
+    assert!(issues.contains(&audit::Issue::PasswordIsUsername) && issues.contains(&audit::Issue::NoUrl));
+    assert!(issues.iter().any(|issue| matches!(issue, audit::Issue::Weak(_))));
+    assert!(report.to_string().contains("Shared password: email [], bank [money]"));
+    assert!(!report.to_string().contains(strong));
+
+    let lenient = audit::AuditOptions { min_score: 0, max_age_days: 500 };
+    assert_eq!(db.audit(&lenient).records[1].issues, vec![audit::Issue::Expired(Utc.timestamp(1_600_000_000, 0))]);
+}
+
+#[test]
+fn breached_passwords() {
+    use sha1::{Digest, Sha1};
+    let sha1 = |password: &str| -> String { Sha1::digest(password.as_bytes()).iter().map(|b| format!("{:02X}", b)).collect() };
+
+    let mut db = Database::create("password", "breach", "").unwrap();
+    let reused = db.add_record(RecordBuilder::new("reused", "password123").group("web").build().unwrap()).unwrap();
+    let common = db.add_record(RecordBuilder::new("common", "letmein").build().unwrap()).unwrap();
+    let unique = db.add_record(RecordBuilder::new("unique", "Zq8!mT3#vK9@pL2$wN7&").build().unwrap()).unwrap();
+    db.add_record(RecordBuilder::new("alias", &Reference::Alias(reused).to_string()).build().unwrap()).unwrap();
+
+    let mut hashes: Vec<(String, u64)> = (0..1000).map(|i| (sha1(&format!("filler{}", i)), i + 1)).collect();
+    hashes.push((sha1("password123"), 2_000_000));
+    hashes.push((sha1("letmein"), 500));
+    hashes.sort();
+    let dir = std::env::temp_dir().join(format!("pwdb-breach-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(dir.join("ranges")).unwrap();
+    let sorted: String = hashes.iter().map(|(hash, count)| format!("{}:{}\r\n", hash, count)).collect();
+    std::fs::write(dir.join("sorted.txt"), sorted.to_lowercase()).unwrap();
+    for (hash, count) in &hashes {
+        let path = dir.join("ranges").join(format!("{}.txt", &hash[..5]));
+        let mut range = std::fs::read_to_string(&path).unwrap_or_default();
+        range.push_str(&format!("{}:{}\r\n", &hash[5..], count));
+        std::fs::write(path, range).unwrap();
+    }
+    std::fs::write(dir.join("ranges").join(&sha1("Zq8!mT3#vK9@pL2$wN7&")[..5]), format!("{}:0\r\n", "0".repeat(35))).unwrap();
+
+    for path in [dir.join("sorted.txt"), dir.join("ranges")].iter() {
+        let list = breach::BreachList::open(path).unwrap();
+        let breached = db.breached(&list).unwrap();
+        let found: Vec<(uuid::Uuid, u64)> = breached.iter().map(|record| (record.uuid, record.count)).collect();
+        assert_eq!(found, vec![(common, 500), (reused, 2_000_000)]);
+        assert!(!found.iter().any(|(uuid, _)| *uuid == unique));
+        for i in (0..1000).step_by(97) {
+            assert_eq!(list.count(&format!("filler{}", i)).unwrap(), i + 1);
+        }
+    }
+
+    std::fs::write(dir.join("ranges").join(format!("{}.txt", &sha1("letmein")[..5])), "not a hash\n").unwrap();
+    let list = breach::BreachList::open(&dir.join("ranges")).unwrap();
+    assert!(matches!(db.breached(&list), Err(Error::InvalidHashList(_))));
+    assert!(breach::BreachList::open(&dir.join("missing")).is_err());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn password_strength() {
+    let score = |password: &str| strength::estimate(password, &[]).score;
+    for password in ["password", "P@ssw0rd", "qwertyuiop", "drowssap", "zxcvbn", "abcdef", "aaaaaaaa", "abcabcabc", "1991", "13/05/1991"].iter() {
+        assert!(score(password) <= 1, "{}", password);
+    }
+    assert_eq!(score("x7#Kq9!vLm2@Wp4$Tz8&"), 4);
+    assert_eq!(score("correcthorsebatterystaple"), 4);
+
+    let common = strength::estimate("password", &[]);
+    assert_eq!(common.warning, Some("This is a top-10 common password"));
+    assert_eq!(common.crack_time(), "less than a second");
+    assert_eq!(strength::estimate("987654", &[]).warning, Some("Sequences like abc or 6543 are easy to guess"));
+    assert_eq!(strength::estimate("asdfghjk", &[]).warning, Some("Rows and patterns of keys on the keyboard are easy to guess"));
+    assert_eq!(strength::estimate("x7#Kq9!vLm2@Wp4$Tz8&", &[]).warning, None);
+
+    let record = RecordBuilder::new("bank", "bankalice").username("alice").build().unwrap();
+    assert!(record.strength().score < strength::estimate("bankalice", &[]).score);
+    let strength = strength::Strength { guesses: 3.6e7, score: 2, warning: None };
+    assert_eq!(strength.to_string(), "fair, could be cracked in 1 hour");
+}
+
+#[test]
+fn password_expiry() {
+    use record::ExpiryStatus;
+    let mut db = Database::create("password", "expiry", "").unwrap();
+    let rotated = db.add_record(RecordBuilder::new("rotated", "secret").password_expiry_interval(30).build().unwrap()).unwrap();
+    let never = db.add_record(RecordBuilder::new("never", "secret").build().unwrap()).unwrap();
+    let record = db.record(&rotated).unwrap();
+    let expiry = record.password_expiry_time.unwrap();
+    assert_eq!((expiry - record.password_mod_time.unwrap()).num_days(), 30);
+    assert_eq!(record.expiry_status(), ExpiryStatus::Ok);
+    assert_eq!(db.record(&never).unwrap().expiry_status(), ExpiryStatus::Ok);
+
+    let mut soon = RecordBuilder::new("soon", "secret").build().unwrap();
+    soon.password_expiry_time = Some(crate::now() + chrono::Duration::days(3));
+    let soon = db.add_record(soon).unwrap();
+    let mut expired = RecordBuilder::new("expired", "secret").build().unwrap();
+    expired.password_expiry_time = Some(Utc.timestamp(1_600_000_000, 0));
+    let expired = db.add_record(expired).unwrap();
+    assert_eq!(db.record(&soon).unwrap().expiry_status(), ExpiryStatus::DueSoon);
+    assert_eq!(db.record(&expired).unwrap().expiry_status(), ExpiryStatus::Expired);
+    let expiring: Vec<uuid::Uuid> = db.expiring_within(chrono::Duration::days(record::DUE_SOON_DAYS)).iter().map(|r| r.uuid()).collect();
+    assert_eq!(expiring, vec![expired, soon]);
+    assert_eq!(db.expiring_within(chrono::Duration::days(60)).len(), 3);
+    assert_eq!(db.expiring_within(chrono::Duration::max_value()).len(), 3);
+
+    // Changing the password moves the expiry on by the interval, other changes leave it
+    let mut record = db.record(&rotated).unwrap().clone();
+    record.password_expiry_time = Some(Utc.timestamp(1_600_000_000, 0));
+    record.notes = "changed".to_string();
+    db.update_record(record.clone()).unwrap();
+    assert_eq!(db.record(&rotated).unwrap().expiry_status(), ExpiryStatus::Expired);
+    record.password = "new secret".into();
+    db.update_record(record).unwrap();
+    assert_eq!(db.record(&rotated).unwrap().expiry_status(), ExpiryStatus::Ok);
+
+    let db2 = Database::new(&db.save(), "password").unwrap();
+    assert_eq!(db2.record(&rotated).unwrap().password_expiry_interval, 30);
+    assert_eq!(db2.expiring_within(chrono::Duration::days(60)).len(), 3);
+}
+
+#[test]
+fn password_expiry_interval_out_of_range() {
+    let mut db = Database::create("password", "expiry", "").unwrap();
+    let mut record = RecordBuilder::new("forever", "secret").build().unwrap();
+    record.password_expiry_interval = 0xFFFF_FFFF;
+    let uuid = db.add_record(record).unwrap();
+
+    // The interval is kept in the file but not used
+    let mut db = Database::new(&db.save(), "password").unwrap();
+    let mut record = db.record(&uuid).unwrap().clone();
+    assert_eq!(record.password_expiry_interval, 0);
+    assert_eq!(record.unknown_fields, vec![(0x11, vec![0xff; 4])]);
+    record.password = "new secret".into();
+    db.update_record(record).unwrap();
+    assert_eq!(db.record(&uuid).unwrap().password_expiry_time, None);
+    let db = Database::new(&db.save(), "password").unwrap();
+    assert_eq!(db.record(&uuid).unwrap().unknown_fields, vec![(0x11, vec![0xff; 4])]);
+
+    // An interval set directly past the range of times leaves the password without an expiry
+    let mut record = db.record(&uuid).unwrap().clone();
+    record.password_expiry_interval = 0xFFFF_FFFF;
+    record.roll_expiry(crate::now());
+    assert_eq!(record.password_expiry_time, None);
+    assert_eq!(RecordBuilder::new("t", "p").password_expiry_interval(0xFFFF_FFFF).build().unwrap().password_expiry_interval, record::MAX_EXPIRY_INTERVAL);
+}
+
+proptest! {
+    // Each case stretches a password so keep the number of cases down
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn parse_arbitrary_file(data in proptest::collection::vec(any::<u8>(), 0..1024)) {
+        let _ = Database::new(&data, "password");
+    }
+
+    #[test]
+    fn parse_arbitrary_fields(data in proptest::collection::vec(any::<u8>(), 0..1024)) {
+        let _ = Database::new(&fuzz_db("password", &data), "password");
+    }
+
+    #[test]
+    fn parse_corrupted_file(changes in proptest::collection::vec((152..1000usize, any::<u8>()), 1..8)) {
+        let mut data = include_bytes!("../test_dbs/three.dat").to_vec();
+        for (pos, value) in changes {
+            let pos = pos % data.len();
+            data[pos] = value;
+        }
+        let _ = Database::new(&data, "three3#;");
+    }
+}
+
 // TODO review test coverage
\ No newline at end of file
@@ -0,0 +1,567 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use uuid::Uuid;
+
+use crate::{Database, Error};
+use crate::history::PasswordHistory;
+use crate::otp::{self, Algorithm, Totp};
+use crate::policy::PasswordPolicy;
+use crate::record::{MAX_EXPIRY_INTERVAL, Record, Reference};
+
+// Times are written in UTC in the xs:dateTime form pwsafe uses, without a time zone
+const TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+
+impl Database {
+    // export_xml writes the database in the XML format of the pwsafe desktop client, which covers
+    // every record field. Of the header only the named password policies and empty groups have a
+    // place in the format. Passwords are written in plain text.
+    pub fn export_xml(&self) -> String {
+        let mut w = Writer::default();
+        w.xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n");
+        w.open("passwordsafe", &[
+            ("Database", &self.header.name),
+            ("ExportTimeStamp", &crate::now().format(TIME_FORMAT).to_string()),
+            ("WhatSaved", &format!("pwapwdb V{}", env!("CARGO_PKG_VERSION"))),
+            ("xmlns:xsi", "http://www.w3.org/2001/XMLSchema-instance"),
+            ("xsi:noNamespaceSchemaLocation", "pwsafe.xsd"),
+        ]);
+        w.value("NumberHashIterations", self.preamble.iter);
+
+        if !self.header.password_policies.is_empty() {
+            w.open("Password_Policies", &[]);
+            for (i, (name, policy)) in self.header.password_policies.iter().enumerate() {
+                w.open("Policy", &[("id", &(i + 1).to_string())]);
+                w.text("PWName", name);
+                write_policy(&mut w, policy);
+                w.text("symbols", &policy.symbols);
+                w.close("Policy");
+            }
+            w.close("Password_Policies");
+        }
+        if !self.header.empty_groups.is_empty() {
+            w.open("EmptyGroups", &[]);
+            for group in &self.header.empty_groups {
+                w.text("EGName", group);
+            }
+            w.close("EmptyGroups");
+        }
+
+        let mut records: Vec<&Record> = self.records.values().collect();
+        records.sort_by(|a, b| (&a.group, &a.title, a.uuid).cmp(&(&b.group, &b.title, b.uuid)));
+        for (i, record) in records.into_iter().enumerate() {
+            w.open("entry", &[("id", &(i + 1).to_string())]);
+            write_record(&mut w, record);
+            w.close("entry");
+        }
+        w.close("passwordsafe");
+        w.xml
+    }
+
+    // import_xml adds the records of a pwsafe XML export along with its named password policies and
+    // empty groups, policies with the same name as an existing one are not replaced. Records keep
+    // their UUID unless it is already used, then they get a new one and aliases and shortcuts to
+    // them are updated. Nothing is imported if any record is invalid. The added UUIDs are returned.
+    pub fn import_xml(&mut self, data: &str) -> Result<Vec<Uuid>, Error> {
+        let root = parse(data)?;
+        if root.name != "passwordsafe" {
+            return Err(Error::InvalidXml(format!("the root element is {} rather than passwordsafe", root.name)))
+        }
+
+        let mut records = Vec::new();
+        let mut new_uuids = HashMap::new();
+        for entry in root.children("entry") {
+            let mut record = read_record(entry)?;
+            if self.records.contains_key(&record.uuid) || new_uuids.contains_key(&record.uuid) {
+                let uuid = Uuid::new_v4();
+                new_uuids.insert(record.uuid, uuid);
+                record.uuid = uuid;
+            }
+            records.push(record);
+        }
+        let mut policies = Vec::new();
+        for policy in root.children("Password_Policies").flat_map(|policies| policies.children("Policy")) {
+            let mut read = read_policy(policy)?;
+            read.symbols = policy.text("symbols").to_string();
+            policies.push((policy.text("PWName").to_string(), read));
+        }
+
+        let mut added = Vec::new();
+        for mut record in records {
+            let reference = record.reference().map(|reference| match (reference, new_uuids.get(&reference.uuid())) {
+                (Reference::Alias(_), Some(uuid)) => Reference::Alias(*uuid),
+                (Reference::Shortcut(_), Some(uuid)) => Reference::Shortcut(*uuid),
+                (reference, None) => reference,
+            });
+            if let Some(reference) = reference {
+                record.password = reference.to_string().into();
+            }
+            self.remove_empty_groups(&record.group);
+            added.push(record.uuid);
+            self.records.insert(record.uuid, record);
+        }
+        for (name, policy) in policies {
+            self.header.password_policies.entry(name).or_insert(policy);
+        }
+        let tree = self.group_tree();
+        for group in root.children("EmptyGroups").flat_map(|groups| groups.children("EGName")) {
+            if tree.get(&group.text).is_none() {
+                self.header.empty_groups.push(group.text.clone());
+            }
+        }
+        self.modified();
+        Ok(added)
+    }
+}
+
+// write_record writes the record fields in the order of the pwsafe schema, empty fields are left out
+fn write_record(w: &mut Writer, record: &Record) {
+    w.text("group", &record.group);
+    w.text("title", &record.title);
+    w.text("username", &record.username);
+    w.text("password", &record.password);
+    // SHA1, config 0, is the only algorithm records can hold
+    if let Some(totp) = &record.totp {
+        w.text("twofactorkey", &otp::base32_encode(&totp.key));
+        w.value("totpconfig", 0);
+        w.time("totpstarttime", Some(totp.start));
+        w.value("totptimestep", totp.period);
+        w.value("totplength", totp.digits);
+    }
+    w.text("url", &record.url);
+    w.text("autotype", &record.autotype);
+    w.text("notes", &record.notes);
+    w.text("uuid", &record.uuid.to_simple().to_string());
+    w.time("ctimex", record.create_time);
+    w.time("atimex", record.access_time);
+    w.time("xtimex", record.password_expiry_time);
+    w.time("pmtimex", record.password_mod_time);
+    w.time("rmtimex", record.mod_time);
+    if record.password_expiry_interval != 0 {
+        w.value("xtime_interval", record.password_expiry_interval);
+    }
+    let history = &record.password_history;
+    if *history != PasswordHistory::default() {
+        w.open("pwhistory", &[]);
+        w.value("status", history.enabled as u8);
+        w.value("max", history.max_entries);
+        w.value("num", history.entries.len());
+        if !history.entries.is_empty() {
+            w.open("history_entries", &[]);
+            for (i, (time, password)) in history.entries.iter().enumerate() {
+                w.open("history_entry", &[("num", &(i + 1).to_string())]);
+                w.time("changedx", Some(*time));
+                w.text("oldpassword", password);
+                w.close("history_entry");
+            }
+            w.close("history_entries");
+        }
+        w.close("pwhistory");
+    }
+    if let Some(policy) = &record.password_policy {
+        w.open("PasswordPolicy", &[]);
+        write_policy(w, policy);
+        w.close("PasswordPolicy");
+    }
+    w.text("PasswordPolicyName", &record.password_policy_name);
+    if let Some(policy) = &record.password_policy {
+        w.text("symbols", &policy.symbols);
+    }
+    w.text("runcommand", &record.run_command);
+    if record.double_click_action != [0; 2] {
+        w.value("dca", u16::from_le_bytes(record.double_click_action));
+    }
+    if record.shift_double_click_action != [0; 2] {
+        w.value("shiftdca", u16::from_le_bytes(record.shift_double_click_action));
+    }
+    w.text("email", &record.email);
+    if record.protected_entry != 0 {
+        w.value("protected", record.protected_entry);
+    }
+    if record.keyboard_shortcut != [0; 4] {
+        w.value("kbshortcut", format!("{:08x}", u32::from_le_bytes(record.keyboard_shortcut)));
+    }
+    w.text("qrcode", &record.qr_code);
+    w.text("creditcardnumber", &record.credit_card_number);
+    w.text("creditcardexpiration", &record.credit_card_expiration);
+    w.text("creditcardverifvalue", &record.credit_card_verify);
+    w.text("creditcardpin", &record.credit_card_pin);
+}
+
+// read_record is the reverse of write_record, elements this application doesn't know are ignored
+fn read_record(entry: &Element) -> Result<Record, Error> {
+    let mut r = Record {
+        group: entry.text("group").to_string(),
+        title: entry.text("title").to_string(),
+        username: entry.text("username").to_string(),
+        password: entry.text("password").into(),
+        url: entry.text("url").to_string(),
+        autotype: entry.text("autotype").to_string(),
+        notes: entry.text("notes").to_string(),
+        create_time: time(entry, "ctimex")?,
+        access_time: time(entry, "atimex")?,
+        password_expiry_time: time(entry, "xtimex")?,
+        password_mod_time: time(entry, "pmtimex")?,
+        mod_time: time(entry, "rmtimex")?,
+        password_expiry_interval: number(entry, "xtime_interval")?.filter(|days| *days <= MAX_EXPIRY_INTERVAL).unwrap_or_default(),
+        password_policy_name: entry.text("PasswordPolicyName").to_string(),
+        run_command: entry.text("runcommand").to_string(),
+        double_click_action: number::<u16>(entry, "dca")?.unwrap_or_default().to_le_bytes(),
+        shift_double_click_action: number::<u16>(entry, "shiftdca")?.unwrap_or_default().to_le_bytes(),
+        email: entry.text("email").to_string(),
+        protected_entry: number(entry, "protected")?.unwrap_or_default(),
+        qr_code: entry.text("qrcode").into(),
+        credit_card_number: entry.text("creditcardnumber").into(),
+        credit_card_expiration: entry.text("creditcardexpiration").to_string(),
+        credit_card_verify: entry.text("creditcardverifvalue").into(),
+        credit_card_pin: entry.text("creditcardpin").into(),
+        ..Default::default()
+    };
+    r.uuid = match entry.child("uuid") {
+        Some(uuid) => Uuid::parse_str(uuid.text.trim())
+            .map_err(|_| Error::InvalidXml(format!("invalid entry UUID {}", uuid.text)))?,
+        None => Uuid::new_v4(),
+    };
+    if let Some(shortcut) = entry.child("kbshortcut") {
+        let value = u32::from_str_radix(shortcut.text.trim(), 16)
+            .map_err(|_| Error::InvalidXml(format!("invalid kbshortcut {}", shortcut.text)))?;
+        r.keyboard_shortcut = value.to_le_bytes();
+    }
+
+    if let Some(history) = entry.child("pwhistory") {
+        r.password_history.enabled = number::<u8>(history, "status")? == Some(1);
+        r.password_history.max_entries = number(history, "max")?.unwrap_or_default();
+        for old in history.children("history_entries").flat_map(|entries| entries.children("history_entry")) {
+            let changed = time(old, "changedx")?.unwrap_or_else(|| Utc.timestamp(0, 0));
+            r.password_history.entries.push((changed, old.text("oldpassword").into()));
+        }
+    }
+    if let Some(policy) = entry.child("PasswordPolicy") {
+        let mut policy = read_policy(policy)?;
+        policy.symbols = entry.text("symbols").to_string();
+        r.password_policy = Some(policy);
+    }
+    if let Some(key) = entry.child("twofactorkey") {
+        let mut totp = Totp::new(&otp::base32_decode(&key.text)
+            .ok_or_else(|| Error::InvalidXml("the twofactorkey is not base32".to_string()))?);
+        if let Some(config) = number(entry, "totpconfig")? {
+            totp.algorithm = Algorithm::from_config(config)
+                .ok_or_else(|| Error::InvalidXml(format!("totpconfig {} is not a known algorithm", config)))?;
+        }
+        totp.digits = number(entry, "totplength")?.unwrap_or(otp::DEFAULT_DIGITS);
+        totp.period = number(entry, "totptimestep")?.unwrap_or(otp::DEFAULT_PERIOD);
+        totp.start = time(entry, "totpstarttime")?.unwrap_or(totp.start);
+        totp.validate()?;
+        r.totp = Some(totp);
+    }
+    r.validate()?;
+    Ok(r)
+}
+
+fn write_policy(w: &mut Writer, policy: &PasswordPolicy) {
+    w.value("PWLength", policy.length);
+    w.value("PWUseDigits", policy.use_digits as u8);
+    w.value("PWUseEasyVision", policy.easy_vision as u8);
+    w.value("PWUseHexDigits", policy.hex_only as u8);
+    w.value("PWUseLowercase", policy.use_lowercase as u8);
+    w.value("PWUseSymbols", policy.use_symbols as u8);
+    w.value("PWUseUppercase", policy.use_uppercase as u8);
+    w.value("PWMakePronounceable", policy.pronounceable as u8);
+    w.value("PWLowercaseMinLength", policy.min_lowercase);
+    w.value("PWUppercaseMinLength", policy.min_uppercase);
+    w.value("PWDigitMinLength", policy.min_digits);
+    w.value("PWSymbolMinLength", policy.min_symbols);
+}
+
+// read_policy reads all but the symbols, which are stored differently for records and named policies
+fn read_policy(element: &Element) -> Result<PasswordPolicy, Error> {
+    let flag = |name| Ok::<bool, Error>(number::<u8>(element, name)? == Some(1));
+    let default = PasswordPolicy::default();
+    Ok(PasswordPolicy {
+        length: number(element, "PWLength")?.unwrap_or(default.length),
+        use_digits: flag("PWUseDigits")?,
+        easy_vision: flag("PWUseEasyVision")?,
+        hex_only: flag("PWUseHexDigits")?,
+        use_lowercase: flag("PWUseLowercase")?,
+        use_symbols: flag("PWUseSymbols")?,
+        use_uppercase: flag("PWUseUppercase")?,
+        pronounceable: flag("PWMakePronounceable")?,
+        min_lowercase: number(element, "PWLowercaseMinLength")?.unwrap_or_default(),
+        min_uppercase: number(element, "PWUppercaseMinLength")?.unwrap_or_default(),
+        min_digits: number(element, "PWDigitMinLength")?.unwrap_or_default(),
+        min_symbols: number(element, "PWSymbolMinLength")?.unwrap_or_default(),
+        symbols: String::new(),
+    })
+}
+
+// number parses the text of the named child, if there is one
+fn number<T: std::str::FromStr>(element: &Element, name: &str) -> Result<Option<T>, Error> {
+    match element.child(name) {
+        Some(child) => child.text.trim().parse().map(Some)
+            .map_err(|_| Error::InvalidXml(format!("{} must be a number, not {}", name, child.text))),
+        None => Ok(None),
+    }
+}
+
+// time parses the named child as an xs:dateTime, a time zone other than UTC isn't supported
+fn time(element: &Element, name: &str) -> Result<Option<DateTime<Utc>>, Error> {
+    match element.child(name) {
+        Some(child) => NaiveDateTime::parse_from_str(child.text.trim().trim_end_matches('Z'), TIME_FORMAT)
+            .map(|time| Some(Utc.from_utc_datetime(&time)))
+            .map_err(|_| Error::InvalidXml(format!("{} is not a valid time: {}", name, child.text))),
+        None => Ok(None),
+    }
+}
+
+// Writer builds indented XML, text values are written as CDATA as pwsafe does
+#[derive(Default)]
+struct Writer {
+    xml: String,
+    depth: usize,
+}
+
+impl Writer {
+    fn open(&mut self, name: &str, attributes: &[(&str, &str)]) {
+        self.indent();
+        self.xml.push('<');
+        self.xml.push_str(name);
+        for (attribute, value) in attributes {
+            self.xml.push_str(&format!(" {}=\"{}\"", attribute, escape(value)));
+        }
+        self.xml.push_str(">\n");
+        self.depth += 1;
+    }
+
+    fn close(&mut self, name: &str) {
+        self.depth -= 1;
+        self.indent();
+        self.xml.push_str(&format!("</{}>\n", name));
+    }
+
+    // text writes an element holding a string, empty strings are left out
+    fn text(&mut self, name: &str, value: &str) {
+        if value.is_empty() {
+            return
+        }
+        // CDATA can't hold its own end so that is split across sections
+        self.value(name, format!("<![CDATA[{}]]>", value.replace("]]>", "]]]]><![CDATA[>")));
+    }
+
+    fn time(&mut self, name: &str, time: Option<DateTime<Utc>>) {
+        if let Some(time) = time {
+            self.value(name, time.format(TIME_FORMAT));
+        }
+    }
+
+    fn value(&mut self, name: &str, value: impl Display) {
+        self.indent();
+        self.xml.push_str(&format!("<{}>{}</{}>\n", name, value, name));
+    }
+
+    fn indent(&mut self) {
+        self.xml.push_str(&"\t".repeat(self.depth));
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// Element is a parsed XML element, text is all of the character data directly within it
+#[derive(Debug, Default)]
+pub(super) struct Element {
+    pub(super) name: String,
+    pub(super) attributes: Vec<(String, String)>,
+    pub(super) children: Vec<Element>,
+    pub(super) text: String,
+}
+
+impl Element {
+    pub(super) fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find(|(attribute, _)| attribute == name).map(|(_, value)| value.as_str())
+    }
+
+    pub(super) fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    pub(super) fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+
+    // text is the text of the named child, empty if there is no such child
+    pub(super) fn text(&self, name: &str) -> &str {
+        self.child(name).map(|child| child.text.as_str()).unwrap_or_default()
+    }
+}
+
+// parse reads the root element of an XML document. This is enough of XML for the pwsafe and KeePass
+// formats, DTDs are skipped and namespaces aren't interpreted.
+pub(super) fn parse(data: &str) -> Result<Element, Error> {
+    let mut parser = Parser { data, pos: 0 };
+    parser.skip_misc()?;
+    if !parser.rest().starts_with('<') {
+        return Err(parser.error("there is no root element"))
+    }
+    let root = parser.element(0)?;
+    parser.skip_misc()?;
+    if parser.pos != data.len() {
+        return Err(parser.error("there is content after the root element"))
+    }
+    Ok(root)
+}
+
+// Nesting deeper than this is rejected rather than risking the stack, as for JSON
+const MAX_DEPTH: usize = 128;
+
+struct Parser<'a> {
+    data: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.data[self.pos..]
+    }
+
+    fn error(&self, reason: &str) -> Error {
+        Error::InvalidXml(format!("{} at byte {}", reason, self.pos))
+    }
+
+    // skip_to moves past the next occurrence of end
+    fn skip_to(&mut self, end: &str) -> Result<&'a str, Error> {
+        match self.rest().find(end) {
+            Some(i) => {
+                let skipped = &self.rest()[..i];
+                self.pos += i + end.len();
+                Ok(skipped)
+            },
+            None => Err(self.error(&format!("{} is missing", end))),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    // skip_misc skips the whitespace, comments, processing instructions and DTD outside the root
+    fn skip_misc(&mut self) -> Result<(), Error> {
+        loop {
+            self.skip_whitespace();
+            let rest = self.rest();
+            if rest.starts_with("<?") {
+                self.skip_to("?>")?;
+            } else if rest.starts_with("<!--") {
+                self.skip_to("-->")?;
+            } else if rest.starts_with("<!") {
+                self.skip_to(">")?;
+            } else {
+                return Ok(())
+            }
+        }
+    }
+
+    fn name(&mut self) -> Result<&'a str, Error> {
+        let rest = self.rest();
+        let end = rest.find(|c: char| c.is_whitespace() || "/>=".contains(c)).unwrap_or(rest.len());
+        if end == 0 {
+            return Err(self.error("a name is missing"))
+        }
+        self.pos += end;
+        Ok(&rest[..end])
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), Error> {
+        if !self.rest().starts_with(expected) {
+            return Err(self.error(&format!("{} is expected", expected)))
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    // element parses an element starting at its <, depth is the number of elements it is within
+    fn element(&mut self, depth: usize) -> Result<Element, Error> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("the elements are nested too deeply"))
+        }
+        self.expect('<')?;
+        let mut element = Element { name: self.name()?.to_string(), ..Default::default() };
+        loop {
+            self.skip_whitespace();
+            if self.rest().starts_with("/>") {
+                self.pos += 2;
+                return Ok(element)
+            }
+            if self.rest().starts_with('>') {
+                self.pos += 1;
+                break
+            }
+            let name = self.name()?.to_string();
+            self.skip_whitespace();
+            self.expect('=')?;
+            self.skip_whitespace();
+            let quote = match self.rest().chars().next() {
+                Some(quote) if quote == '"' || quote == '\'' => quote,
+                _ => return Err(self.error("an attribute value must be quoted")),
+            };
+            self.pos += 1;
+            let value = unescape(self.skip_to(&quote.to_string())?).map_err(|reason| self.error(&reason))?;
+            element.attributes.push((name, value));
+        }
+
+        loop {
+            let rest = self.rest();
+            if rest.starts_with("</") {
+                self.pos += 2;
+                if self.name()? != element.name {
+                    return Err(self.error(&format!("the end of {} is expected", element.name)))
+                }
+                self.skip_whitespace();
+                self.expect('>')?;
+                return Ok(element)
+            } else if rest.starts_with("<![CDATA[") {
+                self.pos += 9;
+                element.text.push_str(self.skip_to("]]>")?);
+            } else if rest.starts_with("<!--") {
+                self.skip_to("-->")?;
+            } else if rest.starts_with("<?") {
+                self.skip_to("?>")?;
+            } else if rest.starts_with('<') {
+                element.children.push(self.element(depth + 1)?);
+            } else if rest.is_empty() {
+                return Err(self.error(&format!("{} is not closed", element.name)))
+            } else {
+                let end = rest.find('<').unwrap_or(rest.len());
+                element.text.push_str(&unescape(&rest[..end]).map_err(|reason| self.error(&reason))?);
+                self.pos += end;
+            }
+        }
+    }
+}
+
+// unescape replaces the predefined and numeric character references
+fn unescape(value: &str) -> Result<String, String> {
+    let mut unescaped = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        let end = rest[start..].find(';').ok_or("a character reference is not closed")? + start;
+        let reference = &rest[start + 1..end];
+        let c = match reference {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ if reference.starts_with("#x") => u32::from_str_radix(&reference[2..], 16).ok().and_then(std::char::from_u32),
+            _ if reference.starts_with('#') => reference[1..].parse().ok().and_then(std::char::from_u32),
+            _ => None,
+        };
+        unescaped.push(c.ok_or(format!("unknown character reference &{};", reference))?);
+        rest = &rest[end + 1..];
+    }
+    unescaped.push_str(rest);
+    Ok(unescaped)
+}